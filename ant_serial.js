@@ -0,0 +1,63 @@
+// Dynastream / Garmin ANT USB sticks
+const ANT_USB_VENDOR_ID = 0x0fcf;
+const ANT_BAUD_RATE = 115200;
+
+export class AntSerial {
+    #onData;
+    #onStateChange;
+    #port = null;
+    #writer = null;
+    #pending = [];
+
+    constructor(onData, onStateChange)  {
+      this.#onData = onData;
+      this.#onStateChange = onStateChange;
+    }
+
+    connect() {
+      if (!('serial' in navigator)) {
+        console.log('Web Serial is not supported');
+        this.#onStateChange('failed');
+        return;
+      }
+      navigator.serial.requestPort({filters: [{usbVendorId: ANT_USB_VENDOR_ID}]})
+      .then(port => {
+        this.#port = port;
+        return port.open({baudRate: ANT_BAUD_RATE});
+      })
+      .then(() => {
+        this.#writer = this.#port.writable.getWriter();
+        this.#pending.forEach(bytes => this.#writer.write(bytes));
+        this.#pending = [];
+        this.#onStateChange('open');
+        return this.readLoop(this.#port.readable.getReader());
+      })
+      .catch(error => {
+        console.log('ANT stick: ' + error);
+      })
+      .finally(() => {
+        this.#writer = null;
+        this.#onStateChange('closed');
+      });
+    }
+
+    readLoop(reader) {
+      return reader.read().then(({value, done}) => {
+        if (done) {
+          return;
+        }
+        this.#onData(value);
+        return this.readLoop(reader);
+      });
+    }
+
+    write(bytes) {
+      // the node may start talking before the port finished opening
+      const copy = new Uint8Array(bytes);
+      if (this.#writer === null) {
+        this.#pending.push(copy);
+        return;
+      }
+      this.#writer.write(copy);
+    }
+}
@@ -134,61 +134,104 @@ let BLE_ATTRIBUTES = {
     "00002a68-0000-1000-8000-00805f9b34fb": "Navigation",
     "00002a69-0000-1000-8000-00805f9b34fb": "Position Quality",
     "00002a6a-0000-1000-8000-00805f9b34fb": "LN Feature",
-    "00002a6b-0000-1000-8000-00805f9b34fb": "LN Control Point"}
+    "00002a6b-0000-1000-8000-00805f9b34fb": "LN Control Point",
+    "00001826-0000-1000-8000-00805f9b34fb": "Fitness Machine",
+    "00002acc-0000-1000-8000-00805f9b34fb": "Fitness Machine Feature",
+    "00002ad2-0000-1000-8000-00805f9b34fb": "Indoor Bike Data",
+    "00002ad8-0000-1000-8000-00805f9b34fb": "Supported Power Range",
+    "00002ad9-0000-1000-8000-00805f9b34fb": "Fitness Machine Control Point",
+    "00002ada-0000-1000-8000-00805f9b34fb": "Fitness Machine Status"}
+
+// suffix of 16-bit assigned numbers expanded to full UUIDs
+const BLUETOOTH_BASE_UUID = '-0000-1000-8000-00805f9b34fb';
+
+function toBytes(dataView) {
+    return new Uint8Array(dataView.buffer, dataView.byteOffset, dataView.byteLength);
+}
 
 class BLEDevice {
     #primaryServices;
     #optionalServices;
-    #chosenService = null;
-    #intervalId = 0;
+    #device = null;
+    #onStateChange;
+    #onServer;
     sensorState = {};
 
-    constructor(primaryServices, optionalServices)  {
+    constructor(primaryServices, optionalServices, onStateChange, onServer)  {
       this.#primaryServices = primaryServices;
       this.#optionalServices = optionalServices;
+      this.#onStateChange = onStateChange;
+      this.#onServer = onServer;
+    }
+
+    updateState(changes) {
+      Object.assign(this.sensorState, changes);
+      this.#onStateChange(this.sensorState);
     }
 
     connect() {
+      this.updateState({state: 'scanning'});
       navigator.bluetooth.requestDevice({
         filters: [{
           services: Object.keys(this.#primaryServices)
         }],
         optionalServices: Object.keys(this.#optionalServices)
       }).then(device => {
-        device.gatt.connect().then(this.subDevice.bind(this));
-        device.addEventListener('gattserverdisconnected', this.onDisconnect.bind(this));
+        this.attach(device);
+        this.resume();
+      }).catch(error => {
+        console.log('Cant connect '+error);
+        this.updateState({state: 'failed'});
       });
+    }
 
-      if (this.#intervalId != 0) {
-        clearInterval(this.#intervalId);
-        this.#intervalId = 0;
-      }
-
-      this.#intervalId = setInterval(() => {
-        if (this.#chosenService != null) {
-          let connected = this.#chosenService.device.gatt.connected;
-
-          console.log("Connected: " + connected);
-          if (!connected) {
-            this.#chosenService.device.gatt.connect().then(this.subDevice.bind(this));
-          }
+    // reconnects the chosen device, or the previously granted one with the given id
+    resume(id) {
+      this.updateState({state: 'connecting'});
+      const acquire = this.#device !== null || id == null
+        ? Promise.resolve(this.#device)
+        : navigator.bluetooth.getDevices().then(devices => {
+            const device = devices.find(d => d.id === id);
+            if (device !== undefined) {
+              this.attach(device);
+            }
+            return device;
+          });
+      acquire.then(device => {
+        if (device == null) {
+          throw 'no device';
         }
-      }, 3000);
+        this.updateState({name: device.name, id: device.id});
+        return device.gatt.connect().then(this.subDevice.bind(this));
+      }).catch(error => {
+        console.log('Cant reconnect '+error);
+        this.updateState({state: 'failed'});
+      });
+    }
 
+    attach(device) {
+      if (this.#device === device) {
+        return;
+      }
+      this.#device = device;
+      device.addEventListener('gattserverdisconnected', this.onDisconnect.bind(this));
     }
 
     onDisconnect() {
       console.log("Disconnect!");
-      this.#chosenService.device.gatt.connect().then(this.subDevice.bind(this));
+      this.updateState({state: 'disconnected'});
     }
 
     subDevice(server) {
-      this.sensorState.name = server.device.name;
+      // subscriptions are in place before anyone reacts to the connection
+      if (this.#onServer !== undefined) {
+        this.#onServer(server);
+      }
+      this.updateState({state: 'connected', name: server.device.name});
 
       Object.entries(this.#primaryServices).forEach( (entry) => {
         const [name, characteristics] = entry;
         server.getPrimaryService(name).then(service => {
-          this.#chosenService = service;
           return Promise.all( Object.entries(characteristics).map( (char) => {
               service.getCharacteristic(char[0]).then(char[1]).catch(error => {
                 console.log('Cant add feature '+char[0]+" for service "+name+" "+error);
@@ -201,7 +244,6 @@ class BLEDevice {
       Object.entries(this.#optionalServices).forEach( (entry) => {
         const [name, characteristics] = entry;
         server.getPrimaryService(name).then(service => {
-          this.#chosenService = service;
           return Promise.all( Object.entries(characteristics).map( (char) => {
               service.getCharacteristic(char[0]).then(char[1]).catch(error => {
                 console.log('Cant add feature '+char[0]+" for service "+name+" "+error);
@@ -213,264 +255,73 @@ class BLEDevice {
     }
 }
 
-export class HRMDevice {
-    #onHeartRate;
-    #onStateChange;
-    #device;
+// Client side of a connected GATT server for the Rust profiles. Services and characteristics
+// are addressed by 16-bit assigned numbers, completions get an error string or null and the value.
+export class GattServer {
+    #server;
+    #characteristics = new Map();
 
-    constructor(onHeartRate, onStateChange)  {
-      this.#device = new BLEDevice( {
-        'heart_rate' : {
-          'body_sensor_location' : this.handleBodySensorLocationCharacteristic.bind(this),
-          'heart_rate_measurement' : this.handleHeartRateMeasurementCharacteristic.bind(this),
-        }
-      }, {
-        'battery_service' : {
-          'battery_level' : this.handleBatteryLevel.bind(this)
-        },
-        'device_information' : c => {}
-      } );
-      this.#onHeartRate = onHeartRate;
-      this.#onStateChange = onStateChange;
+    constructor(server) {
+      this.#server = server;
     }
 
-    connect() {
-      this.#device.connect();
-    }
-
-    handleBodySensorLocationCharacteristic(characteristic) {
-      if (characteristic === null) {
-        console.log("Unknown sensor location.");
-        return Promise.resolve();
+    characteristic(service, characteristic) {
+      const key = service + ':' + characteristic;
+      if (!this.#characteristics.has(key)) {
+        this.#characteristics.set(key, this.#server.getPrimaryService(service)
+          .then(s => s.getCharacteristic(characteristic)));
       }
-      return characteristic.readValue()
-      .then(sensorLocationData => {
-        const sensorLocation = sensorLocationData.getUint8(0);
-        switch (sensorLocation) {
-          case 0: return 'Other';
-          case 1: return 'Chest';
-          case 2: return 'Wrist';
-          case 3: return 'Finger';
-          case 4: return 'Hand';
-          case 5: return 'Ear Lobe';
-          case 6: return 'Foot';
-          default: return 'Unknown';
-        }
-      }).then(location => {
-        this.#device.sensorState.location = location;
-        this.#onStateChange(this.#device.sensorState);
-      });
+      return this.#characteristics.get(key);
     }
 
-    handleBatteryLevel(characteristic) {
-      if (characteristic === null) {
-        console.log("Unknown sensor location.");
-        return Promise.resolve();
-      }
-      return characteristic.readValue()
-      .then(batLev => {
-        this.#device.sensorState.battery = batLev;
-        this.#onStateChange(this.#device.sensorState);
-      });
+    services(onDone) {
+      this.#server.getPrimaryServices()
+        .then(services => services.map(s => s.uuid).filter(uuid => uuid.endsWith(BLUETOOTH_BASE_UUID)))
+        .then(uuids => onDone(null, Uint16Array.from(uuids, uuid => parseInt(uuid.substring(4, 8), 16))))
+        .catch(error => onDone(String(error)));
     }
 
-    handleDeviceInfo(characteristics) {
-      characteristics.forEach(characteristic => {
-        let uuid = characteristic.uuid;
-        characteristic.readValue()
-        .then(deviceInfo => {
-          let decoder = new TextDecoder('windows-1251');
-          let decodedString = decoder.decode(deviceInfo);
-          this.#device.sensorState.deviceInfo = decodedString;
-          console.log(BLE_ATTRIBUTES[uuid] + ": " + decodedString);
-        });
-      });
+    read(service, characteristic, onDone) {
+      this.characteristic(service, characteristic)
+        .then(char => char.readValue())
+        .then(value => onDone(null, toBytes(value)))
+        .catch(error => onDone(String(error)));
     }
 
-    handleHeartRateMeasurementCharacteristic(characteristic) {
-      return characteristic.startNotifications()
-      .then(char => {
-        characteristic.addEventListener('characteristicvaluechanged',
-                                        this.parseHeartRate.bind(this));
-      });
+    write(service, characteristic, bytes, onDone) {
+      // the view into wasm memory doesn't outlive the call
+      const value = new Uint8Array(bytes);
+      this.characteristic(service, characteristic)
+        .then(char => char.writeValueWithResponse(value))
+        .then(() => onDone(null))
+        .catch(error => onDone(String(error)));
     }
 
-    parseHeartRate(event) {
-      const characteristic = event.target;
-      let data = characteristic.value;
-
-      const flags = data.getUint8(0);
-      const rate16Bits = flags & 0x1;
-      const result = {};
-      let index = 1;
-      if (rate16Bits) {
-        result.heartRate = data.getUint16(index, /*littleEndian=*/true);
-        index += 2;
-      } else {
-        result.heartRate = data.getUint8(index);
-        index += 1;
-      }
-      const contactDetected = flags & 0x2;
-      const contactSensorPresent = flags & 0x4;
-      if (contactSensorPresent) {
-        result.contactDetected = !!contactDetected;
-      }
-      const energyPresent = flags & 0x8;
-      if (energyPresent) {
-        result.energyExpended = data.getUint16(index, /*littleEndian=*/true);
-        index += 2;
-      }
-      const rrIntervalPresent = flags & 0x10;
-      if (rrIntervalPresent) {
-        const rrIntervals = [];
-        for (; index + 1 < data.byteLength; index += 2) {
-          rrIntervals.push(data.getUint16(index, /*littleEndian=*/true));
-        }
-        result.rrIntervals = rrIntervals;
-      }
-      this.#onHeartRate(result);
+    subscribe(service, characteristic, onValue, onDone) {
+      this.characteristic(service, characteristic)
+        .then(char => char.startNotifications())
+        .then(char => {
+          char.addEventListener('characteristicvaluechanged', event => onValue(toBytes(event.target.value)));
+          onDone(null);
+        }).catch(error => onDone(String(error)));
     }
 }
 
-export class PowerTrainer {
-    #onPower;
-    #onStateChange;
+// Device whose profiles run in Rust, handed a GattServer on every connect
+export class GattDevice {
     #device;
 
-    constructor(onPower, onStateChange)  {
-      this.#device = new BLEDevice( {
-        'cycling_power' : {
-          'cycling_power_measurement' : this.subscribeForPowerMeasure(),
-          'cycling_power_feature' : this.logCharacteristic.bind(this),
-          'cycling_power_vector' : this.subscribeForLog()
-        }
-      }, {
-        'cycling_speed_and_cadence' : {
-          'csc_measurement' : this.subscribeForLog(),
-          'csc_feature' : this.logCharacteristic.bind(this)
-        },
-        'fitness_machine' : {
-          'fitness_machine_feature' : this.logCharacteristic.bind(this),
-          'indoor_bike_data': this.subscribeForIndoorBikeCharcateristic(),
-          'supported_power_range': this.logCharacteristic.bind(this)
-        },
-        'device_information' : c => {}
-      } );
-      this.#onPower = onPower;
-      this.#onStateChange = onStateChange;
+    constructor(primaryServices, optionalServices, onServer, onStateChange)  {
+      const byName = uuids => Object.fromEntries(Array.from(uuids, uuid => [BluetoothUUID.getService(uuid), {}]));
+      this.#device = new BLEDevice(byName(primaryServices), byName(optionalServices), onStateChange,
+                                   server => onServer(new GattServer(server)));
     }
 
     connect() {
       this.#device.connect();
     }
 
-    logCharacteristic(characteristic) {
-      characteristic.readValue().then( val => {
-        console.log(BLE_ATTRIBUTES[characteristic.uuid]+ ": "+val.getUint16());
-      }).catch(e => console.log("Cant get value for "+BLE_ATTRIBUTES[characteristic.uuid]+" "+e) );
-    }
-
-    subscribeCharacteristic(acceptor) {
-      return characteristic => { characteristic.startNotifications()
-        .then(char => {
-            characteristic.addEventListener('characteristicvaluechanged', acceptor);
-        }).catch(e => console.log("Cant subscribe for "+BLE_ATTRIBUTES[characteristic.uuid]+" "+e) )
-      }
-    }
-
-    subscribeForLog() {
-      return this.subscribeCharacteristic(event => {
-          console.log(BLE_ATTRIBUTES[event.target.uuid] + ": " + event.target.value);
-        });
+    resume(id) {
+      this.#device.resume(id);
     }
-
-    bit_test(num, bit){
-      return ((num>>bit) % 2 != 0)
-    }
-
-    subscribeForIndoorBikeCharcateristic() {
-      return this.subscribeCharacteristic(event => {
-        let flags = event.target.value.getUint16(0, true);
-        let pos = 2;
-  //      if (this.bit_test(flags, 0)) {
-        this.#device.sensorState.speed = event.target.value.getInt16(pos, true);
-        pos += 2;
-  //      }
-        if (this.bit_test(flags, 1)) {
-          this.#device.sensorState.averageSpeed = event.target.value.getUint16(pos, true);
-          pos += 2;
-        }
-        if (this.bit_test(flags, 2)) {
-          this.#device.sensorState.cadence = event.target.value.getUint16(pos, true);
-          pos += 2;
-        }
-        if (this.bit_test(flags, 3)) {
-          this.#device.sensorState.averageCadence = event.target.value.getUint16(pos, true);
-          pos += 2;
-        }
-        if (this.bit_test(flags, 4)) {
-          this.#device.sensorState.totalDistance = event.target.value.getUint16(pos, true);
-          pos += 2;
-          this.#device.sensorState.totalDistance2 = event.target.value.getUint8(pos, true);
-          pos += 1;
-        }
-        if (this.bit_test(flags, 5)) {
-          this.#device.sensorState.resistanceLevel = event.target.value.getInt16(pos, true);
-          pos += 2;
-        }
-        if (this.bit_test(flags, 6)) {
-          this.#device.sensorState.instPower = event.target.value.getInt16(pos, true);
-          pos += 2;
-        }
-        if (this.bit_test(flags, 7)) {
-          this.#device.sensorState.averagePower = event.target.value.getInt16(pos, true);
-          pos += 2;
-        }
-
-        console.log("State :"+JSON.stringify(this.#device.sensorState));
-      });
-    }
-
-    subscribeForPowerMeasure() {
-      return this.subscribeCharacteristic(event => {
-        let flags = event.target.value.getUint16(0, true);
-        let pos = 2;
-        this.#device.sensorState.power = event.target.value.getInt16(pos, true);
-        pos += 2;
-        if (this.bit_test(flags, 0)) {
-          this.#device.sensorState.powerBalance = event.target.value.getUint8(pos, true);
-          pos += 1;
-        }
-        if (this.bit_test(flags, 2)) {
-          this.#device.sensorState.accumulatedTorque = event.target.value.getUint16(pos, true);
-          pos += 2;
-        }
-        if (this.bit_test(flags, 4)) {
-          this.#device.sensorState.wheelRevolutions = event.target.value.getUint32(pos, true);
-          pos += 4;
-          this.#device.sensorState.lastWheelRevolution = event.target.value.getUint16(pos, true);
-          pos += 2;
-        }
-        if (this.bit_test(flags, 5)) {
-          this.#device.sensorState.crankRevolutions = event.target.value.getUint16(pos, true);
-          pos += 2;
-          this.#device.sensorState.lastCrankRevolution = event.target.value.getUint16(pos, true);
-          pos += 2;
-        }
-        if (this.bit_test(flags, 6)) {
-          this.#device.sensorState.maxForce = event.target.value.getInt16(pos, true);
-          pos += 2;
-          this.#device.sensorState.minForce = event.target.value.getInt16(pos, true);
-          pos += 2;
-        }
-        if (this.bit_test(flags, 7)) {
-          this.#device.sensorState.maxTorque = event.target.value.getInt16(pos, true);
-          pos += 2;
-          this.#device.sensorState.minTorque = event.target.value.getInt16(pos, true);
-          pos += 2;
-        }
-
-        console.log("State :"+JSON.stringify(this.#device.sensorState));
-      });
-    }
-}
\ No newline at end of file
+}
@@ -0,0 +1,227 @@
+/// First byte of every serial message
+pub const SYNC: u8 = 0xA4;
+
+pub const MSG_CHANNEL_EVENT: u8 = 0x40;
+pub const MSG_UNASSIGN_CHANNEL: u8 = 0x41;
+pub const MSG_ASSIGN_CHANNEL: u8 = 0x42;
+pub const MSG_CHANNEL_PERIOD: u8 = 0x43;
+pub const MSG_SEARCH_TIMEOUT: u8 = 0x44;
+pub const MSG_CHANNEL_RF_FREQUENCY: u8 = 0x45;
+pub const MSG_SET_NETWORK_KEY: u8 = 0x46;
+pub const MSG_RESET_SYSTEM: u8 = 0x4A;
+pub const MSG_OPEN_CHANNEL: u8 = 0x4B;
+pub const MSG_CLOSE_CHANNEL: u8 = 0x4C;
+pub const MSG_BROADCAST_DATA: u8 = 0x4E;
+pub const MSG_ACKNOWLEDGED_DATA: u8 = 0x4F;
+pub const MSG_CHANNEL_ID: u8 = 0x51;
+pub const MSG_STARTUP: u8 = 0x6F;
+
+/// Channel event codes, carried by `MSG_CHANNEL_EVENT` with message id 1
+pub const EVENT_RX_SEARCH_TIMEOUT: u8 = 0x01;
+pub const EVENT_RX_FAIL: u8 = 0x02;
+pub const EVENT_TRANSFER_TX_COMPLETED: u8 = 0x05;
+pub const EVENT_TRANSFER_TX_FAILED: u8 = 0x06;
+pub const EVENT_CHANNEL_CLOSED: u8 = 0x07;
+pub const EVENT_RX_FAIL_GO_TO_SEARCH: u8 = 0x08;
+
+/// Bidirectional slave, receives broadcasts and may send acknowledged data back
+pub const CHANNEL_TYPE_SLAVE: u8 = 0x00;
+
+/// Longest payload a message length byte is trusted with
+const MAX_PAYLOAD: usize = 32;
+
+/// One ANT serial message: sync, length, id, payload and XOR checksum
+#[derive(Clone, Debug, PartialEq)]
+pub struct AntMessage {
+    pub id: u8,
+    pub payload: Vec<u8>,
+}
+
+/// Reply of the stick to a command, or an event on a channel
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ChannelEvent {
+    pub channel: u8,
+    /// Id of the command answered, 1 for channel events
+    pub message_id: u8,
+    pub code: u8,
+}
+
+impl AntMessage {
+    pub fn new(id: u8, payload: &[u8]) -> AntMessage {
+        AntMessage { id, payload: Vec::from(payload) }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut res = vec![SYNC, self.payload.len() as u8, self.id];
+        res.extend_from_slice(&self.payload);
+        res.push(checksum(&res));
+        res
+    }
+
+    pub fn reset_system() -> AntMessage {
+        AntMessage::new(MSG_RESET_SYSTEM, &[0x00])
+    }
+
+    pub fn set_network_key(network: u8, key: &[u8; 8]) -> AntMessage {
+        let mut payload = vec![network];
+        payload.extend_from_slice(key);
+        AntMessage::new(MSG_SET_NETWORK_KEY, &payload)
+    }
+
+    pub fn assign_channel(channel: u8, channel_type: u8, network: u8) -> AntMessage {
+        AntMessage::new(MSG_ASSIGN_CHANNEL, &[channel, channel_type, network])
+    }
+
+    pub fn unassign_channel(channel: u8) -> AntMessage {
+        AntMessage::new(MSG_UNASSIGN_CHANNEL, &[channel])
+    }
+
+    /// Device number 0 pairs with any device of `device_type`
+    pub fn channel_id(channel: u8, device_number: u16, device_type: u8, transmission_type: u8) -> AntMessage {
+        let [lo, hi] = device_number.to_le_bytes();
+        AntMessage::new(MSG_CHANNEL_ID, &[channel, lo, hi, device_type, transmission_type])
+    }
+
+    /// `period` in 1/32768 s
+    pub fn channel_period(channel: u8, period: u16) -> AntMessage {
+        let [lo, hi] = period.to_le_bytes();
+        AntMessage::new(MSG_CHANNEL_PERIOD, &[channel, lo, hi])
+    }
+
+    /// `timeout` in 2.5 s steps
+    pub fn search_timeout(channel: u8, timeout: u8) -> AntMessage {
+        AntMessage::new(MSG_SEARCH_TIMEOUT, &[channel, timeout])
+    }
+
+    /// `frequency` in MHz above 2400
+    pub fn channel_rf_frequency(channel: u8, frequency: u8) -> AntMessage {
+        AntMessage::new(MSG_CHANNEL_RF_FREQUENCY, &[channel, frequency])
+    }
+
+    pub fn open_channel(channel: u8) -> AntMessage {
+        AntMessage::new(MSG_OPEN_CHANNEL, &[channel])
+    }
+
+    pub fn close_channel(channel: u8) -> AntMessage {
+        AntMessage::new(MSG_CLOSE_CHANNEL, &[channel])
+    }
+
+    pub fn broadcast_data(channel: u8, page: &[u8; 8]) -> AntMessage {
+        Self::data(MSG_BROADCAST_DATA, channel, page)
+    }
+
+    pub fn acknowledged_data(channel: u8, page: &[u8; 8]) -> AntMessage {
+        Self::data(MSG_ACKNOWLEDGED_DATA, channel, page)
+    }
+
+    /// Channel and data page of a broadcast or acknowledged message.
+    /// Extended data some sticks append is ignored
+    pub fn data_page(&self) -> Option<(u8, [u8; 8])> {
+        if (self.id != MSG_BROADCAST_DATA && self.id != MSG_ACKNOWLEDGED_DATA) || self.payload.len() < 9 {
+            return None;
+        }
+        let mut page = [0; 8];
+        page.copy_from_slice(&self.payload[1..9]);
+        Some((self.payload[0], page))
+    }
+
+    pub fn channel_event(&self) -> Option<ChannelEvent> {
+        if self.id != MSG_CHANNEL_EVENT || self.payload.len() < 3 {
+            return None;
+        }
+        Some(ChannelEvent { channel: self.payload[0], message_id: self.payload[1], code: self.payload[2] })
+    }
+
+    fn data(id: u8, channel: u8, page: &[u8; 8]) -> AntMessage {
+        let mut payload = vec![channel];
+        payload.extend_from_slice(page);
+        AntMessage::new(id, &payload)
+    }
+}
+
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |acc, b| acc ^ b)
+}
+
+/// Splits the serial byte stream into messages. Bytes before a sync byte and
+/// messages with a bad checksum are dropped, decoding resumes at the next sync byte.
+#[derive(Default)]
+pub struct AntDecoder {
+    buffer: Vec<u8>,
+}
+
+impl AntDecoder {
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<AntMessage> {
+        self.buffer.extend_from_slice(bytes);
+        let mut res = Vec::new();
+        loop {
+            match self.buffer.iter().position(|b| *b == SYNC) {
+                Some(start) => { self.buffer.drain(..start); }
+                None => {
+                    self.buffer.clear();
+                    return res;
+                }
+            }
+            if self.buffer.len() < 2 {
+                return res;
+            }
+            let len = self.buffer[1] as usize;
+            if len > MAX_PAYLOAD {
+                self.buffer.remove(0);
+                continue;
+            }
+            // sync, length, id, payload, checksum
+            let total = len + 4;
+            if self.buffer.len() < total {
+                return res;
+            }
+            if checksum(&self.buffer[..total - 1]) != self.buffer[total - 1] {
+                self.buffer.remove(0);
+                continue;
+            }
+            res.push(AntMessage::new(self.buffer[2], &self.buffer[3..total - 1]));
+            self.buffer.drain(..total);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_with_checksum() {
+        assert_eq!(AntMessage::reset_system().encode(), vec![0xA4, 0x01, 0x4A, 0x00, 0xEF]);
+        assert_eq!(AntMessage::open_channel(0).encode(), vec![0xA4, 0x01, 0x4B, 0x00, 0xEE]);
+    }
+
+    #[test]
+    fn decodes_split_stream() {
+        let page = [0x04, 0xFF, 0xFF, 0xFF, 0x00, 0x04, 0x10, 0x48];
+        let encoded = AntMessage::broadcast_data(1, &page).encode();
+        let mut decoder = AntDecoder::default();
+        assert!(decoder.push(&encoded[..5]).is_empty());
+        let messages = decoder.push(&encoded[5..]);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].data_page(), Some((1, page)));
+    }
+
+    #[test]
+    fn resyncs_after_garbage_and_bad_checksum() {
+        let mut corrupted = AntMessage::open_channel(2).encode();
+        *corrupted.last_mut().unwrap() ^= 0xFF;
+        let mut stream = vec![0x00, 0x13];
+        stream.extend(corrupted);
+        stream.extend(AntMessage::close_channel(3).encode());
+        let messages = AntDecoder::default().push(&stream);
+        assert_eq!(messages, vec![AntMessage::close_channel(3)]);
+    }
+
+    #[test]
+    fn decodes_channel_event() {
+        let message = AntMessage::new(MSG_CHANNEL_EVENT, &[1, 1, EVENT_TRANSFER_TX_COMPLETED]);
+        let decoded = AntDecoder::default().push(&message.encode());
+        assert_eq!(decoded[0].channel_event(),
+                   Some(ChannelEvent { channel: 1, message_id: 1, code: EVENT_TRANSFER_TX_COMPLETED }));
+    }
+}
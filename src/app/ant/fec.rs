@@ -0,0 +1,140 @@
+use crate::bluetooth::bytes::ByteReader;
+use crate::bluetooth::ftms::{ControlResponse, ResultCode};
+use crate::bluetooth::power::{PowerMeasurement, TrainerEvent};
+
+pub const DEVICE_TYPE: u8 = 17;
+/// 1/32768 s, 4 Hz
+pub const CHANNEL_PERIOD: u16 = 8192;
+
+const PAGE_GENERAL: u8 = 0x10;
+const PAGE_TRAINER: u8 = 0x19;
+const PAGE_BASIC_RESISTANCE: u8 = 0x30;
+const PAGE_TARGET_POWER: u8 = 0x31;
+const PAGE_WIND_RESISTANCE: u8 = 0x32;
+const PAGE_TRACK_RESISTANCE: u8 = 0x33;
+
+/// Fitness Machine Control Point op codes the FE-C control pages stand in for
+const OP_SET_TARGET_RESISTANCE: u8 = 0x04;
+const OP_SET_TARGET_POWER: u8 = 0x05;
+const OP_SET_INDOOR_BIKE_SIMULATION: u8 = 0x11;
+
+const INVALID_CADENCE: u8 = 0xFF;
+const INVALID_POWER: u16 = 0xFFF;
+const INVALID_SPEED: u16 = 0xFFFF;
+/// No drafting, full wind resistance
+const DRAFTING_FACTOR: u8 = 100;
+
+/// Decodes FE-C data pages into the events a BLE trainer produces
+pub fn decode(page: &[u8; 8]) -> Vec<TrainerEvent> {
+    let mut res = Vec::new();
+    match page[0] {
+        PAGE_GENERAL => {
+            // 0.001 m/s
+            let speed = u16::from_le_bytes([page[4], page[5]]);
+            if speed != INVALID_SPEED {
+                res.push(TrainerEvent::Speed(speed as f32 / 1000.0));
+            }
+        }
+        PAGE_TRAINER => {
+            if page[2] != INVALID_CADENCE {
+                res.push(TrainerEvent::Cadence(page[2] as f32));
+            }
+            let power = u16::from_le_bytes([page[5], page[6] & 0x0F]);
+            if power != INVALID_POWER {
+                res.push(TrainerEvent::Power(PowerMeasurement::from_power(power as i16)));
+            }
+        }
+        _ => {}
+    }
+    res
+}
+
+/// Control pages carrying a Fitness Machine Control Point request, so `TrainerControl`
+/// drives FE-C trainers the same way as FTMS ones. Empty for procedures FE-C has
+/// no equivalent for, such as requesting control.
+pub fn control_pages(request: &[u8]) -> Vec<[u8; 8]> {
+    let mut r = ByteReader::new(request);
+    let pages = match r.u8() {
+        Some(OP_SET_TARGET_POWER) => r.i16().map(|watts| {
+            // 0.25 W
+            let [lo, hi] = ((watts.max(0) as u32 * 4).min(u16::MAX as u32) as u16).to_le_bytes();
+            vec![[PAGE_TARGET_POWER, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, lo, hi]]
+        }),
+        Some(OP_SET_TARGET_RESISTANCE) => r.u8().map(|level| {
+            // 0.5 % of the maximum resistance
+            let total = (level as u32 * 200 / 255) as u8;
+            vec![[PAGE_BASIC_RESISTANCE, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, total]]
+        }),
+        Some(OP_SET_INDOOR_BIKE_SIMULATION) => simulation_pages(&mut r),
+        _ => None,
+    };
+    pages.unwrap_or_default()
+}
+
+/// Answer to an FTMS request once its control pages were acknowledged, or not
+pub fn response(request: &[u8], acknowledged: bool) -> Option<ControlResponse> {
+    Some(ControlResponse {
+        request_op_code: *request.first()?,
+        result: if acknowledged { ResultCode::Success } else { ResultCode::OperationFailed },
+        spin_down_target: None,
+    })
+}
+
+fn simulation_pages(r: &mut ByteReader<'_>) -> Option<Vec<[u8; 8]>> {
+    // 0.001 m/s, 0.01 %, 0.0001, 0.01 kg/m
+    let wind_speed = r.i16()? as f32 / 1000.0;
+    let grade = r.i16()? as f32 / 100.0;
+    let crr = r.u8()? as f32 / 10000.0;
+    let cw = r.u8()?;
+
+    // 0.01 % from -200 %, 5e-5
+    let [grade_lo, grade_hi] = (((grade + 200.0) * 100.0).round().max(0.0).min(40000.0) as u16).to_le_bytes();
+    let crr = (crr / 0.00005).round().max(0.0).min(254.0) as u8;
+    // km/h from -127 km/h
+    let wind = (wind_speed * 3.6 + 127.0).round().max(0.0).min(254.0) as u8;
+    Some(vec![
+        [PAGE_TRACK_RESISTANCE, 0xFF, 0xFF, 0xFF, 0xFF, grade_lo, grade_hi, crr],
+        [PAGE_WIND_RESISTANCE, 0xFF, 0xFF, 0xFF, 0xFF, cw.min(254), wind, DRAFTING_FACTOR],
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bluetooth::ftms::{ControlRequest, SimulationParameters};
+
+    #[test]
+    fn decodes_trainer_pages() {
+        // 8.333 m/s
+        let general = decode(&[PAGE_GENERAL, 25, 0, 0, 0x8D, 0x20, 0xFF, 0x30]);
+        assert!(matches!(general[..], [TrainerEvent::Speed(s)] if (s - 8.333).abs() < 1e-3));
+
+        // 90 rpm, 0x12C = 300 W with trainer status bits set in the upper nibble
+        let trainer = decode(&[PAGE_TRAINER, 1, 90, 0, 0, 0x2C, 0x31, 0x30]);
+        assert!(matches!(trainer[0], TrainerEvent::Cadence(c) if c == 90.0));
+        assert!(matches!(&trainer[1], TrainerEvent::Power(m) if m.instantaneous_power == 300));
+
+        assert!(decode(&[PAGE_TRAINER, 1, 0xFF, 0, 0, 0xFF, 0x0F, 0x30]).is_empty());
+    }
+
+    #[test]
+    fn translates_control_requests() {
+        let power = control_pages(&ControlRequest::SetTargetPower(250).encode());
+        assert_eq!(power, vec![[PAGE_TARGET_POWER, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xE8, 0x03]]);
+
+        let resistance = control_pages(&ControlRequest::SetTargetResistance(25.5).encode());
+        assert_eq!(resistance[0][7], 200);
+
+        let simulation = control_pages(&ControlRequest::SetSimulation(SimulationParameters {
+            wind_speed: 0.0,
+            grade: 5.0,
+            crr: 0.004,
+            cw: 0.51,
+        }).encode());
+        // 205 % in 0.01 %, crr 80 * 5e-5
+        assert_eq!(simulation[0], [PAGE_TRACK_RESISTANCE, 0xFF, 0xFF, 0xFF, 0xFF, 0x14, 0x50, 80]);
+        assert_eq!(simulation[1], [PAGE_WIND_RESISTANCE, 0xFF, 0xFF, 0xFF, 0xFF, 51, 127, 100]);
+
+        assert!(control_pages(&ControlRequest::RequestControl.encode()).is_empty());
+    }
+}
@@ -0,0 +1,92 @@
+use crate::bluetooth::hrm::HeartRateMeasurement;
+
+pub const DEVICE_TYPE: u8 = 120;
+/// 1/32768 s, about 4 Hz
+pub const CHANNEL_PERIOD: u16 = 8070;
+
+/// Fields common to every ANT+ heart rate page, bytes 4 to 7
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct HrmPage {
+    /// 1/1024 s, time of the last beat
+    pub beat_time: u16,
+    /// Rolls over at 256
+    pub beat_count: u8,
+    /// bpm, 0 when invalid
+    pub heart_rate: u8,
+}
+
+impl HrmPage {
+    pub fn parse(page: &[u8; 8]) -> HrmPage {
+        HrmPage {
+            beat_time: u16::from_le_bytes([page[4], page[5]]),
+            beat_count: page[6],
+            heart_rate: page[7],
+        }
+    }
+}
+
+/// Turns heart rate pages into measurements. Pages are repeated until the next beat,
+/// the beat to beat interval is known when the count advances by exactly one.
+#[derive(Default)]
+pub struct HrmDecoder {
+    last: Option<HrmPage>,
+}
+
+impl HrmDecoder {
+    /// `None` while the page repeats the previous beat or carries no heart rate
+    pub fn decode(&mut self, page: &[u8; 8]) -> Option<HeartRateMeasurement> {
+        let current = HrmPage::parse(page);
+        let last = self.last.replace(current);
+        if current.heart_rate == 0 {
+            return None;
+        }
+        let mut rr_intervals = Vec::new();
+        if let Some(last) = last {
+            let beats = current.beat_count.wrapping_sub(last.beat_count);
+            if beats == 0 {
+                return None;
+            }
+            if beats == 1 {
+                rr_intervals.push(current.beat_time.wrapping_sub(last.beat_time) as f32 * 1000.0 / 1024.0);
+            }
+        }
+        Some(HeartRateMeasurement {
+            heart_rate: current.heart_rate as u16,
+            contact: None,
+            energy_expended: None,
+            rr_intervals,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(beat_time: u16, beat_count: u8, heart_rate: u8) -> [u8; 8] {
+        let [lo, hi] = beat_time.to_le_bytes();
+        [0x04, 0xFF, 0xFF, 0xFF, lo, hi, beat_count, heart_rate]
+    }
+
+    #[test]
+    fn reports_each_beat_once_with_interval() {
+        let mut decoder = HrmDecoder::default();
+        let first = decoder.decode(&page(65000, 10, 72)).unwrap();
+        assert_eq!(first.heart_rate, 72);
+        assert!(first.rr_intervals.is_empty());
+        assert_eq!(decoder.decode(&page(65000, 10, 72)), None);
+        // 1024 ticks later, across the event time rollover
+        let next = decoder.decode(&page(65000u16.wrapping_add(1024), 11, 71)).unwrap();
+        assert_eq!(next.heart_rate, 71);
+        assert_eq!(next.rr_intervals, vec![1000.0]);
+    }
+
+    #[test]
+    fn skips_interval_after_missed_beats() {
+        let mut decoder = HrmDecoder::default();
+        decoder.decode(&page(0, 255, 80));
+        let m = decoder.decode(&page(2048, 1, 80)).unwrap();
+        assert!(m.rr_intervals.is_empty());
+        assert_eq!(decoder.decode(&page(3072, 2, 0)), None);
+    }
+}
@@ -0,0 +1,6 @@
+pub mod codec;
+pub mod fec;
+pub mod hrm;
+pub mod muscle_oxygen;
+pub mod node;
+pub mod transport;
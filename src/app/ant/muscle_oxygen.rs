@@ -0,0 +1,61 @@
+pub const DEVICE_TYPE: u8 = 31;
+/// 1/32768 s, 4 Hz
+pub const CHANNEL_PERIOD: u16 = 8192;
+
+const PAGE_MUSCLE_OXYGEN: u8 = 0x01;
+
+/// Largest valid readings, codes above mean too much ambient light or no measurement
+const THB_MAX: u16 = 0xFFD;
+const SMO2_MAX: u16 = 0x3E8;
+
+/// ANT+ Muscle Oxygen reading
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MuscleOxygen {
+    /// Saturated hemoglobin percentage
+    pub smo2: Option<f32>,
+    /// Total hemoglobin concentration, g/dl
+    pub thb: Option<f32>,
+}
+
+/// Turns muscle oxygen data pages into readings. A page is repeated until the
+/// next measurement, the event count tells new ones apart.
+#[derive(Default)]
+pub struct MuscleOxygenDecoder {
+    event_count: Option<u8>,
+}
+
+impl MuscleOxygenDecoder {
+    /// `None` for other pages and repeated measurements
+    pub fn decode(&mut self, page: &[u8; 8]) -> Option<MuscleOxygen> {
+        if page[0] != PAGE_MUSCLE_OXYGEN || self.event_count.replace(page[1]) == Some(page[1]) {
+            return None;
+        }
+        // 0.01 g/dl in byte 4 and the low nibble of byte 5, current SmO2 in 0.1 % in the
+        // top two bits of byte 6 and byte 7, previous SmO2 in between is skipped
+        let thb = u16::from_le_bytes([page[4], page[5] & 0x0F]);
+        let smo2 = (page[6] >> 6) as u16 | (page[7] as u16) << 2;
+        Some(MuscleOxygen {
+            smo2: if smo2 <= SMO2_MAX { Some(smo2 as f32 / 10.0) } else { None },
+            thb: if thb <= THB_MAX { Some(thb as f32 / 100.0) } else { None },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_new_measurements_only() {
+        let mut decoder = MuscleOxygenDecoder::default();
+        // tHb 0x4D2 = 12.34 g/dl, SmO2 0x2A7 = 67.9 %
+        let page = [PAGE_MUSCLE_OXYGEN, 7, 0, 0, 0xD2, 0x04, 0xC0, 0xA9];
+        assert_eq!(decoder.decode(&page), Some(MuscleOxygen { smo2: Some(67.9), thb: Some(12.34) }));
+        assert_eq!(decoder.decode(&page), None);
+
+        // invalid SmO2, ambient light too high for tHb
+        let page = [PAGE_MUSCLE_OXYGEN, 8, 0, 0, 0xFE, 0x0F, 0xC0, 0xFF];
+        assert_eq!(decoder.decode(&page), Some(MuscleOxygen { smo2: None, thb: None }));
+        assert_eq!(decoder.decode(&[0x50, 9, 0, 0, 0, 0, 0, 0]), None);
+    }
+}
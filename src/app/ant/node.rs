@@ -0,0 +1,438 @@
+use std::collections::VecDeque;
+
+use crate::ant::codec::*;
+use crate::ant::fec;
+use crate::ant::hrm::{self, HrmDecoder};
+use crate::ant::muscle_oxygen::{self, MuscleOxygen, MuscleOxygenDecoder};
+use crate::ant::transport::AntTransport;
+use crate::bluetooth::hrm::HeartRateMeasurement;
+use crate::bluetooth::power::TrainerEvent;
+use crate::bluetooth::reconnect::LinkEvent;
+
+const NETWORK: u8 = 0;
+/// Continue without the startup message if the stick doesn't send one, ms
+const RESET_TIMEOUT: f32 = 500.0;
+/// 2457 MHz, used by all ANT+ devices
+const RF_FREQUENCY: u8 = 57;
+/// 30 s in 2.5 s steps
+const SEARCH_TIMEOUT: u8 = 12;
+
+/// Device profile, each served on a channel of its own
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AntProfile {
+    HeartRate,
+    /// FE-C trainer
+    FitnessEquipment,
+    /// SmO2 and tHb
+    MuscleOxygen,
+}
+
+impl AntProfile {
+    pub const ALL: [AntProfile; 3] = [AntProfile::HeartRate, AntProfile::FitnessEquipment, AntProfile::MuscleOxygen];
+
+    fn channel(&self) -> u8 {
+        match self {
+            AntProfile::HeartRate => 0,
+            AntProfile::FitnessEquipment => 1,
+            AntProfile::MuscleOxygen => 2,
+        }
+    }
+
+    fn from_channel(channel: u8) -> Option<AntProfile> {
+        AntProfile::ALL.iter().copied().find(|p| p.channel() == channel)
+    }
+
+    fn device_type(&self) -> u8 {
+        match self {
+            AntProfile::HeartRate => hrm::DEVICE_TYPE,
+            AntProfile::FitnessEquipment => fec::DEVICE_TYPE,
+            AntProfile::MuscleOxygen => muscle_oxygen::DEVICE_TYPE,
+        }
+    }
+
+    fn period(&self) -> u16 {
+        match self {
+            AntProfile::HeartRate => hrm::CHANNEL_PERIOD,
+            AntProfile::FitnessEquipment => fec::CHANNEL_PERIOD,
+            AntProfile::MuscleOxygen => muscle_oxygen::CHANNEL_PERIOD,
+        }
+    }
+}
+
+/// What the node reports, in the terms the Bluetooth devices use
+#[derive(Clone, Debug)]
+pub enum AntEvent {
+    Link(AntProfile, LinkEvent),
+    HeartRate(HeartRateMeasurement),
+    Trainer(TrainerEvent),
+    MuscleOxygen(MuscleOxygen),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum StickState {
+    Closed,
+    /// Waiting for the startup message, ms since the reset
+    Resetting(f32),
+    Ready,
+}
+
+struct ChannelState {
+    profile: AntProfile,
+    /// Paired and receiving data pages
+    receiving: bool,
+}
+
+/// Fitness machine control request being sent as FE-C pages
+struct PendingControl {
+    request: Vec<u8>,
+    pages: VecDeque<[u8; 8]>,
+    /// Front page was sent and waits for its acknowledgement
+    sent: bool,
+}
+
+/// ANT USB stick with one slave channel per profile. Reset and channel setup are
+/// repeated whenever the transport (re)opens.
+pub struct AntNode {
+    transport: Box<dyn AntTransport>,
+    decoder: AntDecoder,
+    network_key: [u8; 8],
+    stick: StickState,
+    channels: Vec<ChannelState>,
+    hrm: HrmDecoder,
+    muscle_oxygen: MuscleOxygenDecoder,
+    control: Option<PendingControl>,
+    events: Vec<AntEvent>,
+}
+
+impl AntNode {
+    pub fn new(transport: Box<dyn AntTransport>, network_key: [u8; 8]) -> AntNode {
+        AntNode {
+            transport,
+            decoder: AntDecoder::default(),
+            network_key,
+            stick: StickState::Closed,
+            channels: Vec::new(),
+            hrm: HrmDecoder::default(),
+            muscle_oxygen: MuscleOxygenDecoder::default(),
+            control: None,
+            events: Vec::new(),
+        }
+    }
+
+    /// Search for any device of `profile`, reported through `AntEvent::Link`
+    pub fn open(&mut self, profile: AntProfile) {
+        if self.channels.iter().any(|c| c.profile == profile) {
+            return;
+        }
+        self.channels.push(ChannelState { profile, receiving: false });
+        self.events.push(AntEvent::Link(profile, LinkEvent::Connecting));
+        if self.stick == StickState::Ready {
+            self.configure_channel(profile);
+        }
+    }
+
+    /// Send a Fitness Machine Control Point request to the FE-C trainer. The reply comes
+    /// back as `TrainerEvent::Control` once the trainer acknowledged the pages.
+    pub fn control(&mut self, request: &[u8]) {
+        let pages = fec::control_pages(request);
+        if pages.is_empty() {
+            // nothing to send, e.g. FE-C trainers need no request for control
+            self.respond(request, true);
+            return;
+        }
+        self.control = Some(PendingControl { request: Vec::from(request), pages: pages.into(), sent: false });
+        self.send_control();
+    }
+
+    /// Advance by `dt` ms, returns what happened since the last call
+    pub fn poll(&mut self, dt: f32) -> Vec<AntEvent> {
+        if !self.transport.is_open() {
+            if self.stick != StickState::Closed {
+                self.stick = StickState::Closed;
+                for channel in self.channels.iter_mut() {
+                    if channel.receiving {
+                        channel.receiving = false;
+                        self.events.push(AntEvent::Link(channel.profile, LinkEvent::Disconnected));
+                    }
+                }
+            }
+            return self.events.drain(..).collect();
+        }
+        if self.stick == StickState::Closed {
+            self.send(AntMessage::reset_system());
+            self.stick = StickState::Resetting(0.0);
+        }
+
+        let bytes = self.transport.read();
+        for message in self.decoder.push(&bytes) {
+            self.handle(message);
+        }
+        if let StickState::Resetting(waited) = self.stick {
+            if waited + dt >= RESET_TIMEOUT {
+                self.configure();
+            } else {
+                self.stick = StickState::Resetting(waited + dt);
+            }
+        }
+        self.events.drain(..).collect()
+    }
+
+    fn handle(&mut self, message: AntMessage) {
+        if message.id == MSG_STARTUP {
+            if let StickState::Resetting(_) = self.stick {
+                self.configure();
+            }
+            return;
+        }
+        if let Some((channel, page)) = message.data_page() {
+            self.data_page(channel, &page);
+        } else if let Some(event) = message.channel_event() {
+            // message id 1 marks channel events, anything else answers a command
+            if event.message_id == 1 {
+                self.channel_event(event);
+            }
+        }
+    }
+
+    fn data_page(&mut self, channel: u8, page: &[u8; 8]) {
+        let profile = match AntProfile::from_channel(channel) {
+            Some(profile) => profile,
+            None => return,
+        };
+        if let Some(state) = self.channels.iter_mut().find(|c| c.profile == profile && !c.receiving) {
+            state.receiving = true;
+            self.events.push(AntEvent::Link(profile, LinkEvent::Connected));
+        }
+        match profile {
+            AntProfile::HeartRate => {
+                if let Some(m) = self.hrm.decode(page) {
+                    self.events.push(AntEvent::HeartRate(m));
+                }
+            }
+            AntProfile::FitnessEquipment => {
+                self.events.extend(fec::decode(page).into_iter().map(AntEvent::Trainer));
+                self.send_control();
+            }
+            AntProfile::MuscleOxygen => {
+                if let Some(m) = self.muscle_oxygen.decode(page) {
+                    self.events.push(AntEvent::MuscleOxygen(m));
+                }
+            }
+        }
+    }
+
+    fn channel_event(&mut self, event: ChannelEvent) {
+        let profile = match AntProfile::from_channel(event.channel) {
+            Some(profile) => profile,
+            None => return,
+        };
+        match event.code {
+            EVENT_RX_FAIL_GO_TO_SEARCH => {
+                if let Some(state) = self.channels.iter_mut().find(|c| c.profile == profile && c.receiving) {
+                    state.receiving = false;
+                    self.events.push(AntEvent::Link(profile, LinkEvent::Disconnected));
+                }
+            }
+            EVENT_RX_SEARCH_TIMEOUT => self.events.push(AntEvent::Link(profile, LinkEvent::Failed)),
+            EVENT_CHANNEL_CLOSED => {
+                // the channel has to be opened again, e.g. by the next reconnection attempt
+                self.channels.retain(|c| c.profile != profile);
+            }
+            EVENT_TRANSFER_TX_COMPLETED => {
+                let done = match self.control.as_mut() {
+                    Some(control) if control.sent => {
+                        control.sent = false;
+                        control.pages.pop_front();
+                        control.pages.is_empty()
+                    }
+                    _ => return,
+                };
+                if done {
+                    let control = self.control.take().unwrap();
+                    self.respond(&control.request, true);
+                } else {
+                    self.send_control();
+                }
+            }
+            EVENT_TRANSFER_TX_FAILED => {
+                if let Some(control) = self.control.take() {
+                    self.respond(&control.request, false);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Network key and the channels opened so far, after a reset
+    fn configure(&mut self) {
+        self.stick = StickState::Ready;
+        let key = self.network_key;
+        self.send(AntMessage::set_network_key(NETWORK, &key));
+        let profiles: Vec<AntProfile> = self.channels.iter().map(|c| c.profile).collect();
+        for profile in profiles {
+            self.configure_channel(profile);
+        }
+    }
+
+    fn configure_channel(&mut self, profile: AntProfile) {
+        let channel = profile.channel();
+        self.send(AntMessage::assign_channel(channel, CHANNEL_TYPE_SLAVE, NETWORK));
+        // device number 0 and transmission type 0 pair with the first device found
+        self.send(AntMessage::channel_id(channel, 0, profile.device_type(), 0));
+        self.send(AntMessage::channel_period(channel, profile.period()));
+        self.send(AntMessage::search_timeout(channel, SEARCH_TIMEOUT));
+        self.send(AntMessage::channel_rf_frequency(channel, RF_FREQUENCY));
+        self.send(AntMessage::open_channel(channel));
+    }
+
+    /// Acknowledged data is only sent to a trainer that is in range
+    fn send_control(&mut self) {
+        let receiving = self.channels.iter()
+            .any(|c| c.profile == AntProfile::FitnessEquipment && c.receiving);
+        let page = match self.control.as_mut() {
+            Some(control) if receiving && !control.sent => {
+                control.sent = true;
+                control.pages[0]
+            }
+            _ => return,
+        };
+        self.send(AntMessage::acknowledged_data(AntProfile::FitnessEquipment.channel(), &page));
+    }
+
+    fn respond(&mut self, request: &[u8], acknowledged: bool) {
+        if let Some(response) = fec::response(request, acknowledged) {
+            self.events.push(AntEvent::Trainer(TrainerEvent::Control(response)));
+        }
+    }
+
+    fn send(&mut self, message: AntMessage) {
+        self.transport.write(&message.encode());
+    }
+}
+
+/// Network key given as 16 hex digits, optionally separated by spaces, commas or colons
+pub fn parse_network_key(hex: &str) -> Option<[u8; 8]> {
+    let digits: Vec<char> = hex.chars()
+        .filter(|c| !c.is_whitespace() && *c != ',' && *c != ':')
+        .collect();
+    if digits.len() != 16 {
+        return None;
+    }
+    let mut key = [0; 8];
+    for (k, pair) in digits.chunks(2).enumerate() {
+        key[k] = u8::from_str_radix(&pair.iter().collect::<String>(), 16).ok()?;
+    }
+    Some(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ant::transport::ByteStream;
+    use crate::bluetooth::ftms::{ControlRequest, ResultCode, SimulationParameters};
+
+    const KEY: [u8; 8] = [1, 2, 3, 4, 5, 6, 7, 8];
+
+    fn started(stream: &ByteStream) -> AntNode {
+        let mut node = AntNode::new(Box::new(stream.clone()), KEY);
+        node.poll(0.0);
+        assert_eq!(AntDecoder::default().push(&stream.take_written()), vec![AntMessage::reset_system()]);
+        stream.push_incoming(&AntMessage::new(MSG_STARTUP, &[0x20]).encode());
+        node.poll(10.0);
+        node
+    }
+
+    fn written(stream: &ByteStream) -> Vec<AntMessage> {
+        AntDecoder::default().push(&stream.take_written())
+    }
+
+    fn channel_event(channel: u8, code: u8) -> Vec<u8> {
+        AntMessage::new(MSG_CHANNEL_EVENT, &[channel, 1, code]).encode()
+    }
+
+    #[test]
+    fn configures_and_reports_heart_rate() {
+        let stream = ByteStream::default();
+        let mut node = started(&stream);
+        assert_eq!(written(&stream), vec![AntMessage::set_network_key(0, &KEY)]);
+
+        node.open(AntProfile::HeartRate);
+        let setup = written(&stream);
+        assert_eq!(setup.first(), Some(&AntMessage::assign_channel(0, CHANNEL_TYPE_SLAVE, 0)));
+        assert!(setup.contains(&AntMessage::channel_id(0, 0, hrm::DEVICE_TYPE, 0)));
+        assert_eq!(setup.last(), Some(&AntMessage::open_channel(0)));
+
+        stream.push_incoming(&AntMessage::broadcast_data(0, &[0x04, 0, 0, 0, 0x00, 0x04, 7, 64]).encode());
+        let events = node.poll(250.0);
+        assert!(matches!(events[0], AntEvent::Link(AntProfile::HeartRate, LinkEvent::Connecting)));
+        assert!(matches!(events[1], AntEvent::Link(AntProfile::HeartRate, LinkEvent::Connected)));
+        assert!(matches!(&events[2], AntEvent::HeartRate(m) if m.heart_rate == 64));
+
+        stream.push_incoming(&channel_event(0, EVENT_RX_FAIL_GO_TO_SEARCH));
+        let events = node.poll(250.0);
+        assert!(matches!(events[..], [AntEvent::Link(AntProfile::HeartRate, LinkEvent::Disconnected)]));
+    }
+
+    #[test]
+    fn configures_channels_after_reset_timeout() {
+        let stream = ByteStream::default();
+        let mut node = AntNode::new(Box::new(stream.clone()), KEY);
+        node.open(AntProfile::FitnessEquipment);
+        node.poll(0.0);
+        stream.take_written();
+        node.poll(RESET_TIMEOUT);
+        let setup = written(&stream);
+        assert_eq!(setup[0], AntMessage::set_network_key(0, &KEY));
+        assert!(setup.contains(&AntMessage::channel_period(1, fec::CHANNEL_PERIOD)));
+
+        // the stick is reset again once the port reopens
+        stream.set_open(false);
+        node.poll(100.0);
+        stream.set_open(true);
+        node.poll(100.0);
+        assert_eq!(written(&stream), vec![AntMessage::reset_system()]);
+    }
+
+    #[test]
+    fn sends_control_pages_one_at_a_time() {
+        let stream = ByteStream::default();
+        let mut node = started(&stream);
+        node.open(AntProfile::FitnessEquipment);
+        stream.push_incoming(&AntMessage::broadcast_data(1, &[0x10, 25, 0, 0, 0, 0, 0xFF, 0x30]).encode());
+        node.poll(250.0);
+        written(&stream);
+
+        let request = ControlRequest::SetSimulation(SimulationParameters {
+            wind_speed: 0.0,
+            grade: 2.0,
+            crr: 0.004,
+            cw: 0.51,
+        }).encode();
+        node.control(&request);
+        let sent = written(&stream);
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].id, MSG_ACKNOWLEDGED_DATA);
+
+        stream.push_incoming(&channel_event(1, EVENT_TRANSFER_TX_COMPLETED));
+        assert!(node.poll(250.0).is_empty());
+        assert_eq!(written(&stream)[0].data_page().unwrap().1[0], 0x32);
+
+        stream.push_incoming(&channel_event(1, EVENT_TRANSFER_TX_COMPLETED));
+        let events = node.poll(250.0);
+        assert!(matches!(events[..], [AntEvent::Trainer(TrainerEvent::Control(r))]
+            if r.request_op_code == 0x11 && r.result == ResultCode::Success));
+
+        node.control(&ControlRequest::RequestControl.encode());
+        assert!(written(&stream).is_empty());
+        assert!(matches!(node.poll(0.0)[..], [AntEvent::Trainer(TrainerEvent::Control(r))]
+            if r.request_op_code == 0x00 && r.result == ResultCode::Success));
+    }
+
+    #[test]
+    fn parses_network_key() {
+        assert_eq!(parse_network_key("01 02 03 04 05 06 07 0a"), Some([1, 2, 3, 4, 5, 6, 7, 10]));
+        assert_eq!(parse_network_key("0102030405060708"), Some(KEY));
+        assert_eq!(parse_network_key("01 02"), None);
+        assert_eq!(parse_network_key(""), None);
+    }
+}
@@ -0,0 +1,128 @@
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use js_sys::Uint8Array;
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen(module = "/ant_serial.js")]
+extern "C" {
+    type AntSerial;
+    #[wasm_bindgen(constructor)]
+    fn new(on_data: &Closure<dyn FnMut(&JsValue)>, on_state_change: &Closure<dyn FnMut(&JsValue)>) -> AntSerial;
+
+    #[wasm_bindgen(method)]
+    fn connect(this: &AntSerial);
+
+    #[wasm_bindgen(method)]
+    fn write(this: &AntSerial, bytes: &[u8]);
+}
+
+/// Byte pipe to an ANT USB stick
+pub trait AntTransport {
+    fn write(&mut self, bytes: &[u8]);
+    /// Bytes received since the last call
+    fn read(&mut self) -> Vec<u8>;
+    /// Whether the stick can be talked to, the node resets it whenever this turns true
+    fn is_open(&self) -> bool;
+}
+
+struct SerialPort {
+    on_data: Closure<dyn FnMut(&JsValue)>,
+    on_state_change: Closure<dyn FnMut(&JsValue)>,
+    incoming: Rc<RefCell<Vec<u8>>>,
+    open: Rc<Cell<bool>>,
+    serial: AntSerial,
+}
+
+/// ANT stick behind the Web Serial API. Clones share the port, so the registry
+/// can keep one to reconnect while the node owns another.
+#[derive(Clone)]
+pub struct WebSerialTransport(Rc<SerialPort>);
+
+impl WebSerialTransport {
+    pub fn new() -> WebSerialTransport {
+        let incoming = Rc::new(RefCell::new(Vec::new()));
+        let open = Rc::new(Cell::new(false));
+        let on_data = {
+            let incoming = incoming.clone();
+            Closure::new(move |js: &JsValue| {
+                incoming.borrow_mut().extend(Uint8Array::new(js).to_vec());
+            })
+        };
+        let on_state_change = {
+            let open = open.clone();
+            Closure::new(move |js: &JsValue| {
+                open.set(js.as_string().as_deref() == Some("open"));
+            })
+        };
+        let serial = AntSerial::new(&on_data, &on_state_change);
+        WebSerialTransport(Rc::new(SerialPort {
+            on_data,
+            on_state_change,
+            incoming,
+            open,
+            serial,
+        }))
+    }
+
+    /// Let the user choose the USB stick, has to be called from a user gesture
+    pub fn connect(&self) {
+        self.0.serial.connect();
+    }
+}
+
+impl AntTransport for WebSerialTransport {
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.serial.write(bytes);
+    }
+
+    fn read(&mut self) -> Vec<u8> {
+        self.0.incoming.borrow_mut().drain(..).collect()
+    }
+
+    fn is_open(&self) -> bool {
+        self.0.open.get()
+    }
+}
+
+#[derive(Default)]
+struct Pipe {
+    incoming: Vec<u8>,
+    written: Vec<u8>,
+    closed: bool,
+}
+
+/// In-memory stand-in for a stick. Clones share the pipe, so one copy can be
+/// handed to the node and the other used to feed and inspect bytes.
+#[derive(Clone, Default)]
+pub struct ByteStream(Rc<RefCell<Pipe>>);
+
+impl ByteStream {
+    /// Bytes the node will read next
+    pub fn push_incoming(&self, bytes: &[u8]) {
+        self.0.borrow_mut().incoming.extend_from_slice(bytes);
+    }
+
+    /// Everything written since the last call
+    pub fn take_written(&self) -> Vec<u8> {
+        self.0.borrow_mut().written.drain(..).collect()
+    }
+
+    pub fn set_open(&self, open: bool) {
+        self.0.borrow_mut().closed = !open;
+    }
+}
+
+impl AntTransport for ByteStream {
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.borrow_mut().written.extend_from_slice(bytes);
+    }
+
+    fn read(&mut self) -> Vec<u8> {
+        self.0.borrow_mut().incoming.drain(..).collect()
+    }
+
+    fn is_open(&self) -> bool {
+        !self.0.borrow().closed
+    }
+}
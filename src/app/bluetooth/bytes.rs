@@ -0,0 +1,70 @@
+/// Little-endian reader over a GATT characteristic value
+pub struct ByteReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    pub fn new(data: &'a [u8]) -> ByteReader<'a> {
+        ByteReader { data, pos: 0 }
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.remaining() < n {
+            return None;
+        }
+        let res = &self.data[self.pos..self.pos + n];
+        self.pos += n;
+        Some(res)
+    }
+
+    pub fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    pub fn i8(&mut self) -> Option<i8> {
+        self.u8().map(|v| v as i8)
+    }
+
+    pub fn u16(&mut self) -> Option<u16> {
+        self.take(2).map(|b| u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    pub fn i16(&mut self) -> Option<i16> {
+        self.u16().map(|v| v as i16)
+    }
+
+    pub fn u24(&mut self) -> Option<u32> {
+        self.take(3).map(|b| u32::from_le_bytes([b[0], b[1], b[2], 0]))
+    }
+
+    pub fn u32(&mut self) -> Option<u32> {
+        self.take(4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    /// IEEE-11073 32-bit FLOAT: signed 24-bit mantissa and signed 8-bit base 10 exponent.
+    /// `None` if the value is missing or one of NaN, NRes and the infinities.
+    pub fn float(&mut self) -> Option<f32> {
+        let raw = self.u32()?;
+        let mantissa = raw & 0x00FF_FFFF;
+        if (0x007F_FFFE..=0x0080_0002).contains(&mantissa) {
+            return None;
+        }
+        // sign extend from 24 bits
+        let mantissa = ((mantissa << 8) as i32) >> 8;
+        let exponent = (raw >> 24) as i8;
+        Some(mantissa as f32 * 10f32.powi(exponent as i32))
+    }
+
+    pub fn skip(&mut self, n: usize) -> Option<()> {
+        self.take(n).map(|_| ())
+    }
+}
+
+pub fn bit_test(flags: u32, bit: u32) -> bool {
+    (flags >> bit) & 1 != 0
+}
@@ -0,0 +1,290 @@
+use crate::bluetooth::ftms::{ControlResponse, ResultCode, SpinDownStatus, SpinDownTarget};
+use crate::bluetooth::power::PowerControlResponse;
+
+/// Give up when the device stops answering, ms
+const STEP_TIMEOUT: f32 = 60000.0;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CalibrationKind {
+    /// Cycling power meter zero offset
+    ZeroOffset,
+    /// FTMS trainer spin-down
+    SpinDown,
+}
+
+impl CalibrationKind {
+    pub fn label(&self) -> &'static str {
+        match self {
+            CalibrationKind::ZeroOffset => "Zero offset",
+            CalibrationKind::SpinDown => "Spin-down",
+        }
+    }
+}
+
+/// Outcome of a calibration run
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CalibrationResult {
+    pub kind: CalibrationKind,
+    pub success: bool,
+    /// Raw offset reported by the power meter
+    pub offset: Option<i16>,
+    /// Time the trainer spent coasting down, ms
+    pub coast_time: Option<f32>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CalibrationStep {
+    Idle,
+    /// Tell the rider how to prepare, waits for confirmation
+    Instruct(CalibrationKind),
+    /// Request sent, waiting for the device
+    Calibrating(CalibrationKind),
+    /// Ride up to the speed range the trainer asked for, m/s
+    WaitForSpeed(SpinDownTarget),
+    /// Stop pedaling and let the flywheel run down
+    Coast,
+    Done(CalibrationResult),
+}
+
+/// What the device has to be told when the rider confirms
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CalibrationCommand {
+    StartOffsetCompensation,
+    StartSpinDown,
+}
+
+/// Guided calibration workflow: instruct, (wait for speed, coast,) result
+pub struct Calibration {
+    kind: CalibrationKind,
+    step: CalibrationStep,
+    /// Time spent in the current step, ms
+    elapsed: f32,
+}
+
+impl Calibration {
+    pub fn new(kind: CalibrationKind) -> Calibration {
+        Calibration {
+            kind,
+            step: CalibrationStep::Instruct(kind),
+            elapsed: 0.0,
+        }
+    }
+
+    pub fn kind(&self) -> CalibrationKind {
+        self.kind
+    }
+
+    pub fn step(&self) -> CalibrationStep {
+        self.step
+    }
+
+    pub fn is_finished(&self) -> bool {
+        matches!(self.step, CalibrationStep::Done(_))
+    }
+
+    /// Result to log once finished, `time` in ms since epoch
+    pub fn record(&self, time: f64) -> Option<CalibrationRecord> {
+        match self.step {
+            CalibrationStep::Done(result) => Some(CalibrationRecord { time, result }),
+            _ => None,
+        }
+    }
+
+    pub fn instruction(&self) -> &'static str {
+        match (self.kind, self.step) {
+            (_, CalibrationStep::Idle) => "",
+            (CalibrationKind::ZeroOffset, CalibrationStep::Instruct(_)) =>
+                "Unclip, keep the cranks still and press Ready",
+            (CalibrationKind::SpinDown, CalibrationStep::Instruct(_)) =>
+                "Warm up the trainer for 10 minutes, then press Ready",
+            (_, CalibrationStep::Calibrating(_)) => "Calibrating...",
+            (_, CalibrationStep::WaitForSpeed(_)) => "Speed up into the target range",
+            (_, CalibrationStep::Coast) => "Stop pedaling and coast",
+            (_, CalibrationStep::Done(result)) if result.success => "Calibration complete",
+            (_, CalibrationStep::Done(_)) => "Calibration failed",
+        }
+    }
+
+    /// Rider is ready, returns what to send to the device
+    pub fn confirm(&mut self) -> Option<CalibrationCommand> {
+        if self.step != CalibrationStep::Instruct(self.kind) {
+            return None;
+        }
+        self.set_step(CalibrationStep::Calibrating(self.kind));
+        Some(match self.kind {
+            CalibrationKind::ZeroOffset => CalibrationCommand::StartOffsetCompensation,
+            CalibrationKind::SpinDown => CalibrationCommand::StartSpinDown,
+        })
+    }
+
+    /// Device can't be reached
+    pub fn fail(&mut self) {
+        self.finish(false, None, None);
+    }
+
+    /// Feed a Cycling Power Control Point indication, true if the step changed
+    pub fn power_control(&mut self, response: &PowerControlResponse) -> bool {
+        if self.step != CalibrationStep::Calibrating(CalibrationKind::ZeroOffset) || !response.is_offset_compensation() {
+            return false;
+        }
+        self.finish(response.result == ResultCode::Success, response.offset, None);
+        true
+    }
+
+    /// Feed a Fitness Machine Control Point indication, true if the step changed
+    pub fn control_response(&mut self, response: &ControlResponse) -> bool {
+        if self.step != CalibrationStep::Calibrating(CalibrationKind::SpinDown)
+            || !response.is_spin_down() {
+            return false;
+        }
+        match (response.result, response.spin_down_target) {
+            (ResultCode::Success, Some(target)) => self.set_step(CalibrationStep::WaitForSpeed(target)),
+            // trainer accepted but didn't say how fast, go straight to its status
+            (ResultCode::Success, None) => return false,
+            _ => self.fail(),
+        }
+        true
+    }
+
+    /// Feed a spin-down status of the Fitness Machine Status, true if the step changed
+    pub fn spin_down_status(&mut self, status: SpinDownStatus) -> bool {
+        match self.step {
+            CalibrationStep::Calibrating(CalibrationKind::SpinDown) | CalibrationStep::WaitForSpeed(_) | CalibrationStep::Coast => {}
+            _ => return false,
+        }
+        match status {
+            SpinDownStatus::StopPedaling if self.step != CalibrationStep::Coast => self.set_step(CalibrationStep::Coast),
+            SpinDownStatus::Success => {
+                let coast_time = if self.step == CalibrationStep::Coast { Some(self.elapsed) } else { None };
+                self.finish(true, None, coast_time);
+            }
+            SpinDownStatus::Error => self.fail(),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Advance by `dt` ms, true if the step changed
+    pub fn tick(&mut self, dt: f32) -> bool {
+        self.elapsed += dt;
+        match self.step {
+            CalibrationStep::Calibrating(_) | CalibrationStep::WaitForSpeed(_) | CalibrationStep::Coast
+            if self.elapsed >= STEP_TIMEOUT => {
+                self.fail();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn finish(&mut self, success: bool, offset: Option<i16>, coast_time: Option<f32>) {
+        self.set_step(CalibrationStep::Done(CalibrationResult { kind: self.kind, success, offset, coast_time }));
+    }
+
+    fn set_step(&mut self, step: CalibrationStep) {
+        self.step = step;
+        self.elapsed = 0.0;
+    }
+}
+
+/// Calibration result logged with the session
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CalibrationRecord {
+    /// ms since epoch
+    pub time: f64,
+    pub result: CalibrationResult,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spin_down_target(data: &[u8]) -> SpinDownTarget {
+        ControlResponse::parse(data).unwrap().spin_down_target.unwrap()
+    }
+
+    #[test]
+    fn guides_through_spin_down() {
+        let mut calibration = Calibration::new(CalibrationKind::SpinDown);
+        assert_eq!(calibration.step(), CalibrationStep::Instruct(CalibrationKind::SpinDown));
+        // nothing happens before the rider is ready
+        assert!(!calibration.spin_down_status(SpinDownStatus::StopPedaling));
+        assert!(!calibration.control_response(&ControlResponse::parse(&[0x80, 0x13, 0x01, 0x10, 0x0E, 0x80, 0x0C]).unwrap()));
+        assert_eq!(calibration.confirm(), Some(CalibrationCommand::StartSpinDown));
+        assert_eq!(calibration.confirm(), None);
+        assert_eq!(calibration.step(), CalibrationStep::Calibrating(CalibrationKind::SpinDown));
+
+        // replies to other requests are not for us
+        assert!(!calibration.control_response(&ControlResponse::parse(&[0x80, 0x05, 0x01]).unwrap()));
+        let reply = [0x80, 0x13, 0x01, 0x10, 0x0E, 0x80, 0x0C];
+        assert!(calibration.control_response(&ControlResponse::parse(&reply).unwrap()));
+        assert_eq!(calibration.step(), CalibrationStep::WaitForSpeed(spin_down_target(&reply)));
+        assert!(calibration.spin_down_status(SpinDownStatus::StopPedaling));
+        assert_eq!(calibration.step(), CalibrationStep::Coast);
+        assert!(!calibration.spin_down_status(SpinDownStatus::StopPedaling));
+        assert!(!calibration.tick(12_500.0));
+        assert!(calibration.spin_down_status(SpinDownStatus::Success));
+        assert!(calibration.is_finished());
+        let result = CalibrationResult { kind: CalibrationKind::SpinDown, success: true, offset: None, coast_time: Some(12_500.0) };
+        assert_eq!(calibration.step(), CalibrationStep::Done(result));
+        assert_eq!(calibration.record(1000.0), Some(CalibrationRecord { time: 1000.0, result }));
+        // finished runs ignore late reports
+        assert!(!calibration.spin_down_status(SpinDownStatus::Error));
+    }
+
+    #[test]
+    fn reports_failed_spin_down() {
+        let mut calibration = Calibration::new(CalibrationKind::SpinDown);
+        calibration.confirm();
+        assert!(calibration.control_response(&ControlResponse::parse(&[0x80, 0x13, 0x04]).unwrap()));
+        assert_eq!(calibration.record(0.0).unwrap().result,
+                   CalibrationResult { kind: CalibrationKind::SpinDown, success: false, offset: None, coast_time: None });
+
+        let mut calibration = Calibration::new(CalibrationKind::SpinDown);
+        calibration.confirm();
+        assert!(calibration.control_response(&ControlResponse::parse(&[0x80, 0x13, 0x01, 0x10, 0x0E, 0x80, 0x0C]).unwrap()));
+        assert!(calibration.spin_down_status(SpinDownStatus::Error));
+        assert!(!calibration.record(0.0).unwrap().result.success);
+    }
+
+    #[test]
+    fn zero_offset_reports_offset() {
+        let mut calibration = Calibration::new(CalibrationKind::ZeroOffset);
+        assert_eq!(calibration.record(0.0), None);
+        assert_eq!(calibration.confirm(), Some(CalibrationCommand::StartOffsetCompensation));
+        // spin-down reports don't apply
+        assert!(!calibration.spin_down_status(SpinDownStatus::Success));
+        assert!(calibration.power_control(&PowerControlResponse::parse(&[0x20, 0x0C, 0x01, 0xF6, 0xFF]).unwrap()));
+        let result = CalibrationResult { kind: CalibrationKind::ZeroOffset, success: true, offset: Some(-10), coast_time: None };
+        assert_eq!(calibration.record(5.0), Some(CalibrationRecord { time: 5.0, result }));
+
+        let mut calibration = Calibration::new(CalibrationKind::ZeroOffset);
+        calibration.confirm();
+        assert!(calibration.power_control(&PowerControlResponse::parse(&[0x20, 0x0C, 0x04]).unwrap()));
+        assert_eq!(calibration.step(), CalibrationStep::Done(CalibrationResult { success: false, offset: None, ..result }));
+    }
+
+    #[test]
+    fn fails_when_a_step_times_out() {
+        let mut calibration = Calibration::new(CalibrationKind::ZeroOffset);
+        // the rider may take their time reading the instructions
+        assert!(!calibration.tick(2.0 * STEP_TIMEOUT));
+        calibration.confirm();
+        assert!(!calibration.tick(STEP_TIMEOUT - 1.0));
+        assert!(calibration.tick(1.0));
+        assert!(!calibration.record(0.0).unwrap().result.success);
+
+        // every step of the spin-down gets its own time
+        let mut calibration = Calibration::new(CalibrationKind::SpinDown);
+        calibration.confirm();
+        calibration.tick(STEP_TIMEOUT - 1.0);
+        calibration.control_response(&ControlResponse::parse(&[0x80, 0x13, 0x01, 0x10, 0x0E, 0x80, 0x0C]).unwrap());
+        calibration.tick(STEP_TIMEOUT - 1.0);
+        calibration.spin_down_status(SpinDownStatus::StopPedaling);
+        assert!(!calibration.tick(STEP_TIMEOUT - 1.0));
+        assert!(calibration.tick(1.0));
+        assert_eq!(calibration.step(), CalibrationStep::Done(CalibrationResult {
+            kind: CalibrationKind::SpinDown, success: false, offset: None, coast_time: None,
+        }));
+    }
+}
@@ -0,0 +1,297 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use wasm_bindgen::prelude::*;
+
+use crate::bluetooth::bytes::{bit_test, ByteReader};
+use crate::bluetooth::device_info::DeviceDetails;
+use crate::bluetooth::gatt::{optional, BluetoothDevice, GattTransport, CSC_MEASUREMENT, CYCLING_SPEED_AND_CADENCE_SERVICE};
+
+const WHEEL_REVOLUTION_DATA_PRESENT: u32 = 0;
+const CRANK_REVOLUTION_DATA_PRESENT: u32 = 1;
+
+/// 700x25c
+pub const DEFAULT_WHEEL_CIRCUMFERENCE: f32 = 2.105;
+/// Event times of CSC measurement are in 1/1024 s
+const CSC_TICKS_PER_SECOND: f32 = 1024.0;
+/// Repeated readings without a new event before the value drops to zero
+const STOP_AFTER_REPEATS: u32 = 3;
+/// Rates above these are treated as counter resets, rev/s
+const MAX_WHEEL_RATE: f32 = 40.0;
+const MAX_CRANK_RATE: f32 = 4.0;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RevolutionReading {
+    pub revolutions: u32,
+    pub last_event_time: u16,
+}
+
+/// Decoded CSC Measurement (0x2A5B)
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CscMeasurement {
+    pub wheel: Option<RevolutionReading>,
+    pub crank: Option<RevolutionReading>,
+}
+
+impl CscMeasurement {
+    pub fn parse(data: &[u8]) -> Option<CscMeasurement> {
+        let mut r = ByteReader::new(data);
+        let flags = r.u8()? as u32;
+        let wheel = if bit_test(flags, WHEEL_REVOLUTION_DATA_PRESENT) {
+            Some(RevolutionReading { revolutions: r.u32()?, last_event_time: r.u16()? })
+        } else { None };
+        let crank = if bit_test(flags, CRANK_REVOLUTION_DATA_PRESENT) {
+            Some(RevolutionReading { revolutions: r.u16()? as u32, last_event_time: r.u16()? })
+        } else { None };
+        Some(CscMeasurement { wheel, crank })
+    }
+}
+
+/// Turns cumulative revolution counter and last event time into revolutions per second.
+/// Both counter and 16-bit event time are allowed to wrap between readings.
+pub struct RevolutionRate {
+    counter_mask: u32,
+    ticks_per_second: f32,
+    max_rate: f32,
+    last: Option<RevolutionReading>,
+    rate: Option<f32>,
+    repeats: u32,
+}
+
+impl RevolutionRate {
+    pub fn new(counter_bits: u32, ticks_per_second: f32, max_rate: f32) -> RevolutionRate {
+        RevolutionRate {
+            counter_mask: if counter_bits >= 32 { u32::MAX } else { (1 << counter_bits) - 1 },
+            ticks_per_second,
+            max_rate,
+            last: None,
+            rate: None,
+            repeats: 0,
+        }
+    }
+
+    /// Current rate, `None` until two readings were seen
+    pub fn rate(&self) -> Option<f32> {
+        self.rate
+    }
+
+    pub fn reset(&mut self) {
+        self.last = None;
+        self.rate = None;
+        self.repeats = 0;
+    }
+
+    pub fn update(&mut self, reading: RevolutionReading) -> Option<f32> {
+        let last = match self.last {
+            Some(last) => last,
+            None => {
+                self.last = Some(reading);
+                return self.rate;
+            }
+        };
+        let revolutions = reading.revolutions.wrapping_sub(last.revolutions) & self.counter_mask;
+        let ticks = reading.last_event_time.wrapping_sub(last.last_event_time);
+
+        if ticks == 0 {
+            if revolutions == 0 {
+                // no new event since the previous notification
+                self.repeats += 1;
+                if self.repeats >= STOP_AFTER_REPEATS {
+                    self.rate = Some(0.0);
+                }
+            }
+            return self.rate;
+        }
+
+        self.last = Some(reading);
+        self.repeats = 0;
+        let rate = revolutions as f32 * self.ticks_per_second / ticks as f32;
+        if rate <= self.max_rate {
+            self.rate = Some(rate);
+        }
+        self.rate
+    }
+}
+
+/// Speed and cadence from a CSC sensor
+pub struct CscCalculator {
+    /// m
+    wheel_circumference: f32,
+    wheel: RevolutionRate,
+    crank: RevolutionRate,
+}
+
+impl CscCalculator {
+    pub fn new(wheel_circumference: f32) -> CscCalculator {
+        CscCalculator {
+            wheel_circumference,
+            wheel: RevolutionRate::new(32, CSC_TICKS_PER_SECOND, MAX_WHEEL_RATE),
+            crank: RevolutionRate::new(16, CSC_TICKS_PER_SECOND, MAX_CRANK_RATE),
+        }
+    }
+
+    pub fn set_wheel_circumference(&mut self, wheel_circumference: f32) {
+        self.wheel_circumference = wheel_circumference;
+    }
+
+    /// Speed in m/s and cadence in rpm, each present once the sensor reported it twice
+    pub fn update(&mut self, m: &CscMeasurement) -> (Option<f32>, Option<f32>) {
+        let speed = m.wheel
+            .and_then(|w| self.wheel.update(w))
+            .map(|rate| rate * self.wheel_circumference);
+        let cadence = m.crank
+            .and_then(|c| self.crank.update(c))
+            .map(|rate| rate * 60.0);
+        (speed, cadence)
+    }
+}
+
+impl Default for CscCalculator {
+    fn default() -> Self {
+        CscCalculator::new(DEFAULT_WHEEL_CIRCUMFERENCE)
+    }
+}
+
+/// Cycling Speed and Cadence (0x1816) profile on top of a connected transport
+#[derive(Clone)]
+pub struct CscProfile {
+    calculator: Rc<RefCell<CscCalculator>>,
+    on_update: Rc<RefCell<dyn FnMut(Option<f32>, Option<f32>)>>,
+}
+
+impl CscProfile {
+    /// `on_update` receives speed in m/s and cadence in rpm
+    pub fn new<F: 'static>(calculator: Rc<RefCell<CscCalculator>>, on_update: F) -> CscProfile
+    where F: FnMut(Option<f32>, Option<f32>) {
+        CscProfile { calculator, on_update: Rc::new(RefCell::new(on_update)) }
+    }
+
+    pub fn attach(&self, gatt: &dyn GattTransport) {
+        let profile = self.clone();
+        gatt.subscribe(CYCLING_SPEED_AND_CADENCE_SERVICE, CSC_MEASUREMENT, Box::new(move |bytes| {
+            if let Some(measurement) = CscMeasurement::parse(bytes) {
+                let (speed, cadence) = profile.calculator.borrow_mut().update(&measurement);
+                (profile.on_update.borrow_mut())(speed, cadence);
+            }
+        }), optional());
+    }
+}
+
+/// Standalone speed and cadence sensor
+pub struct CscSensor {
+    calculator: Rc<RefCell<CscCalculator>>,
+    sensor: BluetoothDevice,
+}
+
+impl CscSensor {
+    /// `on_update` receives speed in m/s and cadence in rpm
+    pub fn new<F: 'static, D: 'static, G: 'static>(wheel_circumference: f32, on_update: F, on_details: D, on_state: G) -> CscSensor
+    where F: FnMut(Option<f32>, Option<f32>), D: FnMut(DeviceDetails), G: FnMut(&JsValue) {
+        let calculator = Rc::new(RefCell::new(CscCalculator::new(wheel_circumference)));
+        let profile = CscProfile::new(calculator.clone(), on_update);
+        let sensor = BluetoothDevice::new(&[CYCLING_SPEED_AND_CADENCE_SERVICE], &[], move |gatt| profile.attach(gatt),
+                                          on_details, on_state);
+        CscSensor {
+            calculator,
+            sensor,
+        }
+    }
+
+    pub fn reconnect(&self) {
+        self.sensor.connect();
+    }
+
+    pub fn resume(&self, id: Option<String>) {
+        self.sensor.resume(id);
+    }
+
+    pub fn set_wheel_circumference(&self, wheel_circumference: f32) {
+        self.calculator.borrow_mut().set_wheel_circumference(wheel_circumference);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bluetooth::fake_peripheral::FakePeripheral;
+
+    fn measurement(wheel_revolutions: u32, wheel_time: u16, crank_revolutions: u16, crank_time: u16) -> Vec<u8> {
+        let mut bytes = vec![0x03];
+        bytes.extend_from_slice(&wheel_revolutions.to_le_bytes());
+        bytes.extend_from_slice(&wheel_time.to_le_bytes());
+        bytes.extend_from_slice(&crank_revolutions.to_le_bytes());
+        bytes.extend_from_slice(&crank_time.to_le_bytes());
+        bytes
+    }
+
+    fn reading(revolutions: u32, last_event_time: u16) -> RevolutionReading {
+        RevolutionReading { revolutions, last_event_time }
+    }
+
+    #[test]
+    fn parses_measurement() {
+        let m = CscMeasurement::parse(&measurement(70_000, 0x1234, 300, 0x4321)).unwrap();
+        assert_eq!(m.wheel, Some(reading(70_000, 0x1234)));
+        assert_eq!(m.crank, Some(reading(300, 0x4321)));
+        // crank data only
+        let m = CscMeasurement::parse(&[0x02, 0x2C, 0x01, 0x21, 0x43]).unwrap();
+        assert_eq!(m, CscMeasurement { wheel: None, crank: Some(reading(300, 0x4321)) });
+        assert_eq!(CscMeasurement::parse(&measurement(1, 2, 3, 4)[..9]), None);
+    }
+
+    #[test]
+    fn drops_to_zero_after_repeated_readings() {
+        let mut rate = RevolutionRate::new(16, CSC_TICKS_PER_SECOND, MAX_CRANK_RATE);
+        assert_eq!(rate.update(reading(10, 0)), None);
+        assert_eq!(rate.update(reading(11, 1024)), Some(1.0));
+        // the same event again, the rider may just be between pedal strokes
+        for _ in 1..STOP_AFTER_REPEATS {
+            assert_eq!(rate.update(reading(11, 1024)), Some(1.0));
+        }
+        assert_eq!(rate.update(reading(11, 1024)), Some(0.0));
+        // pedalling again
+        assert_eq!(rate.update(reading(13, 2048)), Some(2.0));
+        assert_eq!(rate.update(reading(13, 2048)), Some(2.0));
+        assert_eq!(rate.update(reading(14, 2560)), Some(2.0));
+
+        rate.reset();
+        assert_eq!(rate.rate(), None);
+        assert_eq!(rate.update(reading(14, 2560)), None);
+    }
+
+    #[test]
+    fn ignores_counter_resets() {
+        let mut rate = RevolutionRate::new(32, CSC_TICKS_PER_SECOND, MAX_WHEEL_RATE);
+        rate.update(reading(1000, 0));
+        assert_eq!(rate.update(reading(1008, 1024)), Some(8.0));
+        // sensor restarted counting from zero, the wrapped difference is far too fast
+        assert_eq!(rate.update(reading(2, 2048)), Some(8.0));
+        // the next reading is measured from the reset counter
+        assert_eq!(rate.update(reading(10, 3072)), Some(8.0));
+        assert_eq!(rate.update(reading(14, 4096)), Some(4.0));
+
+        let mut calculator = CscCalculator::new(2.0);
+        calculator.update(&CscMeasurement { wheel: Some(reading(0, 0)), crank: None });
+        calculator.set_wheel_circumference(2.2);
+        let (speed, cadence) = calculator.update(&CscMeasurement { wheel: Some(reading(5, 1024)), crank: None });
+        assert!((speed.unwrap() - 11.0).abs() < 1e-4);
+        assert_eq!(cadence, None);
+    }
+
+    #[test]
+    fn computes_speed_and_cadence_from_peripheral() {
+        let peripheral = FakePeripheral::new().with_characteristic(CYCLING_SPEED_AND_CADENCE_SERVICE, CSC_MEASUREMENT, &[]);
+        let received = Rc::new(RefCell::new(Vec::new()));
+        let sink = received.clone();
+        let calculator = Rc::new(RefCell::new(CscCalculator::new(2.0)));
+        CscProfile::new(calculator, move |speed, cadence| sink.borrow_mut().push((speed, cadence)))
+            .attach(&peripheral);
+        peripheral.run();
+
+        // 5 wheel and 1.5 crank revolutions per second, counters wrapping
+        peripheral.notify(CYCLING_SPEED_AND_CADENCE_SERVICE, CSC_MEASUREMENT, &measurement(u32::MAX - 1, 0xFE00, 0xFFFF, 0xFE00));
+        peripheral.notify(CYCLING_SPEED_AND_CADENCE_SERVICE, CSC_MEASUREMENT, &measurement(8, 0x0600, 2, 0x0600));
+        peripheral.run();
+        assert_eq!(*received.borrow(), vec![(None, None), (Some(10.0), Some(90.0))]);
+    }
+}
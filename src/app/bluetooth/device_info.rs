@@ -0,0 +1,164 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::bluetooth::gatt::{optional, GattTransport, BATTERY_LEVEL, BATTERY_SERVICE, DEVICE_INFORMATION_SERVICE,
+                             FIRMWARE_REVISION_STRING, HARDWARE_REVISION_STRING, MANUFACTURER_NAME_STRING,
+                             MODEL_NUMBER_STRING, SERIAL_NUMBER_STRING};
+
+/// Percent, a warning is raised when the battery level drops below
+pub const LOW_BATTERY: u8 = 15;
+
+const STRINGS: [u16; 5] = [
+    MANUFACTURER_NAME_STRING, MODEL_NUMBER_STRING, SERIAL_NUMBER_STRING, FIRMWARE_REVISION_STRING,
+    HARDWARE_REVISION_STRING,
+];
+
+/// Device Information (0x180A), each field `None` until read or if the device doesn't have it
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DeviceInformation {
+    pub manufacturer: Option<String>,
+    pub model: Option<String>,
+    pub serial: Option<String>,
+    pub firmware_revision: Option<String>,
+    pub hardware_revision: Option<String>,
+}
+
+impl DeviceInformation {
+    /// Store the value read from one of the string characteristics
+    pub fn set(&mut self, characteristic: u16, value: &[u8]) {
+        let value = parse_string(value);
+        match characteristic {
+            MANUFACTURER_NAME_STRING => self.manufacturer = value,
+            MODEL_NUMBER_STRING => self.model = value,
+            SERIAL_NUMBER_STRING => self.serial = value,
+            FIRMWARE_REVISION_STRING => self.firmware_revision = value,
+            HARDWARE_REVISION_STRING => self.hardware_revision = value,
+            _ => {}
+        }
+    }
+}
+
+/// UTF-8 string characteristic, some devices pad it with NULs
+pub fn parse_string(data: &[u8]) -> Option<String> {
+    let value = String::from_utf8_lossy(data);
+    let value = value.trim_end_matches('\0').trim();
+    if value.is_empty() { None } else { Some(value.to_string()) }
+}
+
+/// Battery Level (0x2A19) in percent
+pub fn parse_battery_level(data: &[u8]) -> Option<u8> {
+    data.first().copied().filter(|level| *level <= 100)
+}
+
+/// Whether `level` is the first one below `LOW_BATTERY` since it was last above
+pub fn is_low_battery(previous: Option<u8>, level: u8) -> bool {
+    level < LOW_BATTERY && previous.map_or(true, |previous| previous >= LOW_BATTERY)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DeviceDetails {
+    /// Everything read so far
+    Info(DeviceInformation),
+    /// Percent
+    Battery(u8),
+}
+
+/// Device Information and Battery services, attached for every Bluetooth sensor
+#[derive(Clone)]
+pub struct DetailsProfile {
+    info: Rc<RefCell<DeviceInformation>>,
+    on_update: Rc<RefCell<dyn FnMut(DeviceDetails)>>,
+}
+
+impl DetailsProfile {
+    pub fn new<F: 'static>(on_update: F) -> DetailsProfile
+    where F: FnMut(DeviceDetails) {
+        DetailsProfile {
+            info: Rc::new(RefCell::new(DeviceInformation::default())),
+            on_update: Rc::new(RefCell::new(on_update)),
+        }
+    }
+
+    pub fn attach(&self, gatt: &dyn GattTransport) {
+        // the user may have picked another device since the last connection
+        self.info.replace(DeviceInformation::default());
+        for &characteristic in &STRINGS {
+            let profile = self.clone();
+            gatt.read(DEVICE_INFORMATION_SERVICE, characteristic, Box::new(move |result| {
+                if let Ok(value) = result {
+                    profile.info.borrow_mut().set(characteristic, &value);
+                    let info = profile.info.borrow().clone();
+                    (profile.on_update.borrow_mut())(DeviceDetails::Info(info));
+                }
+            }));
+        }
+
+        let profile = self.clone();
+        gatt.read(BATTERY_SERVICE, BATTERY_LEVEL, Box::new(move |result| {
+            if let Ok(value) = result {
+                profile.battery(&value);
+            }
+        }));
+        let profile = self.clone();
+        gatt.subscribe(BATTERY_SERVICE, BATTERY_LEVEL, Box::new(move |value| profile.battery(value)), optional());
+    }
+
+    fn battery(&self, value: &[u8]) {
+        if let Some(level) = parse_battery_level(value) {
+            (self.on_update.borrow_mut())(DeviceDetails::Battery(level));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bluetooth::fake_peripheral::FakePeripheral;
+
+    #[test]
+    fn parses_padded_strings_and_levels() {
+        assert_eq!(parse_string(b"Wahoo\0\0"), Some("Wahoo".to_string()));
+        assert_eq!(parse_string(b"\0"), None);
+        assert_eq!(parse_battery_level(&[87]), Some(87));
+        assert_eq!(parse_battery_level(&[101]), None);
+        assert_eq!(parse_battery_level(&[]), None);
+    }
+
+    #[test]
+    fn warns_once_when_battery_gets_low() {
+        assert!(is_low_battery(None, 10));
+        assert!(is_low_battery(Some(15), 14));
+        assert!(!is_low_battery(Some(14), 13));
+        assert!(!is_low_battery(Some(20), 15));
+    }
+
+    #[test]
+    fn reads_information_and_follows_battery() {
+        let peripheral = FakePeripheral::new()
+            .with_characteristic(DEVICE_INFORMATION_SERVICE, MANUFACTURER_NAME_STRING, b"Garmin")
+            .with_characteristic(DEVICE_INFORMATION_SERVICE, SERIAL_NUMBER_STRING, b"3412345\0")
+            .with_characteristic(DEVICE_INFORMATION_SERVICE, FIRMWARE_REVISION_STRING, b"4.30")
+            .with_characteristic(BATTERY_SERVICE, BATTERY_LEVEL, &[64]);
+        let updates = Rc::new(RefCell::new(Vec::new()));
+        let sink = updates.clone();
+        DetailsProfile::new(move |details| sink.borrow_mut().push(details)).attach(&peripheral);
+        peripheral.run();
+        peripheral.notify(BATTERY_SERVICE, BATTERY_LEVEL, &[12]);
+        peripheral.run();
+
+        let updates = updates.borrow();
+        let info = updates.iter().filter_map(|d| match d {
+            DeviceDetails::Info(info) => Some(info.clone()),
+            _ => None,
+        }).last();
+        assert_eq!(info, Some(DeviceInformation {
+            manufacturer: Some("Garmin".to_string()),
+            model: None,
+            serial: Some("3412345".to_string()),
+            firmware_revision: Some("4.30".to_string()),
+            hardware_revision: None,
+        }));
+        let levels: Vec<&DeviceDetails> = updates.iter().filter(|d| matches!(d, DeviceDetails::Battery(_))).collect();
+        assert_eq!(levels, vec![&DeviceDetails::Battery(64), &DeviceDetails::Battery(12)]);
+    }
+}
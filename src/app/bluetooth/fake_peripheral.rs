@@ -0,0 +1,176 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use crate::bluetooth::gatt::{GattCallback, GattError, GattTransport, NotificationHandler};
+
+/// Answers a write to a characteristic, `Some` value is indicated back on it
+type WriteScript = Box<dyn FnMut(&[u8]) -> Option<Vec<u8>>>;
+
+struct FakeCharacteristic {
+    service: u16,
+    uuid: u16,
+    value: Vec<u8>,
+    written: Vec<Vec<u8>>,
+    subscribers: Vec<NotificationHandler>,
+    on_write: Option<WriteScript>,
+}
+
+enum Pending {
+    Complete(Box<dyn FnOnce()>),
+    Notify(u16, u16, Vec<u8>),
+}
+
+#[derive(Default)]
+struct Peripheral {
+    services: Vec<u16>,
+    characteristics: Vec<FakeCharacteristic>,
+    connected: bool,
+    pending: VecDeque<Pending>,
+}
+
+impl Peripheral {
+    fn find(&mut self, service: u16, characteristic: u16) -> Result<&mut FakeCharacteristic, GattError> {
+        if !self.connected {
+            return Err(GattError::NotConnected);
+        }
+        self.characteristics.iter_mut()
+            .find(|c| c.service == service && c.uuid == characteristic)
+            .ok_or(GattError::NotFound)
+    }
+
+    fn complete<T: 'static>(&mut self, on_done: GattCallback<T>, result: Result<T, GattError>) {
+        self.pending.push_back(Pending::Complete(Box::new(move || on_done(result))));
+    }
+}
+
+/// In-memory peripheral for exercising device profiles natively. Completions and
+/// notifications are queued like they would be by a radio and delivered by `run`,
+/// so handlers are free to issue further requests.
+#[derive(Clone)]
+pub struct FakePeripheral(Rc<RefCell<Peripheral>>);
+
+impl FakePeripheral {
+    pub fn new() -> FakePeripheral {
+        FakePeripheral(Rc::new(RefCell::new(Peripheral {
+            connected: true,
+            ..Peripheral::default()
+        })))
+    }
+
+    pub fn with_characteristic(self, service: u16, characteristic: u16, value: &[u8]) -> FakePeripheral {
+        {
+            let mut p = self.0.borrow_mut();
+            if !p.services.contains(&service) {
+                p.services.push(service);
+            }
+            p.characteristics.push(FakeCharacteristic {
+                service,
+                uuid: characteristic,
+                value: value.to_vec(),
+                written: Vec::new(),
+                subscribers: Vec::new(),
+                on_write: None,
+            });
+        }
+        self
+    }
+
+    /// Answer writes to a characteristic, e.g. a control point indicating its response
+    pub fn on_write<F: 'static>(&self, service: u16, characteristic: u16, script: F)
+    where F: FnMut(&[u8]) -> Option<Vec<u8>> {
+        self.0.borrow_mut().characteristics.iter_mut()
+            .find(|c| c.service == service && c.uuid == characteristic)
+            .expect("no such characteristic")
+            .on_write = Some(Box::new(script));
+    }
+
+    /// Queue a notification to the subscribers of a characteristic
+    pub fn notify(&self, service: u16, characteristic: u16, value: &[u8]) {
+        self.0.borrow_mut().pending.push_back(Pending::Notify(service, characteristic, value.to_vec()));
+    }
+
+    /// Values written to a characteristic, oldest first
+    pub fn written(&self, service: u16, characteristic: u16) -> Vec<Vec<u8>> {
+        self.0.borrow().characteristics.iter()
+            .filter(|c| c.service == service && c.uuid == characteristic)
+            .flat_map(|c| c.written.clone())
+            .collect()
+    }
+
+    pub fn is_subscribed(&self, service: u16, characteristic: u16) -> bool {
+        self.0.borrow().characteristics.iter()
+            .any(|c| c.service == service && c.uuid == characteristic && !c.subscribers.is_empty())
+    }
+
+    /// Drop the link, subscriptions are lost and requests fail until reconnected
+    pub fn disconnect(&self) {
+        let mut p = self.0.borrow_mut();
+        p.connected = false;
+        p.pending.clear();
+        p.characteristics.iter_mut().for_each(|c| c.subscribers.clear());
+    }
+
+    pub fn reconnect(&self) {
+        self.0.borrow_mut().connected = true;
+    }
+
+    /// Deliver everything queued, including what handlers queue meanwhile
+    pub fn run(&self) {
+        loop {
+            let next = self.0.borrow_mut().pending.pop_front();
+            match next {
+                None => return,
+                Some(Pending::Complete(on_done)) => on_done(),
+                Some(Pending::Notify(service, characteristic, value)) => {
+                    // handlers run without the peripheral borrowed and are put back afterwards
+                    let found = self.0.borrow_mut().find(service, characteristic).map(|c| std::mem::take(&mut c.subscribers));
+                    let mut handlers = match found {
+                        Ok(handlers) => handlers,
+                        Err(_) => continue,
+                    };
+                    handlers.iter_mut().for_each(|handler| handler(&value));
+                    if let Ok(c) = self.0.borrow_mut().find(service, characteristic) {
+                        handlers.append(&mut c.subscribers);
+                        c.subscribers = handlers;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl GattTransport for FakePeripheral {
+    fn discover_services(&self, on_done: GattCallback<Vec<u16>>) {
+        let mut p = self.0.borrow_mut();
+        let result = if p.connected { Ok(p.services.clone()) } else { Err(GattError::NotConnected) };
+        p.complete(on_done, result);
+    }
+
+    fn read(&self, service: u16, characteristic: u16, on_done: GattCallback<Vec<u8>>) {
+        let mut p = self.0.borrow_mut();
+        let result = p.find(service, characteristic).map(|c| c.value.clone());
+        p.complete(on_done, result);
+    }
+
+    fn write(&self, service: u16, characteristic: u16, value: &[u8], on_done: GattCallback<()>) {
+        let mut p = self.0.borrow_mut();
+        let (result, response) = match p.find(service, characteristic) {
+            Ok(c) => {
+                c.written.push(value.to_vec());
+                (Ok(()), c.on_write.as_mut().and_then(|script| script(value)))
+            }
+            Err(e) => (Err(e), None),
+        };
+        p.complete(on_done, result);
+        if let Some(response) = response {
+            p.pending.push_back(Pending::Notify(service, characteristic, response));
+        }
+    }
+
+    fn subscribe(&self, service: u16, characteristic: u16, on_value: NotificationHandler, on_done: GattCallback<()>) {
+        let mut p = self.0.borrow_mut();
+        let result = p.find(service, characteristic).map(|c| c.subscribers.push(on_value));
+        p.complete(on_done, result);
+    }
+}
@@ -0,0 +1,703 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use crate::bluetooth::bytes::{bit_test, ByteReader};
+
+const OP_REQUEST_CONTROL: u8 = 0x00;
+const OP_RESET: u8 = 0x01;
+const OP_SET_TARGET_RESISTANCE: u8 = 0x04;
+const OP_SET_TARGET_POWER: u8 = 0x05;
+const OP_START_OR_RESUME: u8 = 0x07;
+const OP_STOP_OR_PAUSE: u8 = 0x08;
+const OP_SET_INDOOR_BIKE_SIMULATION: u8 = 0x11;
+const OP_SPIN_DOWN_CONTROL: u8 = 0x13;
+const OP_RESPONSE_CODE: u8 = 0x80;
+
+/// Indoor Bike Data (0x2AD2) flags
+/// Cleared when instantaneous speed is present, set when the rest follows in another notification
+const MORE_DATA: u32 = 0;
+const AVERAGE_SPEED_PRESENT: u32 = 1;
+const INSTANTANEOUS_CADENCE_PRESENT: u32 = 2;
+const AVERAGE_CADENCE_PRESENT: u32 = 3;
+const TOTAL_DISTANCE_PRESENT: u32 = 4;
+const RESISTANCE_LEVEL_PRESENT: u32 = 5;
+const INSTANTANEOUS_POWER_PRESENT: u32 = 6;
+const AVERAGE_POWER_PRESENT: u32 = 7;
+const EXPENDED_ENERGY_PRESENT: u32 = 8;
+const HEART_RATE_PRESENT: u32 = 9;
+const METABOLIC_EQUIVALENT_PRESENT: u32 = 10;
+const ELAPSED_TIME_PRESENT: u32 = 11;
+const REMAINING_TIME_PRESENT: u32 = 12;
+
+/// Fitness Machine Status (0x2ADA) op code reporting spin down progress
+const STATUS_SPIN_DOWN: u8 = 0x14;
+
+/// Drop the request in flight if the trainer didn't indicate a response in time, ms
+const RESPONSE_TIMEOUT: f32 = 3000.0;
+/// Minimal interval between simulation parameter updates, ms
+const SIMULATION_INTERVAL: f32 = 1000.0;
+
+/// Indoor bike simulation parameters in SI units
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SimulationParameters {
+    /// m/s, positive is headwind
+    pub wind_speed: f32,
+    /// Percent
+    pub grade: f32,
+    /// Rolling resistance coefficient
+    pub crr: f32,
+    /// Wind resistance coefficient, kg/m
+    pub cw: f32,
+}
+
+impl SimulationParameters {
+    fn encode_into(&self, res: &mut Vec<u8>) {
+        let wind = (self.wind_speed * 1000.0).round().max(i16::MIN as f32).min(i16::MAX as f32) as i16;
+        let grade = (self.grade * 100.0).round().max(i16::MIN as f32).min(i16::MAX as f32) as i16;
+        res.extend_from_slice(&wind.to_le_bytes());
+        res.extend_from_slice(&grade.to_le_bytes());
+        res.push((self.crr * 10000.0).round().max(0.0).min(255.0) as u8);
+        res.push((self.cw * 100.0).round().max(0.0).min(255.0) as u8);
+    }
+}
+
+/// Fitness Machine Control Point (0x2AD9) procedures
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ControlRequest {
+    RequestControl,
+    Reset,
+    /// Unitless, 0.1 resolution
+    SetTargetResistance(f32),
+    /// Watts
+    SetTargetPower(i16),
+    StartOrResume,
+    Stop,
+    Pause,
+    SetSimulation(SimulationParameters),
+    StartSpinDown,
+    IgnoreSpinDown,
+}
+
+impl ControlRequest {
+    pub fn op_code(&self) -> u8 {
+        match self {
+            ControlRequest::RequestControl => OP_REQUEST_CONTROL,
+            ControlRequest::Reset => OP_RESET,
+            ControlRequest::SetTargetResistance(_) => OP_SET_TARGET_RESISTANCE,
+            ControlRequest::SetTargetPower(_) => OP_SET_TARGET_POWER,
+            ControlRequest::StartOrResume => OP_START_OR_RESUME,
+            ControlRequest::Stop | ControlRequest::Pause => OP_STOP_OR_PAUSE,
+            ControlRequest::SetSimulation(_) => OP_SET_INDOOR_BIKE_SIMULATION,
+            ControlRequest::StartSpinDown | ControlRequest::IgnoreSpinDown => OP_SPIN_DOWN_CONTROL,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut res = vec![self.op_code()];
+        match self {
+            ControlRequest::SetTargetResistance(level) => res.push((level * 10.0).round().max(0.0).min(255.0) as u8),
+            ControlRequest::SetTargetPower(watts) => res.extend_from_slice(&watts.to_le_bytes()),
+            ControlRequest::Stop => res.push(0x01),
+            ControlRequest::Pause => res.push(0x02),
+            ControlRequest::SetSimulation(params) => params.encode_into(&mut res),
+            ControlRequest::StartSpinDown => res.push(0x01),
+            ControlRequest::IgnoreSpinDown => res.push(0x02),
+            _ => {}
+        }
+        res
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ResultCode {
+    Success,
+    NotSupported,
+    InvalidParameter,
+    OperationFailed,
+    ControlNotPermitted,
+    Reserved(u8),
+}
+
+impl From<u8> for ResultCode {
+    fn from(v: u8) -> Self {
+        match v {
+            0x01 => ResultCode::Success,
+            0x02 => ResultCode::NotSupported,
+            0x03 => ResultCode::InvalidParameter,
+            0x04 => ResultCode::OperationFailed,
+            0x05 => ResultCode::ControlNotPermitted,
+            other => ResultCode::Reserved(other),
+        }
+    }
+}
+
+/// Speed range the trainer wants to be ridden at before coasting, m/s
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SpinDownTarget {
+    pub low: f32,
+    pub high: f32,
+}
+
+/// Indication sent by the trainer in reply to a control point write
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ControlResponse {
+    pub request_op_code: u8,
+    pub result: ResultCode,
+    /// Present in a successful reply to `StartSpinDown`
+    pub spin_down_target: Option<SpinDownTarget>,
+}
+
+impl ControlResponse {
+    pub fn parse(data: &[u8]) -> Option<ControlResponse> {
+        let mut r = ByteReader::new(data);
+        if r.u8()? != OP_RESPONSE_CODE {
+            return None;
+        }
+        let request_op_code = r.u8()?;
+        let result = ResultCode::from(r.u8()?);
+        // speeds in 0.01 km/h
+        let spin_down_target = if request_op_code == OP_SPIN_DOWN_CONTROL && r.remaining() >= 4 {
+            Some(SpinDownTarget { low: r.u16()? as f32 / 360.0, high: r.u16()? as f32 / 360.0 })
+        } else { None };
+        Some(ControlResponse { request_op_code, result, spin_down_target })
+    }
+
+    pub fn is_spin_down(&self) -> bool {
+        self.request_op_code == OP_SPIN_DOWN_CONTROL
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SpinDownStatus {
+    Requested,
+    Success,
+    Error,
+    StopPedaling,
+    Reserved(u8),
+}
+
+impl From<u8> for SpinDownStatus {
+    fn from(v: u8) -> Self {
+        match v {
+            0x01 => SpinDownStatus::Requested,
+            0x02 => SpinDownStatus::Success,
+            0x03 => SpinDownStatus::Error,
+            0x04 => SpinDownStatus::StopPedaling,
+            other => SpinDownStatus::Reserved(other),
+        }
+    }
+}
+
+/// Decoded Fitness Machine Status (0x2ADA) notification
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MachineStatus {
+    SpinDown(SpinDownStatus),
+    /// Status op code not handled here
+    Other(u8),
+}
+
+impl MachineStatus {
+    pub fn parse(data: &[u8]) -> Option<MachineStatus> {
+        let mut r = ByteReader::new(data);
+        match r.u8()? {
+            STATUS_SPIN_DOWN => Some(MachineStatus::SpinDown(SpinDownStatus::from(r.u8()?))),
+            other => Some(MachineStatus::Other(other)),
+        }
+    }
+}
+
+/// Kilocalories
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ExpendedEnergy {
+    pub total: u16,
+    pub per_hour: u16,
+    pub per_minute: u8,
+}
+
+/// Decoded Indoor Bike Data (0x2AD2)
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct IndoorBikeData {
+    /// Remaining fields follow in the next notification
+    pub more_data: bool,
+    /// m/s
+    pub instantaneous_speed: Option<f32>,
+    /// m/s
+    pub average_speed: Option<f32>,
+    /// rpm
+    pub instantaneous_cadence: Option<f32>,
+    /// rpm
+    pub average_cadence: Option<f32>,
+    /// Metres
+    pub total_distance: Option<u32>,
+    /// Unitless, trainer specific
+    pub resistance_level: Option<i16>,
+    /// Watts
+    pub instantaneous_power: Option<i16>,
+    /// Watts
+    pub average_power: Option<i16>,
+    pub expended_energy: Option<ExpendedEnergy>,
+    /// bpm
+    pub heart_rate: Option<u8>,
+    pub metabolic_equivalent: Option<f32>,
+    /// Seconds
+    pub elapsed_time: Option<u16>,
+    /// Seconds
+    pub remaining_time: Option<u16>,
+}
+
+impl IndoorBikeData {
+    /// Decode characteristic value, `None` if it is shorter than its flags announce
+    pub fn parse(data: &[u8]) -> Option<IndoorBikeData> {
+        let mut r = ByteReader::new(data);
+        let flags = r.u16()? as u32;
+        let more_data = bit_test(flags, MORE_DATA);
+        // speeds in 0.01 km/h, cadence in 0.5 rpm
+        let instantaneous_speed = if !more_data { Some(r.u16()? as f32 / 360.0) } else { None };
+        let average_speed = if bit_test(flags, AVERAGE_SPEED_PRESENT) { Some(r.u16()? as f32 / 360.0) } else { None };
+        let instantaneous_cadence = if bit_test(flags, INSTANTANEOUS_CADENCE_PRESENT) {
+            Some(r.u16()? as f32 / 2.0)
+        } else { None };
+        let average_cadence = if bit_test(flags, AVERAGE_CADENCE_PRESENT) { Some(r.u16()? as f32 / 2.0) } else { None };
+        let total_distance = if bit_test(flags, TOTAL_DISTANCE_PRESENT) { Some(r.u24()?) } else { None };
+        let resistance_level = if bit_test(flags, RESISTANCE_LEVEL_PRESENT) { Some(r.i16()?) } else { None };
+        let instantaneous_power = if bit_test(flags, INSTANTANEOUS_POWER_PRESENT) { Some(r.i16()?) } else { None };
+        let average_power = if bit_test(flags, AVERAGE_POWER_PRESENT) { Some(r.i16()?) } else { None };
+        let expended_energy = if bit_test(flags, EXPENDED_ENERGY_PRESENT) {
+            Some(ExpendedEnergy { total: r.u16()?, per_hour: r.u16()?, per_minute: r.u8()? })
+        } else { None };
+        let heart_rate = if bit_test(flags, HEART_RATE_PRESENT) { Some(r.u8()?) } else { None };
+        let metabolic_equivalent = if bit_test(flags, METABOLIC_EQUIVALENT_PRESENT) {
+            Some(r.u8()? as f32 / 10.0)
+        } else { None };
+        let elapsed_time = if bit_test(flags, ELAPSED_TIME_PRESENT) { Some(r.u16()?) } else { None };
+        let remaining_time = if bit_test(flags, REMAINING_TIME_PRESENT) { Some(r.u16()?) } else { None };
+        Some(IndoorBikeData {
+            more_data,
+            instantaneous_speed,
+            average_speed,
+            instantaneous_cadence,
+            average_cadence,
+            total_distance,
+            resistance_level,
+            instantaneous_power,
+            average_power,
+            expended_energy,
+            heart_rate,
+            metabolic_equivalent,
+            elapsed_time,
+            remaining_time,
+        })
+    }
+}
+
+pub type ControlWriter = Box<dyn Fn(&[u8])>;
+
+struct ControlState {
+    writer: Option<ControlWriter>,
+    has_control: bool,
+    queue: VecDeque<ControlRequest>,
+    in_flight: Option<ControlRequest>,
+    waiting: f32,
+    target_power: Option<i16>,
+    simulation: Option<SimulationParameters>,
+    sent_simulation: Option<Vec<u8>>,
+    since_simulation: f32,
+}
+
+/// Shared handle to the trainer control point. FTMS allows only one outstanding
+/// procedure, so requests are queued and written one by one as responses arrive.
+#[derive(Clone)]
+pub struct TrainerControl(Rc<RefCell<ControlState>>);
+
+impl TrainerControl {
+    pub fn new() -> TrainerControl {
+        TrainerControl(Rc::new(RefCell::new(ControlState {
+            writer: None,
+            has_control: false,
+            queue: VecDeque::new(),
+            in_flight: None,
+            waiting: 0.0,
+            target_power: None,
+            simulation: None,
+            sent_simulation: None,
+            since_simulation: SIMULATION_INTERVAL,
+        })))
+    }
+
+    /// Set by the connected device, requests are kept queued until then
+    pub fn set_writer(&self, writer: Option<ControlWriter>) {
+        self.0.borrow_mut().writer = writer;
+        self.restart();
+    }
+
+    /// Forget the control handshake, e.g. after the trainer reconnected
+    pub fn restart(&self) {
+        let mut state = self.0.borrow_mut();
+        state.has_control = false;
+        state.in_flight = None;
+        state.sent_simulation = None;
+        drop(state);
+        self.send_next();
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.0.borrow().writer.is_some()
+    }
+
+    pub fn has_control(&self) -> bool {
+        self.0.borrow().has_control
+    }
+
+    /// Last target confirmed by the trainer
+    pub fn target_power(&self) -> Option<i16> {
+        self.0.borrow().target_power
+    }
+
+    pub fn request_control(&self) {
+        self.enqueue(ControlRequest::RequestControl);
+    }
+
+    pub fn reset(&self) {
+        self.enqueue(ControlRequest::Reset);
+    }
+
+    pub fn start(&self) {
+        self.enqueue(ControlRequest::StartOrResume);
+    }
+
+    pub fn stop(&self) {
+        self.enqueue(ControlRequest::Stop);
+    }
+
+    pub fn pause(&self) {
+        self.enqueue(ControlRequest::Pause);
+    }
+
+    pub fn set_target_power(&self, watts: i16) {
+        self.enqueue(ControlRequest::SetTargetPower(watts));
+    }
+
+    /// Resistance mode, for trainers that don't simulate
+    pub fn set_target_resistance(&self, level: f32) {
+        self.enqueue(ControlRequest::SetTargetResistance(level));
+    }
+
+    /// Progress is reported through `MachineStatus::SpinDown`
+    pub fn start_spin_down(&self) {
+        self.enqueue(ControlRequest::StartSpinDown);
+    }
+
+    /// Desired simulation state, written on `tick` at most once per `SIMULATION_INTERVAL`
+    /// and only when it differs from what the trainer already has
+    pub fn set_simulation(&self, params: SimulationParameters) {
+        self.0.borrow_mut().simulation = Some(params);
+    }
+
+    /// Switch back from simulation, e.g. before ERG mode
+    pub fn clear_simulation(&self) {
+        let mut state = self.0.borrow_mut();
+        state.simulation = None;
+        state.sent_simulation = None;
+    }
+
+    pub fn enqueue(&self, request: ControlRequest) {
+        {
+            let mut state = self.0.borrow_mut();
+            // only the latest setting matters if the previous one wasn't sent yet
+            let pending = state.queue.iter_mut()
+                .find(|r| r.op_code() == request.op_code() && r.op_code() != OP_STOP_OR_PAUSE);
+            match pending {
+                Some(r) => *r = request,
+                None => state.queue.push_back(request),
+            }
+        }
+        self.send_next();
+    }
+
+    /// Feed an indication received from the control point
+    pub fn handle_response(&self, response: &ControlResponse) {
+        {
+            let mut state = self.0.borrow_mut();
+            let in_flight = match state.in_flight {
+                Some(r) if r.op_code() == response.request_op_code => r,
+                _ => return,
+            };
+            state.in_flight = None;
+            match (in_flight, response.result) {
+                (ControlRequest::RequestControl, ResultCode::Success) => state.has_control = true,
+                (ControlRequest::RequestControl, _) => {
+                    // trainer refuses remote control, nothing queued can succeed
+                    state.queue.clear();
+                }
+                (ControlRequest::Reset, ResultCode::Success) => {
+                    state.has_control = false;
+                    state.target_power = None;
+                }
+                (ControlRequest::SetTargetPower(watts), ResultCode::Success) => state.target_power = Some(watts),
+                (ControlRequest::SetSimulation(_), ResultCode::Success)
+                | (ControlRequest::SetTargetResistance(_), ResultCode::Success) => state.target_power = None,
+                (_, ResultCode::ControlNotPermitted) => {
+                    // trainer was taken over or reset, ask again and retry
+                    state.has_control = false;
+                    state.queue.push_front(in_flight);
+                }
+                (ControlRequest::SetSimulation(_), _) => state.sent_simulation = None,
+                _ => {}
+            }
+        }
+        self.send_next();
+    }
+
+    /// Called on every clock advance with elapsed ms
+    pub fn tick(&self, dt: f32) {
+        self.feed_simulation(dt);
+        {
+            let mut state = self.0.borrow_mut();
+            if state.in_flight.is_none() {
+                return;
+            }
+            state.waiting += dt;
+            if state.waiting < RESPONSE_TIMEOUT {
+                return;
+            }
+            state.in_flight = None;
+        }
+        self.send_next();
+    }
+
+    fn feed_simulation(&self, dt: f32) {
+        let request = {
+            let mut state = self.0.borrow_mut();
+            state.since_simulation += dt;
+            let params = match state.simulation {
+                Some(p) if state.writer.is_some() && state.since_simulation >= SIMULATION_INTERVAL => p,
+                _ => return,
+            };
+            let request = ControlRequest::SetSimulation(params);
+            let encoded = request.encode();
+            if state.sent_simulation.as_ref() == Some(&encoded) {
+                return;
+            }
+            state.sent_simulation = Some(encoded);
+            state.since_simulation = 0.0;
+            request
+        };
+        self.enqueue(request);
+    }
+
+    fn send_next(&self) {
+        let mut state = self.0.borrow_mut();
+        if state.in_flight.is_some() || state.writer.is_none() {
+            return;
+        }
+        let request = if !state.has_control && state.queue.front() != Some(&ControlRequest::RequestControl) {
+            if state.queue.is_empty() {
+                return;
+            }
+            ControlRequest::RequestControl
+        } else {
+            match state.queue.pop_front() {
+                Some(r) => r,
+                None => return,
+            }
+        };
+        state.in_flight = Some(request);
+        state.waiting = 0.0;
+        // release the state while writing so a synchronous reply can be handled
+        let writer = state.writer.take().unwrap();
+        drop(state);
+        writer(&request.encode());
+        let mut state = self.0.borrow_mut();
+        if state.writer.is_none() {
+            state.writer = Some(writer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recorder(control: &TrainerControl) -> Rc<RefCell<Vec<Vec<u8>>>> {
+        let written = Rc::new(RefCell::new(Vec::new()));
+        let sink = written.clone();
+        control.set_writer(Some(Box::new(move |data| sink.borrow_mut().push(data.to_vec()))));
+        written
+    }
+
+    #[test]
+    fn encodes_control_requests() {
+        assert_eq!(ControlRequest::RequestControl.encode(), vec![0x00]);
+        assert_eq!(ControlRequest::Reset.encode(), vec![0x01]);
+        assert_eq!(ControlRequest::SetTargetResistance(4.56).encode(), vec![0x04, 46]);
+        assert_eq!(ControlRequest::SetTargetResistance(-1.0).encode(), vec![0x04, 0]);
+        assert_eq!(ControlRequest::SetTargetPower(250).encode(), vec![0x05, 0xFA, 0x00]);
+        assert_eq!(ControlRequest::SetTargetPower(-2).encode(), vec![0x05, 0xFE, 0xFF]);
+        assert_eq!(ControlRequest::StartOrResume.encode(), vec![0x07]);
+        assert_eq!(ControlRequest::Stop.encode(), vec![0x08, 0x01]);
+        assert_eq!(ControlRequest::Pause.encode(), vec![0x08, 0x02]);
+        assert_eq!(ControlRequest::StartSpinDown.encode(), vec![0x13, 0x01]);
+        assert_eq!(ControlRequest::IgnoreSpinDown.encode(), vec![0x13, 0x02]);
+        // -1.5 m/s wind, 5.5 % grade, Crr 0.004, Cw 0.51 kg/m
+        let params = SimulationParameters { wind_speed: -1.5, grade: 5.5, crr: 0.004, cw: 0.51 };
+        assert_eq!(ControlRequest::SetSimulation(params).encode(), vec![0x11, 0x24, 0xFA, 0x26, 0x02, 40, 51]);
+        // out of range values saturate
+        let params = SimulationParameters { wind_speed: 100.0, grade: -400.0, crr: 1.0, cw: -1.0 };
+        assert_eq!(ControlRequest::SetSimulation(params).encode(), vec![0x11, 0xFF, 0x7F, 0x00, 0x80, 255, 0]);
+    }
+
+    #[test]
+    fn parses_control_responses() {
+        let r = ControlResponse::parse(&[0x80, 0x05, 0x01]).unwrap();
+        assert_eq!(r, ControlResponse { request_op_code: 0x05, result: ResultCode::Success, spin_down_target: None });
+        assert!(!r.is_spin_down());
+        assert_eq!(ControlResponse::parse(&[0x80, 0x00, 0x05]).unwrap().result, ResultCode::ControlNotPermitted);
+        assert_eq!(ControlResponse::parse(&[0x80, 0x11, 0x09]).unwrap().result, ResultCode::Reserved(0x09));
+        // spin down target 36 km/h to 32 km/h
+        let r = ControlResponse::parse(&[0x80, 0x13, 0x01, 0x10, 0x0E, 0x80, 0x0C]).unwrap();
+        assert!(r.is_spin_down());
+        let target = r.spin_down_target.unwrap();
+        assert!((target.low * 3.6 - 36.0).abs() < 1e-3 && (target.high * 3.6 - 32.0).abs() < 1e-3);
+        assert_eq!(ControlResponse::parse(&[0x80, 0x13, 0x02]).unwrap().spin_down_target, None);
+        // not a response, or truncated
+        assert_eq!(ControlResponse::parse(&[0x05, 0x05, 0x01]), None);
+        assert_eq!(ControlResponse::parse(&[0x80, 0x05]), None);
+        assert_eq!(MachineStatus::parse(&[0x14, 0x04]), Some(MachineStatus::SpinDown(SpinDownStatus::StopPedaling)));
+        assert_eq!(MachineStatus::parse(&[0x02]), Some(MachineStatus::Other(0x02)));
+    }
+
+    #[test]
+    fn queues_requests_until_control_is_granted() {
+        let control = TrainerControl::new();
+        control.set_target_power(200);
+        control.set_target_power(250);
+        assert!(!control.is_connected());
+        let written = recorder(&control);
+        assert_eq!(*written.borrow(), vec![vec![0x00]]);
+        control.handle_response(&ControlResponse::parse(&[0x80, 0x00, 0x01]).unwrap());
+        assert!(control.has_control());
+        // the superseded target is never written
+        assert_eq!(written.borrow()[1], vec![0x05, 0xFA, 0x00]);
+        assert_eq!(control.target_power(), None);
+        control.stop();
+        control.pause();
+        assert_eq!(written.borrow().len(), 2);
+        control.handle_response(&ControlResponse::parse(&[0x80, 0x05, 0x01]).unwrap());
+        assert_eq!(control.target_power(), Some(250));
+        assert_eq!(written.borrow()[2], vec![0x08, 0x01]);
+        // no reply, the next request goes out after the timeout
+        control.tick(RESPONSE_TIMEOUT - 1.0);
+        assert_eq!(written.borrow().len(), 3);
+        control.tick(1.0);
+        assert_eq!(written.borrow()[3], vec![0x08, 0x02]);
+    }
+
+    #[test]
+    fn asks_for_control_again_when_taken_over() {
+        let control = TrainerControl::new();
+        let written = recorder(&control);
+        control.set_target_power(150);
+        control.handle_response(&ControlResponse::parse(&[0x80, 0x00, 0x01]).unwrap());
+        control.handle_response(&ControlResponse::parse(&[0x80, 0x05, 0x05]).unwrap());
+        assert!(!control.has_control());
+        control.handle_response(&ControlResponse::parse(&[0x80, 0x00, 0x01]).unwrap());
+        assert_eq!(*written.borrow(), vec![vec![0x00], vec![0x05, 150, 0], vec![0x00], vec![0x05, 150, 0]]);
+
+        // a trainer refusing control drops everything queued
+        let control = TrainerControl::new();
+        let written = recorder(&control);
+        control.set_target_power(150);
+        control.handle_response(&ControlResponse::parse(&[0x80, 0x00, 0x05]).unwrap());
+        control.tick(RESPONSE_TIMEOUT);
+        assert_eq!(*written.borrow(), vec![vec![0x00]]);
+    }
+
+    #[test]
+    fn rate_limits_simulation_updates() {
+        let flat = SimulationParameters { wind_speed: 0.0, grade: 0.0, crr: 0.004, cw: 0.51 };
+        let climb = SimulationParameters { grade: 5.0, ..flat };
+        let control = TrainerControl::new();
+        control.set_simulation(flat);
+        control.tick(SIMULATION_INTERVAL);
+        // nothing is sent before the trainer is connected
+        let written = recorder(&control);
+        assert!(written.borrow().is_empty());
+        control.tick(100.0);
+        control.handle_response(&ControlResponse::parse(&[0x80, 0x00, 0x01]).unwrap());
+        assert_eq!(written.borrow()[1], ControlRequest::SetSimulation(flat).encode());
+        control.handle_response(&ControlResponse::parse(&[0x80, 0x11, 0x01]).unwrap());
+
+        // unchanged parameters aren't sent again, changed ones wait for the interval
+        for _ in 0..20 {
+            control.tick(100.0);
+        }
+        assert_eq!(written.borrow().len(), 2);
+        control.set_simulation(climb);
+        control.tick(100.0);
+        assert_eq!(written.borrow()[2], ControlRequest::SetSimulation(climb).encode());
+        control.handle_response(&ControlResponse::parse(&[0x80, 0x11, 0x01]).unwrap());
+        control.set_simulation(flat);
+        control.tick(SIMULATION_INTERVAL - 100.0);
+        assert_eq!(written.borrow().len(), 3);
+        control.tick(100.0);
+        assert_eq!(written.borrow()[3], ControlRequest::SetSimulation(flat).encode());
+
+        // a rejected update is retried
+        control.handle_response(&ControlResponse::parse(&[0x80, 0x11, 0x04]).unwrap());
+        control.tick(SIMULATION_INTERVAL);
+        assert_eq!(written.borrow()[4], ControlRequest::SetSimulation(flat).encode());
+
+        control.clear_simulation();
+        control.handle_response(&ControlResponse::parse(&[0x80, 0x11, 0x01]).unwrap());
+        control.tick(SIMULATION_INTERVAL);
+        assert_eq!(written.borrow().len(), 5);
+    }
+
+    /// Speed 25.2 km/h, cadence 90 rpm, 200 W
+    const SPEED_CADENCE_POWER: [u8; 8] = [0x44, 0x00, 0xD8, 0x09, 0xB4, 0x00, 0xC8, 0x00];
+    /// Every field after instantaneous speed, which follows in another notification
+    const ALL_BUT_SPEED: [u8; 28] = [
+        0xFF, 0x1F, 0x10, 0x0E, 0xA0, 0x00, 0x9C, 0x00, 0x40, 0xE2, 0x01, 0x05, 0x00, 0xFA, 0x00,
+        0xBE, 0x00, 0x40, 0x01, 0x58, 0x02, 0x0A, 0x96, 0x64, 0x10, 0x0E, 0x2C, 0x01,
+    ];
+
+    #[test]
+    fn speed_present_when_more_data_cleared() {
+        let d = IndoorBikeData::parse(&SPEED_CADENCE_POWER).unwrap();
+        assert!(!d.more_data);
+        assert!((d.instantaneous_speed.unwrap() - 7.0).abs() < 1e-4);
+        assert_eq!(d.instantaneous_cadence, Some(90.0));
+        assert_eq!(d.instantaneous_power, Some(200));
+        assert_eq!(d.total_distance, None);
+        assert_eq!(d.resistance_level, None);
+        assert_eq!(d.heart_rate, None);
+    }
+
+    #[test]
+    fn parses_all_fields_without_speed() {
+        let d = IndoorBikeData::parse(&ALL_BUT_SPEED).unwrap();
+        assert!(d.more_data);
+        assert_eq!(d.instantaneous_speed, None);
+        assert!((d.average_speed.unwrap() - 10.0).abs() < 1e-4);
+        assert_eq!(d.instantaneous_cadence, Some(80.0));
+        assert_eq!(d.average_cadence, Some(78.0));
+        assert_eq!(d.total_distance, Some(123456));
+        assert_eq!(d.resistance_level, Some(5));
+        assert_eq!(d.instantaneous_power, Some(250));
+        assert_eq!(d.average_power, Some(190));
+        assert_eq!(d.expended_energy, Some(ExpendedEnergy { total: 320, per_hour: 600, per_minute: 10 }));
+        assert_eq!(d.heart_rate, Some(150));
+        assert_eq!(d.metabolic_equivalent, Some(10.0));
+        assert_eq!(d.elapsed_time, Some(3600));
+        assert_eq!(d.remaining_time, Some(300));
+    }
+
+    #[test]
+    fn rejects_truncated_value() {
+        assert_eq!(IndoorBikeData::parse(&SPEED_CADENCE_POWER[..7]), None);
+        // total distance is three bytes
+        assert_eq!(IndoorBikeData::parse(&[0x11, 0x00, 0x40, 0xE2]), None);
+        assert_eq!(IndoorBikeData::parse(&[0x00]), None);
+    }
+}
@@ -0,0 +1,211 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+use js_sys::{Uint16Array, Uint8Array};
+use wasm_bindgen::JsCast;
+use wasm_bindgen::prelude::*;
+
+use crate::bluetooth::device_info::{DetailsProfile, DeviceDetails};
+
+#[wasm_bindgen(module = "/ble_devices.js")]
+extern "C" {
+    type GattServer;
+
+    #[wasm_bindgen(method)]
+    fn services(this: &GattServer, on_done: JsValue);
+
+    #[wasm_bindgen(method)]
+    fn read(this: &GattServer, service: u16, characteristic: u16, on_done: JsValue);
+
+    #[wasm_bindgen(method)]
+    fn write(this: &GattServer, service: u16, characteristic: u16, value: &[u8], on_done: JsValue);
+
+    #[wasm_bindgen(method)]
+    fn subscribe(this: &GattServer, service: u16, characteristic: u16, on_value: &Closure<dyn FnMut(&JsValue)>,
+                 on_done: JsValue);
+
+    type GattDevice;
+    #[wasm_bindgen(constructor)]
+    fn new(primary_services: &[u16], optional_services: &[u16], on_server: &Closure<dyn FnMut(&JsValue)>,
+           on_state_change: &Closure<dyn FnMut(&JsValue)>) -> GattDevice;
+
+    #[wasm_bindgen(method)]
+    fn connect(this: &GattDevice);
+
+    #[wasm_bindgen(method)]
+    fn resume(this: &GattDevice, id: Option<String>);
+}
+
+pub const DEVICE_INFORMATION_SERVICE: u16 = 0x180A;
+pub const MANUFACTURER_NAME_STRING: u16 = 0x2A29;
+pub const MODEL_NUMBER_STRING: u16 = 0x2A24;
+pub const SERIAL_NUMBER_STRING: u16 = 0x2A25;
+pub const FIRMWARE_REVISION_STRING: u16 = 0x2A26;
+pub const HARDWARE_REVISION_STRING: u16 = 0x2A27;
+
+pub const BATTERY_SERVICE: u16 = 0x180F;
+pub const BATTERY_LEVEL: u16 = 0x2A19;
+
+pub const HEART_RATE_SERVICE: u16 = 0x180D;
+pub const HEART_RATE_MEASUREMENT: u16 = 0x2A37;
+
+pub const HEALTH_THERMOMETER_SERVICE: u16 = 0x1809;
+pub const TEMPERATURE_MEASUREMENT: u16 = 0x2A1C;
+pub const INTERMEDIATE_TEMPERATURE: u16 = 0x2A1E;
+
+pub const CYCLING_SPEED_AND_CADENCE_SERVICE: u16 = 0x1816;
+pub const CSC_MEASUREMENT: u16 = 0x2A5B;
+
+pub const CYCLING_POWER_SERVICE: u16 = 0x1818;
+pub const CYCLING_POWER_MEASUREMENT: u16 = 0x2A63;
+pub const CYCLING_POWER_VECTOR: u16 = 0x2A64;
+pub const CYCLING_POWER_CONTROL_POINT: u16 = 0x2A66;
+
+pub const FITNESS_MACHINE_SERVICE: u16 = 0x1826;
+pub const INDOOR_BIKE_DATA: u16 = 0x2AD2;
+pub const FITNESS_MACHINE_CONTROL_POINT: u16 = 0x2AD9;
+pub const FITNESS_MACHINE_STATUS: u16 = 0x2ADA;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum GattError {
+    NotConnected,
+    /// The device has no such service or characteristic
+    NotFound,
+    Failed(String),
+}
+
+impl fmt::Display for GattError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GattError::NotConnected => write!(f, "not connected"),
+            GattError::NotFound => write!(f, "not found"),
+            GattError::Failed(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+/// Called once the operation completed
+pub type GattCallback<T> = Box<dyn FnOnce(Result<T, GattError>)>;
+/// Called with the value of every notification or indication
+pub type NotificationHandler = Box<dyn FnMut(&[u8])>;
+
+/// GATT client side of a connected peripheral. Services and characteristics are
+/// addressed by their 16-bit assigned numbers, every operation completes asynchronously.
+pub trait GattTransport {
+    /// Primary services of the peripheral
+    fn discover_services(&self, on_done: GattCallback<Vec<u16>>);
+
+    fn read(&self, service: u16, characteristic: u16, on_done: GattCallback<Vec<u8>>);
+
+    /// Write with response
+    fn write(&self, service: u16, characteristic: u16, value: &[u8], on_done: GattCallback<()>);
+
+    /// Enable notifications or indications, whichever the characteristic supports
+    fn subscribe(&self, service: u16, characteristic: u16, on_value: NotificationHandler, on_done: GattCallback<()>);
+}
+
+/// Completion for characteristics a device may or may not have
+pub fn optional<T>() -> GattCallback<T> {
+    Box::new(|_| {})
+}
+
+/// Transport of the currently connected server, `None` while disconnected
+pub type SharedGatt = Rc<RefCell<Option<Rc<dyn GattTransport>>>>;
+
+/// `GattTransport` over a Web Bluetooth GATT server
+pub struct WebGatt {
+    server: GattServer,
+    subscriptions: RefCell<Vec<Closure<dyn FnMut(&JsValue)>>>,
+}
+
+impl WebGatt {
+    fn completion<T: 'static, F: 'static>(on_done: GattCallback<T>, value: F) -> JsValue
+    where F: FnOnce(JsValue) -> T {
+        Closure::once_into_js(move |error: JsValue, js: JsValue| {
+            on_done(match error.as_string() {
+                // DOMException names are kept by the shim
+                Some(error) if error.starts_with("NotFoundError") => Err(GattError::NotFound),
+                Some(error) if error.starts_with("NetworkError") => Err(GattError::NotConnected),
+                Some(error) => Err(GattError::Failed(error)),
+                None => Ok(value(js)),
+            });
+        })
+    }
+}
+
+impl GattTransport for WebGatt {
+    fn discover_services(&self, on_done: GattCallback<Vec<u16>>) {
+        self.server.services(Self::completion(on_done, |js| Uint16Array::new(&js).to_vec()));
+    }
+
+    fn read(&self, service: u16, characteristic: u16, on_done: GattCallback<Vec<u8>>) {
+        self.server.read(service, characteristic, Self::completion(on_done, |js| Uint8Array::new(&js).to_vec()));
+    }
+
+    fn write(&self, service: u16, characteristic: u16, value: &[u8], on_done: GattCallback<()>) {
+        self.server.write(service, characteristic, value, Self::completion(on_done, |_| ()));
+    }
+
+    fn subscribe(&self, service: u16, characteristic: u16, mut on_value: NotificationHandler, on_done: GattCallback<()>) {
+        let on_value = Closure::new(move |js: &JsValue| {
+            on_value(&Uint8Array::new(js).to_vec());
+        });
+        self.server.subscribe(service, characteristic, &on_value, Self::completion(on_done, |_| ()));
+        self.subscriptions.borrow_mut().push(on_value);
+    }
+}
+
+/// Bluetooth device found by its primary services, handing out a `WebGatt` on every connect
+pub struct BluetoothDevice {
+    on_server: Closure<dyn FnMut(&JsValue)>,
+    on_state_change: Closure<dyn FnMut(&JsValue)>,
+    server: SharedGatt,
+    device: GattDevice,
+}
+
+impl BluetoothDevice {
+    /// `on_server` is called before the connected state is reported, profiles subscribe there.
+    /// Scanning looks for `primary_services`, `optional_services` are the other ones used.
+    /// Device information and battery level of every device go to `on_details`.
+    pub fn new<F: 'static, D: 'static, G: 'static>(primary_services: &[u16], optional_services: &[u16], mut on_server: F,
+                                                   on_details: D, on_state: G) -> BluetoothDevice
+    where F: FnMut(&dyn GattTransport), D: FnMut(DeviceDetails), G: FnMut(&JsValue) {
+        let details = DetailsProfile::new(on_details);
+        let server: SharedGatt = Rc::new(RefCell::new(None));
+        let current = server.clone();
+        let on_server = Closure::new(move |js: &JsValue| {
+            let gatt: Rc<dyn GattTransport> = Rc::new(WebGatt {
+                server: js.clone().unchecked_into(),
+                subscriptions: RefCell::new(Vec::new()),
+            });
+            on_server(gatt.as_ref());
+            details.attach(gatt.as_ref());
+            current.replace(Some(gatt));
+        });
+        let on_state_change = Closure::new(on_state);
+        let mut optional_services = optional_services.to_vec();
+        optional_services.extend_from_slice(&[DEVICE_INFORMATION_SERVICE, BATTERY_SERVICE]);
+        let device = GattDevice::new(primary_services, &optional_services, &on_server, &on_state_change);
+        BluetoothDevice {
+            on_server,
+            on_state_change,
+            server,
+            device,
+        }
+    }
+
+    pub fn connect(&self) {
+        self.device.connect();
+    }
+
+    /// Connect the known device without asking the user, `id` of a remembered one if none is known yet
+    pub fn resume(&self, id: Option<String>) {
+        self.device.resume(id);
+    }
+
+    /// Server of the last connection
+    pub fn server(&self) -> SharedGatt {
+        self.server.clone()
+    }
+}
@@ -1,39 +1,161 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
 use wasm_bindgen::prelude::*;
 
+use crate::bluetooth::bytes::{bit_test, ByteReader};
+use crate::bluetooth::device_info::DeviceDetails;
+use crate::bluetooth::gatt::{optional, BluetoothDevice, GattTransport, HEART_RATE_MEASUREMENT, HEART_RATE_SERVICE};
+
 #[wasm_bindgen(module = "/ble_devices.js")]
 extern "C" {
     fn name() -> String;
+}
+
+
+const HEART_RATE_16_BIT: u32 = 0;
+const SENSOR_CONTACT_DETECTED: u32 = 1;
+const SENSOR_CONTACT_SUPPORTED: u32 = 2;
+const ENERGY_EXPENDED_PRESENT: u32 = 3;
+const RR_INTERVALS_PRESENT: u32 = 4;
+
+/// Decoded Heart Rate Measurement (0x2A37)
+#[derive(Clone, Debug, PartialEq)]
+pub struct HeartRateMeasurement {
+    /// Beats per minute
+    pub heart_rate: u16,
+    /// `None` if the sensor can't detect skin contact
+    pub contact: Option<bool>,
+    /// Kilojoules since the last reset
+    pub energy_expended: Option<u16>,
+    /// Milliseconds, oldest first
+    pub rr_intervals: Vec<f32>,
+}
 
-    type HRMDevice;
-    #[wasm_bindgen(constructor)]
-    fn new(on_heartrate: &Closure<dyn FnMut(&JsValue)>, on_state_change: &Closure<dyn FnMut(&JsValue)>) -> HRMDevice;
+impl HeartRateMeasurement {
+    pub fn parse(data: &[u8]) -> Option<HeartRateMeasurement> {
+        let mut r = ByteReader::new(data);
+        let flags = r.u8()? as u32;
+        let heart_rate = if bit_test(flags, HEART_RATE_16_BIT) { r.u16()? } else { r.u8()? as u16 };
+        let contact = if bit_test(flags, SENSOR_CONTACT_SUPPORTED) {
+            Some(bit_test(flags, SENSOR_CONTACT_DETECTED))
+        } else { None };
+        let energy_expended = if bit_test(flags, ENERGY_EXPENDED_PRESENT) {
+            Some(r.u16()?)
+        } else { None };
+        let mut rr_intervals = Vec::new();
+        if bit_test(flags, RR_INTERVALS_PRESENT) {
+            // as many 1/1024 s values as fit into the rest of the packet
+            while r.remaining() >= 2 {
+                rr_intervals.push(r.u16()? as f32 * 1000.0 / 1024.0);
+            }
+        }
+        Some(HeartRateMeasurement {
+            heart_rate,
+            contact,
+            energy_expended,
+            rr_intervals,
+        })
+    }
+}
 
-    #[wasm_bindgen(method)]
-    fn connect(this: &HRMDevice);
+/// Heart Rate (0x180D) profile on top of a connected transport
+#[derive(Clone)]
+pub struct HeartRateProfile {
+    on_hr: Rc<RefCell<dyn FnMut(&HeartRateMeasurement)>>,
 }
 
+impl HeartRateProfile {
+    pub fn new<F: 'static>(on_hr: F) -> HeartRateProfile
+    where F: FnMut(&HeartRateMeasurement) {
+        HeartRateProfile { on_hr: Rc::new(RefCell::new(on_hr)) }
+    }
+
+    pub fn attach(&self, gatt: &dyn GattTransport) {
+        let on_hr = self.on_hr.clone();
+        gatt.subscribe(HEART_RATE_SERVICE, HEART_RATE_MEASUREMENT, Box::new(move |bytes| {
+            if let Some(measurement) = HeartRateMeasurement::parse(bytes) {
+                (on_hr.borrow_mut())(&measurement);
+            }
+        }), optional());
+    }
+}
 
 pub struct HRM {
-    on_heartrate: Closure<dyn FnMut(&JsValue)>,
-    on_state_change: Closure<dyn FnMut(&JsValue)>,
-    hrm: HRMDevice,
+    hrm: BluetoothDevice,
 }
 
 impl HRM {
-    pub fn new<F: 'static, G: 'static>(on_hr : F, on_state: G) -> HRM
-    where F: FnMut(&JsValue), G: FnMut(&JsValue) {
-        let on_heartrate = Closure::new(on_hr);
-        let on_state_change = Closure::new(on_state);
-        let hrm = HRMDevice::new(&on_heartrate, &on_state_change);
-        HRM {
-            on_heartrate,
-            on_state_change,
-            hrm,
-        }
+    pub fn new<F: 'static, D: 'static, G: 'static>(on_hr : F, on_details: D, on_state: G) -> HRM
+    where F: FnMut(&HeartRateMeasurement), D: FnMut(DeviceDetails), G: FnMut(&JsValue) {
+        let profile = HeartRateProfile::new(on_hr);
+        let hrm = BluetoothDevice::new(&[HEART_RATE_SERVICE], &[], move |gatt| profile.attach(gatt), on_details, on_state);
+        HRM { hrm }
     }
 
     pub fn reconnect_hrm(&self) {
         self.hrm.connect();
     }
+
+    pub fn resume(&self, id: Option<String>) {
+        self.hrm.resume(id);
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bluetooth::fake_peripheral::FakePeripheral;
+
+    #[test]
+    fn parses_8_bit_heart_rate() {
+        let m = HeartRateMeasurement::parse(&[0x00, 72]).unwrap();
+        assert_eq!(m, HeartRateMeasurement { heart_rate: 72, contact: None, energy_expended: None, rr_intervals: vec![] });
+        // contact supported but lost
+        assert_eq!(HeartRateMeasurement::parse(&[0x04, 72]).unwrap().contact, Some(false));
+    }
+
+    #[test]
+    fn parses_all_fields() {
+        // 16-bit 300 bpm, contact, 16 kJ, RR 1000 ms and 500 ms
+        let m = HeartRateMeasurement::parse(&[0x1F, 0x2C, 0x01, 0x10, 0x00, 0x00, 0x04, 0x00, 0x02]).unwrap();
+        assert_eq!(m.heart_rate, 300);
+        assert_eq!(m.contact, Some(true));
+        assert_eq!(m.energy_expended, Some(16));
+        assert_eq!(m.rr_intervals, vec![1000.0, 500.0]);
+        // a trailing odd byte is not an interval
+        let m = HeartRateMeasurement::parse(&[0x10, 60, 0x00, 0x04, 0x01]).unwrap();
+        assert_eq!(m.rr_intervals, vec![1000.0]);
+    }
+
+    #[test]
+    fn rejects_truncated_value() {
+        assert_eq!(HeartRateMeasurement::parse(&[]), None);
+        assert_eq!(HeartRateMeasurement::parse(&[0x01, 72]), None);
+        assert_eq!(HeartRateMeasurement::parse(&[0x08, 72, 0x10]), None);
+    }
+
+    #[test]
+    fn notifies_heart_rate_from_peripheral() {
+        let peripheral = FakePeripheral::new().with_characteristic(HEART_RATE_SERVICE, HEART_RATE_MEASUREMENT, &[]);
+        let received = Rc::new(RefCell::new(Vec::new()));
+        let sink = received.clone();
+        HeartRateProfile::new(move |m: &HeartRateMeasurement| sink.borrow_mut().push(m.heart_rate))
+            .attach(&peripheral);
+        peripheral.run();
+        assert!(peripheral.is_subscribed(HEART_RATE_SERVICE, HEART_RATE_MEASUREMENT));
+
+        // 8-bit 72 bpm, then 16-bit 180 bpm with an RR interval
+        peripheral.notify(HEART_RATE_SERVICE, HEART_RATE_MEASUREMENT, &[0x00, 72]);
+        peripheral.notify(HEART_RATE_SERVICE, HEART_RATE_MEASUREMENT, &[0x11, 180, 0x00, 0x55, 0x01]);
+        // truncated packets are dropped
+        peripheral.notify(HEART_RATE_SERVICE, HEART_RATE_MEASUREMENT, &[0x01, 180]);
+        peripheral.run();
+        assert_eq!(*received.borrow(), vec![72, 180]);
+
+        peripheral.disconnect();
+        peripheral.notify(HEART_RATE_SERVICE, HEART_RATE_MEASUREMENT, &[0x00, 90]);
+        peripheral.run();
+        assert_eq!(received.borrow().len(), 2);
+    }
+}
@@ -1 +1,15 @@
-pub mod hrm;
\ No newline at end of file
+pub mod bytes;
+pub mod calibration;
+pub mod csc;
+pub mod device_info;
+#[cfg(test)]
+pub mod fake_peripheral;
+pub mod ftms;
+pub mod gatt;
+pub mod hrm;
+pub mod power;
+pub mod reconnect;
+pub mod registry;
+pub mod remembered;
+pub mod simulated;
+pub mod thermometer;
@@ -0,0 +1,606 @@
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use wasm_bindgen::prelude::*;
+
+use crate::bluetooth::bytes::{bit_test, ByteReader};
+use crate::bluetooth::csc::{CscCalculator, CscMeasurement};
+use crate::bluetooth::device_info::DeviceDetails;
+use crate::bluetooth::ftms::{ControlResponse, IndoorBikeData, MachineStatus, ResultCode, TrainerControl};
+use crate::bluetooth::gatt::{optional, BluetoothDevice, GattTransport, SharedGatt, CSC_MEASUREMENT, CYCLING_POWER_CONTROL_POINT,
+                             CYCLING_POWER_MEASUREMENT, CYCLING_POWER_SERVICE, CYCLING_POWER_VECTOR,
+                             CYCLING_SPEED_AND_CADENCE_SERVICE, FITNESS_MACHINE_CONTROL_POINT, FITNESS_MACHINE_SERVICE,
+                             FITNESS_MACHINE_STATUS, INDOOR_BIKE_DATA};
+
+const PEDAL_POWER_BALANCE_PRESENT: u32 = 0;
+const PEDAL_POWER_BALANCE_REFERENCE: u32 = 1;
+const ACCUMULATED_TORQUE_PRESENT: u32 = 2;
+const ACCUMULATED_TORQUE_SOURCE: u32 = 3;
+const WHEEL_REVOLUTION_DATA_PRESENT: u32 = 4;
+const CRANK_REVOLUTION_DATA_PRESENT: u32 = 5;
+const EXTREME_FORCE_MAGNITUDES_PRESENT: u32 = 6;
+const EXTREME_TORQUE_MAGNITUDES_PRESENT: u32 = 7;
+const EXTREME_ANGLES_PRESENT: u32 = 8;
+const TOP_DEAD_SPOT_ANGLE_PRESENT: u32 = 9;
+const BOTTOM_DEAD_SPOT_ANGLE_PRESENT: u32 = 10;
+const ACCUMULATED_ENERGY_PRESENT: u32 = 11;
+const OFFSET_COMPENSATION_INDICATOR: u32 = 12;
+
+const VECTOR_CRANK_REVOLUTION_DATA_PRESENT: u32 = 0;
+const VECTOR_FIRST_CRANK_ANGLE_PRESENT: u32 = 1;
+const VECTOR_FORCE_ARRAY_PRESENT: u32 = 2;
+const VECTOR_TORQUE_ARRAY_PRESENT: u32 = 3;
+/// Two bits
+const VECTOR_MEASUREMENT_DIRECTION: u32 = 4;
+
+/// Cycling Power Control Point (0x2A66) op codes
+const CP_OP_START_OFFSET_COMPENSATION: u8 = 0x0C;
+const CP_OP_RESPONSE_CODE: u8 = 0x20;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TorqueSource {
+    Wheel,
+    Crank,
+}
+
+/// Cumulative wheel revolutions, event time in 1/2048 s
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct WheelRevolutionData {
+    pub revolutions: u32,
+    pub last_event_time: u16,
+}
+
+/// Cumulative crank revolutions, event time in 1/1024 s
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CrankRevolutionData {
+    pub revolutions: u16,
+    pub last_event_time: u16,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Extremes<T> {
+    pub max: T,
+    pub min: T,
+}
+
+/// Decoded Cycling Power Measurement (0x2A63)
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PowerMeasurement {
+    /// Watts
+    pub instantaneous_power: i16,
+    /// Percent of total power, see `balance_reference_left`
+    pub pedal_power_balance: Option<f32>,
+    /// Balance refers to the left pedal when set, unknown otherwise
+    pub balance_reference_left: bool,
+    /// Newton metres
+    pub accumulated_torque: Option<f32>,
+    pub torque_source: TorqueSource,
+    pub wheel_revolutions: Option<WheelRevolutionData>,
+    pub crank_revolutions: Option<CrankRevolutionData>,
+    /// Newtons
+    pub extreme_force: Option<Extremes<i16>>,
+    /// Newton metres
+    pub extreme_torque: Option<Extremes<f32>>,
+    /// Degrees
+    pub extreme_angles: Option<Extremes<u16>>,
+    /// Degrees
+    pub top_dead_spot_angle: Option<u16>,
+    /// Degrees
+    pub bottom_dead_spot_angle: Option<u16>,
+    /// Kilojoules
+    pub accumulated_energy: Option<u16>,
+    pub offset_compensation: bool,
+}
+
+impl PowerMeasurement {
+    /// Measurement carrying nothing but instantaneous power
+    pub fn from_power(watts: i16) -> PowerMeasurement {
+        PowerMeasurement {
+            instantaneous_power: watts,
+            pedal_power_balance: None,
+            balance_reference_left: false,
+            accumulated_torque: None,
+            torque_source: TorqueSource::Wheel,
+            wheel_revolutions: None,
+            crank_revolutions: None,
+            extreme_force: None,
+            extreme_torque: None,
+            extreme_angles: None,
+            top_dead_spot_angle: None,
+            bottom_dead_spot_angle: None,
+            accumulated_energy: None,
+            offset_compensation: false,
+        }
+    }
+
+    /// Decode characteristic value, `None` if it is shorter than its flags announce
+    pub fn parse(data: &[u8]) -> Option<PowerMeasurement> {
+        let mut r = ByteReader::new(data);
+        let flags = r.u16()? as u32;
+        let instantaneous_power = r.i16()?;
+
+        let pedal_power_balance = if bit_test(flags, PEDAL_POWER_BALANCE_PRESENT) {
+            Some(r.u8()? as f32 / 2.0)
+        } else { None };
+        let accumulated_torque = if bit_test(flags, ACCUMULATED_TORQUE_PRESENT) {
+            Some(r.u16()? as f32 / 32.0)
+        } else { None };
+        let wheel_revolutions = if bit_test(flags, WHEEL_REVOLUTION_DATA_PRESENT) {
+            Some(WheelRevolutionData { revolutions: r.u32()?, last_event_time: r.u16()? })
+        } else { None };
+        let crank_revolutions = if bit_test(flags, CRANK_REVOLUTION_DATA_PRESENT) {
+            Some(CrankRevolutionData { revolutions: r.u16()?, last_event_time: r.u16()? })
+        } else { None };
+        let extreme_force = if bit_test(flags, EXTREME_FORCE_MAGNITUDES_PRESENT) {
+            Some(Extremes { max: r.i16()?, min: r.i16()? })
+        } else { None };
+        let extreme_torque = if bit_test(flags, EXTREME_TORQUE_MAGNITUDES_PRESENT) {
+            Some(Extremes { max: r.i16()? as f32 / 32.0, min: r.i16()? as f32 / 32.0 })
+        } else { None };
+        let extreme_angles = if bit_test(flags, EXTREME_ANGLES_PRESENT) {
+            // two 12-bit values packed into three bytes, maximum first
+            let packed = r.u24()?;
+            Some(Extremes { max: (packed & 0xFFF) as u16, min: (packed >> 12) as u16 })
+        } else { None };
+        let top_dead_spot_angle = if bit_test(flags, TOP_DEAD_SPOT_ANGLE_PRESENT) {
+            Some(r.u16()?)
+        } else { None };
+        let bottom_dead_spot_angle = if bit_test(flags, BOTTOM_DEAD_SPOT_ANGLE_PRESENT) {
+            Some(r.u16()?)
+        } else { None };
+        let accumulated_energy = if bit_test(flags, ACCUMULATED_ENERGY_PRESENT) {
+            Some(r.u16()?)
+        } else { None };
+
+        Some(PowerMeasurement {
+            instantaneous_power,
+            pedal_power_balance,
+            balance_reference_left: bit_test(flags, PEDAL_POWER_BALANCE_REFERENCE),
+            accumulated_torque,
+            torque_source: if bit_test(flags, ACCUMULATED_TORQUE_SOURCE) { TorqueSource::Crank } else { TorqueSource::Wheel },
+            wheel_revolutions,
+            crank_revolutions,
+            extreme_force,
+            extreme_torque,
+            extreme_angles,
+            top_dead_spot_angle,
+            bottom_dead_spot_angle,
+            accumulated_energy,
+            offset_compensation: bit_test(flags, OFFSET_COMPENSATION_INDICATOR),
+        })
+    }
+}
+
+/// Direction of the instantaneous measurements in a power vector
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MeasurementDirection {
+    Unknown,
+    Tangential,
+    Radial,
+    Lateral,
+}
+
+/// Decoded Cycling Power Vector (0x2A64)
+#[derive(Clone, Debug, PartialEq)]
+pub struct PowerVector {
+    pub crank_revolutions: Option<CrankRevolutionData>,
+    /// Degrees, angle of the first element of the arrays
+    pub first_crank_angle: Option<u16>,
+    /// Newtons, equally spaced from the first crank angle
+    pub forces: Vec<i16>,
+    /// Newton metres, equally spaced from the first crank angle
+    pub torques: Vec<f32>,
+    pub direction: MeasurementDirection,
+}
+
+impl PowerVector {
+    pub fn parse(data: &[u8]) -> Option<PowerVector> {
+        let mut r = ByteReader::new(data);
+        let flags = r.u8()? as u32;
+        let crank_revolutions = if bit_test(flags, VECTOR_CRANK_REVOLUTION_DATA_PRESENT) {
+            Some(CrankRevolutionData { revolutions: r.u16()?, last_event_time: r.u16()? })
+        } else { None };
+        let first_crank_angle = if bit_test(flags, VECTOR_FIRST_CRANK_ANGLE_PRESENT) {
+            Some(r.u16()?)
+        } else { None };
+        // the array takes the rest of the value, only one of them can be present
+        let mut forces = Vec::new();
+        let mut torques = Vec::new();
+        if bit_test(flags, VECTOR_FORCE_ARRAY_PRESENT) {
+            while r.remaining() >= 2 {
+                forces.push(r.i16()?);
+            }
+        } else if bit_test(flags, VECTOR_TORQUE_ARRAY_PRESENT) {
+            while r.remaining() >= 2 {
+                torques.push(r.i16()? as f32 / 32.0);
+            }
+        }
+        let direction = match (flags >> VECTOR_MEASUREMENT_DIRECTION) & 0x03 {
+            1 => MeasurementDirection::Tangential,
+            2 => MeasurementDirection::Radial,
+            3 => MeasurementDirection::Lateral,
+            _ => MeasurementDirection::Unknown,
+        };
+        Some(PowerVector { crank_revolutions, first_crank_angle, forces, torques, direction })
+    }
+}
+
+/// Indication of the Cycling Power Control Point
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PowerControlResponse {
+    pub request_op_code: u8,
+    pub result: ResultCode,
+    /// Raw force or torque offset in reply to offset compensation, sensor specific units
+    pub offset: Option<i16>,
+}
+
+impl PowerControlResponse {
+    pub fn parse(data: &[u8]) -> Option<PowerControlResponse> {
+        let mut r = ByteReader::new(data);
+        if r.u8()? != CP_OP_RESPONSE_CODE {
+            return None;
+        }
+        let request_op_code = r.u8()?;
+        let result = ResultCode::from(r.u8()?);
+        let offset = if request_op_code == CP_OP_START_OFFSET_COMPENSATION && result == ResultCode::Success {
+            r.i16()
+        } else { None };
+        Some(PowerControlResponse { request_op_code, result, offset })
+    }
+
+    pub fn is_offset_compensation(&self) -> bool {
+        self.request_op_code == CP_OP_START_OFFSET_COMPENSATION
+    }
+}
+
+/// Decoded notification of a power meter or smart trainer
+#[derive(Clone, Debug)]
+pub enum TrainerEvent {
+    Power(PowerMeasurement),
+    Vector(PowerVector),
+    /// Fitness machine data, power and speed are left out once the trainer
+    /// reports them through the cycling power and CSC services
+    IndoorBike(IndoorBikeData),
+    /// m/s
+    Speed(f32),
+    /// rpm
+    Cadence(f32),
+    Control(ControlResponse),
+    PowerControl(PowerControlResponse),
+    Status(MachineStatus),
+}
+
+/// Cycling power, fitness machine and CSC profiles of a power meter or trainer on
+/// top of a connected transport, each used if the device has the service
+#[derive(Clone)]
+pub struct PowerProfile {
+    on_event: Rc<RefCell<dyn FnMut(TrainerEvent)>>,
+    csc: Rc<RefCell<CscCalculator>>,
+    power_measured: Rc<Cell<bool>>,
+    /// Speed and cadence seen in CSC measurements
+    csc_measured: Rc<Cell<(bool, bool)>>,
+}
+
+impl PowerProfile {
+    pub fn new<F: 'static>(csc: Rc<RefCell<CscCalculator>>, on_event: F) -> PowerProfile
+    where F: FnMut(TrainerEvent) {
+        PowerProfile {
+            on_event: Rc::new(RefCell::new(on_event)),
+            csc,
+            power_measured: Rc::new(Cell::new(false)),
+            csc_measured: Rc::new(Cell::new((false, false))),
+        }
+    }
+
+    pub fn attach(&self, gatt: &dyn GattTransport) {
+        let profile = self.clone();
+        gatt.subscribe(CYCLING_POWER_SERVICE, CYCLING_POWER_MEASUREMENT, Box::new(move |bytes| profile.on_power(bytes)), optional());
+        let profile = self.clone();
+        gatt.subscribe(CYCLING_POWER_SERVICE, CYCLING_POWER_VECTOR, Box::new(move |bytes| {
+            if let Some(vector) = PowerVector::parse(bytes) {
+                profile.emit(TrainerEvent::Vector(vector));
+            }
+        }), optional());
+        let profile = self.clone();
+        gatt.subscribe(CYCLING_SPEED_AND_CADENCE_SERVICE, CSC_MEASUREMENT, Box::new(move |bytes| profile.on_csc(bytes)), optional());
+        let profile = self.clone();
+        gatt.subscribe(FITNESS_MACHINE_SERVICE, INDOOR_BIKE_DATA, Box::new(move |bytes| profile.on_indoor_bike(bytes)), optional());
+        // both control points indicate through here, told apart by the response op code
+        for &(service, control_point) in &[(CYCLING_POWER_SERVICE, CYCLING_POWER_CONTROL_POINT),
+                                           (FITNESS_MACHINE_SERVICE, FITNESS_MACHINE_CONTROL_POINT)] {
+            let profile = self.clone();
+            gatt.subscribe(service, control_point, Box::new(move |bytes| profile.on_control_response(bytes)), optional());
+        }
+        let profile = self.clone();
+        gatt.subscribe(FITNESS_MACHINE_SERVICE, FITNESS_MACHINE_STATUS, Box::new(move |bytes| {
+            if let Some(status) = MachineStatus::parse(bytes) {
+                profile.emit(TrainerEvent::Status(status));
+            }
+        }), optional());
+    }
+
+    fn emit(&self, event: TrainerEvent) {
+        (self.on_event.borrow_mut())(event);
+    }
+
+    fn on_power(&self, bytes: &[u8]) {
+        if let Some(measurement) = PowerMeasurement::parse(bytes) {
+            self.power_measured.set(true);
+            self.emit(TrainerEvent::Power(measurement));
+        }
+    }
+
+    fn on_csc(&self, bytes: &[u8]) {
+        if let Some(measurement) = CscMeasurement::parse(bytes) {
+            let (speed, cadence) = self.csc.borrow_mut().update(&measurement);
+            let (speed_measured, cadence_measured) = self.csc_measured.get();
+            self.csc_measured.set((speed_measured || speed.is_some(), cadence_measured || cadence.is_some()));
+            if let Some(speed) = speed {
+                self.emit(TrainerEvent::Speed(speed));
+            }
+            if let Some(cadence) = cadence {
+                self.emit(TrainerEvent::Cadence(cadence));
+            }
+        }
+    }
+
+    fn on_indoor_bike(&self, bytes: &[u8]) {
+        if let Some(mut data) = IndoorBikeData::parse(bytes) {
+            if self.power_measured.get() {
+                data.instantaneous_power = None;
+            }
+            let (speed_measured, cadence_measured) = self.csc_measured.get();
+            if speed_measured {
+                data.instantaneous_speed = None;
+            }
+            if cadence_measured {
+                data.instantaneous_cadence = None;
+            }
+            self.emit(TrainerEvent::IndoorBike(data));
+        }
+    }
+
+    fn on_control_response(&self, bytes: &[u8]) {
+        if let Some(response) = ControlResponse::parse(bytes) {
+            self.emit(TrainerEvent::Control(response));
+        } else if let Some(response) = PowerControlResponse::parse(bytes) {
+            self.emit(TrainerEvent::PowerControl(response));
+        }
+    }
+}
+
+/// Start offset compensation through the cycling power control point
+pub fn start_offset_compensation(gatt: &dyn GattTransport) {
+    gatt.write(CYCLING_POWER_SERVICE, CYCLING_POWER_CONTROL_POINT, &[CP_OP_START_OFFSET_COMPENSATION], optional());
+}
+
+/// Route control point writes of `control` to the fitness machine behind `gatt`
+pub fn attach_control(gatt: SharedGatt, control: &TrainerControl) {
+    control.set_writer(Some(Box::new(move |data| {
+        if let Some(gatt) = gatt.borrow().as_ref() {
+            gatt.write(FITNESS_MACHINE_SERVICE, FITNESS_MACHINE_CONTROL_POINT, data, optional());
+        }
+    })));
+}
+
+pub struct PowerMeter {
+    csc: Rc<RefCell<CscCalculator>>,
+    trainer: BluetoothDevice,
+}
+
+impl PowerMeter {
+    /// `trainer` looks for a fitness machine instead of a cycling power sensor when scanning
+    pub fn new<F: 'static, D: 'static, G: 'static>(trainer: bool, on_event: F, on_details: D, on_state: G) -> PowerMeter
+    where F: FnMut(TrainerEvent), D: FnMut(DeviceDetails), G: FnMut(&JsValue) {
+        let csc = Rc::new(RefCell::new(CscCalculator::default()));
+        let profile = PowerProfile::new(csc.clone(), on_event);
+        let (primary, optional) = if trainer {
+            (FITNESS_MACHINE_SERVICE, [CYCLING_POWER_SERVICE, CYCLING_SPEED_AND_CADENCE_SERVICE])
+        } else {
+            (CYCLING_POWER_SERVICE, [FITNESS_MACHINE_SERVICE, CYCLING_SPEED_AND_CADENCE_SERVICE])
+        };
+        let trainer = BluetoothDevice::new(&[primary], &optional, move |gatt| profile.attach(gatt), on_details, on_state);
+        PowerMeter {
+            csc,
+            trainer,
+        }
+    }
+
+    pub fn reconnect(&self) {
+        self.trainer.connect();
+    }
+
+    pub fn resume(&self, id: Option<String>) {
+        self.trainer.resume(id);
+    }
+
+    /// Metres, used to turn CSC wheel revolutions into speed
+    pub fn set_wheel_circumference(&self, wheel_circumference: f32) {
+        self.csc.borrow_mut().set_wheel_circumference(wheel_circumference);
+    }
+
+    /// Ask the power meter to zero its offset, cranks must be unloaded.
+    /// The result comes back as `TrainerEvent::PowerControl`
+    pub fn start_offset_compensation(&self) {
+        if let Some(gatt) = self.trainer.server().borrow().as_ref() {
+            start_offset_compensation(gatt.as_ref());
+        }
+    }
+
+    /// Route control point writes of `control` to this trainer
+    pub fn attach_control(&self, control: &TrainerControl) {
+        attach_control(self.trainer.server(), control);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bluetooth::fake_peripheral::FakePeripheral;
+
+    /// Flags only, 215 W
+    const POWER_ONLY: [u8; 4] = [0x00, 0x00, 0xD7, 0x00];
+    /// Left referenced balance 51 %, crank revs 4660 at 0x3A00
+    const BALANCE_AND_CRANK: [u8; 9] = [0x23, 0x00, 0x2C, 0x01, 0x66, 0x34, 0x12, 0x00, 0x3A];
+    /// Crank based torque, wheel data, energy 310 kJ and offset compensation
+    const TORQUE_WHEEL_ENERGY: [u8; 14] = [
+        0x1C, 0x18, 0xFA, 0x00, 0x40, 0x06, 0x10, 0x27, 0x00, 0x00, 0x00, 0x80, 0x36, 0x01,
+    ];
+    /// Force and torque extremes, angles 45..200, dead spots 10 and 190
+    const EXTREMES_AND_ANGLES: [u8; 19] = [
+        0xC0, 0x07, 0x96, 0x00, 0xF4, 0x01, 0xCE, 0xFF, 0x80, 0x02, 0xE0, 0xFF, 0x2D, 0x80, 0x0C, 0x0A,
+        0x00, 0xBE, 0x00,
+    ];
+    /// Tangential forces 100 and -100 N from 90 degrees, crank revs 16
+    const FORCE_VECTOR: [u8; 11] = [0x17, 0x10, 0x00, 0x00, 0x04, 0x5A, 0x00, 0x64, 0x00, 0x9C, 0xFF];
+
+    #[test]
+    fn parses_instantaneous_power_only() {
+        let m = PowerMeasurement::parse(&POWER_ONLY).unwrap();
+        assert_eq!(m.instantaneous_power, 215);
+        assert_eq!(m.pedal_power_balance, None);
+        assert_eq!(m.crank_revolutions, None);
+        assert_eq!(m.torque_source, TorqueSource::Wheel);
+        assert!(!m.offset_compensation);
+    }
+
+    #[test]
+    fn parses_balance_and_crank_data() {
+        let m = PowerMeasurement::parse(&BALANCE_AND_CRANK).unwrap();
+        assert_eq!(m.instantaneous_power, 300);
+        assert_eq!(m.pedal_power_balance, Some(51.0));
+        assert!(m.balance_reference_left);
+        assert_eq!(m.crank_revolutions, Some(CrankRevolutionData { revolutions: 0x1234, last_event_time: 0x3A00 }));
+    }
+
+    #[test]
+    fn parses_torque_wheel_and_energy() {
+        let m = PowerMeasurement::parse(&TORQUE_WHEEL_ENERGY).unwrap();
+        assert_eq!(m.instantaneous_power, 250);
+        assert_eq!(m.accumulated_torque, Some(50.0));
+        assert_eq!(m.torque_source, TorqueSource::Crank);
+        assert_eq!(m.wheel_revolutions, Some(WheelRevolutionData { revolutions: 10000, last_event_time: 0x8000 }));
+        assert_eq!(m.accumulated_energy, Some(310));
+        assert!(m.offset_compensation);
+    }
+
+    #[test]
+    fn parses_extremes_and_angles() {
+        let m = PowerMeasurement::parse(&EXTREMES_AND_ANGLES).unwrap();
+        assert_eq!(m.instantaneous_power, 150);
+        assert_eq!(m.extreme_force, Some(Extremes { max: 500, min: -50 }));
+        assert_eq!(m.extreme_torque, Some(Extremes { max: 20.0, min: -1.0 }));
+        assert_eq!(m.extreme_angles, Some(Extremes { max: 45, min: 200 }));
+        assert_eq!(m.top_dead_spot_angle, Some(10));
+        assert_eq!(m.bottom_dead_spot_angle, Some(190));
+    }
+
+    #[test]
+    fn rejects_truncated_value() {
+        assert_eq!(PowerMeasurement::parse(&BALANCE_AND_CRANK[..7]), None);
+        assert_eq!(PowerMeasurement::parse(&[0x00]), None);
+    }
+
+    #[test]
+    fn parses_force_vector() {
+        let v = PowerVector::parse(&FORCE_VECTOR).unwrap();
+        assert_eq!(v.crank_revolutions, Some(CrankRevolutionData { revolutions: 16, last_event_time: 0x0400 }));
+        assert_eq!(v.first_crank_angle, Some(90));
+        assert_eq!(v.forces, vec![100, -100]);
+        assert!(v.torques.is_empty());
+        assert_eq!(v.direction, MeasurementDirection::Tangential);
+    }
+
+    #[test]
+    fn parses_torque_vector() {
+        let v = PowerVector::parse(&[0x08, 0x40, 0x00, 0xF0, 0xFF]).unwrap();
+        assert_eq!(v.torques, vec![2.0, -0.5]);
+        assert_eq!(v.direction, MeasurementDirection::Unknown);
+    }
+
+    fn recorder() -> (Rc<RefCell<Vec<TrainerEvent>>>, impl FnMut(TrainerEvent)) {
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let sink = events.clone();
+        (events, move |event| sink.borrow_mut().push(event))
+    }
+
+    #[test]
+    fn power_meter_over_gatt() {
+        let peripheral = FakePeripheral::new()
+            .with_characteristic(CYCLING_POWER_SERVICE, CYCLING_POWER_MEASUREMENT, &[])
+            .with_characteristic(CYCLING_POWER_SERVICE, CYCLING_POWER_VECTOR, &[])
+            .with_characteristic(CYCLING_POWER_SERVICE, CYCLING_POWER_CONTROL_POINT, &[]);
+        // offset of 16 Nm / 32
+        peripheral.on_write(CYCLING_POWER_SERVICE, CYCLING_POWER_CONTROL_POINT, |data| {
+            Some(vec![CP_OP_RESPONSE_CODE, data[0], 0x01, 0x10, 0x00])
+        });
+        let (events, on_event) = recorder();
+        PowerProfile::new(Rc::new(RefCell::new(CscCalculator::default())), on_event).attach(&peripheral);
+        peripheral.run();
+
+        peripheral.notify(CYCLING_POWER_SERVICE, CYCLING_POWER_MEASUREMENT, &POWER_ONLY);
+        peripheral.notify(CYCLING_POWER_SERVICE, CYCLING_POWER_VECTOR, &FORCE_VECTOR);
+        start_offset_compensation(&peripheral);
+        peripheral.run();
+
+        assert_eq!(peripheral.written(CYCLING_POWER_SERVICE, CYCLING_POWER_CONTROL_POINT), vec![vec![CP_OP_START_OFFSET_COMPENSATION]]);
+        let events = events.borrow();
+        assert_eq!(events.len(), 3);
+        assert!(matches!(&events[0], TrainerEvent::Power(m) if m.instantaneous_power == 215));
+        assert!(matches!(&events[1], TrainerEvent::Vector(v) if v.forces == vec![100, -100]));
+        assert!(matches!(&events[2], TrainerEvent::PowerControl(r) if r.is_offset_compensation() && r.offset == Some(16)));
+    }
+
+    #[test]
+    fn trainer_over_gatt() {
+        let peripheral = FakePeripheral::new()
+            .with_characteristic(FITNESS_MACHINE_SERVICE, INDOOR_BIKE_DATA, &[])
+            .with_characteristic(FITNESS_MACHINE_SERVICE, FITNESS_MACHINE_CONTROL_POINT, &[])
+            .with_characteristic(FITNESS_MACHINE_SERVICE, FITNESS_MACHINE_STATUS, &[])
+            .with_characteristic(CYCLING_POWER_SERVICE, CYCLING_POWER_MEASUREMENT, &[])
+            .with_characteristic(CYCLING_SPEED_AND_CADENCE_SERVICE, CSC_MEASUREMENT, &[]);
+        // every procedure succeeds
+        peripheral.on_write(FITNESS_MACHINE_SERVICE, FITNESS_MACHINE_CONTROL_POINT, |data| Some(vec![0x80, data[0], 0x01]));
+
+        let control = TrainerControl::new();
+        let (events, mut record) = recorder();
+        let responder = control.clone();
+        let profile = PowerProfile::new(Rc::new(RefCell::new(CscCalculator::default())), move |event| {
+            if let TrainerEvent::Control(response) = &event {
+                responder.handle_response(response);
+            }
+            record(event);
+        });
+        profile.attach(&peripheral);
+        let gatt: Rc<dyn GattTransport> = Rc::new(peripheral.clone());
+        attach_control(Rc::new(RefCell::new(Some(gatt))), &control);
+        peripheral.run();
+        assert!(peripheral.is_subscribed(FITNESS_MACHINE_SERVICE, FITNESS_MACHINE_CONTROL_POINT));
+
+        control.set_target_power(200);
+        peripheral.run();
+        assert_eq!(peripheral.written(FITNESS_MACHINE_SERVICE, FITNESS_MACHINE_CONTROL_POINT),
+                   vec![vec![0x00], vec![0x05, 0xC8, 0x00]]);
+        assert!(control.has_control());
+        assert_eq!(control.target_power(), Some(200));
+
+        // power from the cycling power service wins over indoor bike data
+        events.borrow_mut().clear();
+        peripheral.notify(CYCLING_POWER_SERVICE, CYCLING_POWER_MEASUREMENT, &POWER_ONLY);
+        // 36 km/h, 90 rpm, 220 W
+        peripheral.notify(FITNESS_MACHINE_SERVICE, INDOOR_BIKE_DATA, &[0x44, 0x00, 0x10, 0x0E, 0xB4, 0x00, 0xDC, 0x00]);
+        peripheral.notify(FITNESS_MACHINE_SERVICE, FITNESS_MACHINE_STATUS, &[0x14, 0x01]);
+        peripheral.run();
+        let events = events.borrow();
+        assert_eq!(events.len(), 3);
+        match &events[1] {
+            TrainerEvent::IndoorBike(data) => {
+                assert_eq!(data.instantaneous_power, None);
+                assert_eq!(data.instantaneous_speed, Some(10.0));
+                assert_eq!(data.instantaneous_cadence, Some(90.0));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(matches!(&events[2], TrainerEvent::Status(status) if Some(*status) == MachineStatus::parse(&[0x14, 0x01])));
+    }
+}
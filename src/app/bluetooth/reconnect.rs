@@ -0,0 +1,287 @@
+use crate::bluetooth::registry::ConnectionState;
+
+/// Give up on an attempt the device never answered, ms
+const ATTEMPT_TIMEOUT: f32 = 15000.0;
+
+/// Connection progress reported by the JS device
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LinkEvent {
+    /// Device chosen or re-acquired, GATT connection in progress
+    Connecting,
+    Connected,
+    /// Connection dropped
+    Disconnected,
+    /// Pairing or a connection attempt failed
+    Failed,
+}
+
+impl LinkEvent {
+    pub fn from_js(state: &str) -> Option<LinkEvent> {
+        match state {
+            "connecting" => Some(LinkEvent::Connecting),
+            "connected" => Some(LinkEvent::Connected),
+            "disconnected" => Some(LinkEvent::Disconnected),
+            "failed" => Some(LinkEvent::Failed),
+            _ => None,
+        }
+    }
+}
+
+/// Delays between reconnection attempts, ms
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ReconnectPolicy {
+    pub initial_delay: f32,
+    pub max_delay: f32,
+    pub factor: f32,
+    /// Failed attempts in a row before the device is reported lost
+    pub max_attempts: u32,
+}
+
+impl ReconnectPolicy {
+    /// Delay before attempt number `attempt`, the first one starts right away
+    pub fn delay(&self, attempt: u32) -> f32 {
+        if attempt == 0 {
+            return 0.0;
+        }
+        (self.initial_delay * self.factor.powi(attempt as i32 - 1)).min(self.max_delay)
+    }
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            initial_delay: 1000.0,
+            max_delay: 30000.0,
+            factor: 2.0,
+            max_attempts: 6,
+        }
+    }
+}
+
+/// Connection state machine of one device. Dropped connections are retried
+/// with exponential backoff until `max_attempts` fail in a row.
+pub struct Reconnector {
+    policy: ReconnectPolicy,
+    state: ConnectionState,
+    /// Failed attempts since the connection dropped
+    attempts: u32,
+    /// Time left before the next attempt
+    wait: Option<f32>,
+    /// Time spent in the attempt in progress
+    attempt: Option<f32>,
+}
+
+impl Reconnector {
+    pub fn new(policy: ReconnectPolicy) -> Reconnector {
+        Reconnector {
+            policy,
+            state: ConnectionState::Disconnected,
+            attempts: 0,
+            wait: None,
+            attempt: None,
+        }
+    }
+
+    pub fn state(&self) -> ConnectionState {
+        self.state
+    }
+
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    /// User asked to choose a device
+    pub fn scan(&mut self) -> Option<ConnectionState> {
+        self.wait = None;
+        self.attempt = None;
+        self.attempts = 0;
+        self.set_state(ConnectionState::Scanning)
+    }
+
+    /// Re-acquire a remembered device without asking the user
+    pub fn restore(&mut self) -> Option<ConnectionState> {
+        self.attempts = 0;
+        self.attempt = None;
+        self.wait = Some(0.0);
+        self.set_state(ConnectionState::Reconnecting)
+    }
+
+    /// Feed a device report, returns the new state if it changed
+    pub fn on_link(&mut self, event: LinkEvent) -> Option<ConnectionState> {
+        match event {
+            LinkEvent::Connecting => {
+                if self.state == ConnectionState::Reconnecting {
+                    return None;
+                }
+                // a chosen device that never answers is retried like a dropped one
+                self.attempt = Some(0.0);
+                self.set_state(ConnectionState::Connecting)
+            }
+            LinkEvent::Connected => {
+                self.attempts = 0;
+                self.wait = None;
+                self.attempt = None;
+                self.set_state(ConnectionState::Connected)
+            }
+            LinkEvent::Disconnected => {
+                if self.state != ConnectionState::Connected {
+                    return None;
+                }
+                self.restore()
+            }
+            LinkEvent::Failed => self.attempt_failed(),
+        }
+    }
+
+    /// Advance by `dt` ms. Returns whether the next reconnection attempt should start now,
+    /// and the new state if the attempt in progress timed out
+    pub fn tick(&mut self, dt: f32) -> (bool, Option<ConnectionState>) {
+        if let Some(elapsed) = self.attempt.as_mut() {
+            *elapsed += dt;
+            if *elapsed >= ATTEMPT_TIMEOUT {
+                return (false, self.attempt_failed());
+            }
+            return (false, None);
+        }
+        match self.wait.as_mut() {
+            Some(wait) => {
+                *wait -= dt;
+                if *wait > 0.0 {
+                    return (false, None);
+                }
+                self.wait = None;
+                self.attempt = Some(0.0);
+                (true, None)
+            }
+            None => (false, None),
+        }
+    }
+
+    fn attempt_failed(&mut self) -> Option<ConnectionState> {
+        self.attempt = None;
+        match self.state {
+            // chooser was cancelled or nothing was found
+            ConnectionState::Scanning => self.set_state(ConnectionState::Disconnected),
+            ConnectionState::Connecting | ConnectionState::Reconnecting => {
+                self.attempts += 1;
+                if self.attempts >= self.policy.max_attempts {
+                    self.wait = None;
+                    return self.set_state(ConnectionState::Lost);
+                }
+                self.wait = Some(self.policy.delay(self.attempts));
+                self.set_state(ConnectionState::Reconnecting)
+                    // report every failed attempt so the UI can show progress
+                    .or(Some(ConnectionState::Reconnecting))
+            }
+            _ => None,
+        }
+    }
+
+    fn set_state(&mut self, state: ConnectionState) -> Option<ConnectionState> {
+        if self.state == state {
+            return None;
+        }
+        self.state = state;
+        Some(state)
+    }
+}
+
+impl Default for Reconnector {
+    fn default() -> Self {
+        Reconnector::new(ReconnectPolicy::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bluetooth::registry::ConnectionState::*;
+
+    /// ms until `tick` asks for the next attempt
+    fn wait_for_attempt(link: &mut Reconnector) -> f32 {
+        let (mut waited, mut dt) = (0.0, 0.0);
+        loop {
+            let (attempt, changed) = link.tick(dt);
+            assert_eq!(changed, None);
+            waited += dt;
+            if attempt {
+                return waited;
+            }
+            dt = 100.0;
+        }
+    }
+
+    #[test]
+    fn doubles_delay_up_to_the_cap() {
+        let policy = ReconnectPolicy::default();
+        let delays: Vec<f32> = (0..9).map(|attempt| policy.delay(attempt)).collect();
+        assert_eq!(delays, vec![0.0, 1000.0, 2000.0, 4000.0, 8000.0, 16000.0, 30000.0, 30000.0, 30000.0]);
+    }
+
+    #[test]
+    fn reconnects_with_backoff_until_lost() {
+        let mut link = Reconnector::default();
+        assert_eq!(link.scan(), Some(Scanning));
+        assert_eq!(link.on_link(LinkEvent::Connecting), Some(Connecting));
+        assert_eq!(link.on_link(LinkEvent::Connected), Some(Connected));
+        assert_eq!(link.on_link(LinkEvent::Disconnected), Some(Reconnecting));
+        // the first attempt starts right away
+        assert_eq!(wait_for_attempt(&mut link), 0.0);
+        assert_eq!(link.on_link(LinkEvent::Connecting), None);
+
+        let mut waits = Vec::new();
+        for attempts in 1..ReconnectPolicy::default().max_attempts {
+            assert_eq!(link.on_link(LinkEvent::Failed), Some(Reconnecting));
+            assert_eq!(link.attempts(), attempts);
+            waits.push(wait_for_attempt(&mut link));
+        }
+        assert_eq!(waits, vec![1000.0, 2000.0, 4000.0, 8000.0, 16000.0]);
+        assert_eq!(link.on_link(LinkEvent::Failed), Some(Lost));
+        assert_eq!(link.tick(100_000.0), (false, None));
+        assert_eq!(link.state(), Lost);
+    }
+
+    #[test]
+    fn reconnected_device_starts_over() {
+        let mut link = Reconnector::default();
+        assert_eq!(link.restore(), Some(Reconnecting));
+        wait_for_attempt(&mut link);
+        link.on_link(LinkEvent::Failed);
+        wait_for_attempt(&mut link);
+        assert_eq!(link.on_link(LinkEvent::Connected), Some(Connected));
+        assert_eq!(link.attempts(), 0);
+        link.on_link(LinkEvent::Disconnected);
+        assert_eq!(wait_for_attempt(&mut link), 0.0);
+        // only a connected device can drop
+        let mut link = Reconnector::default();
+        assert_eq!(link.on_link(LinkEvent::Disconnected), None);
+        assert_eq!(link.state(), Disconnected);
+    }
+
+    #[test]
+    fn times_out_unanswered_attempts() {
+        let mut link = Reconnector::default();
+        link.restore();
+        assert_eq!(link.tick(0.0), (true, None));
+        assert_eq!(link.tick(ATTEMPT_TIMEOUT - 1.0), (false, None));
+        assert_eq!(link.tick(1.0), (false, Some(Reconnecting)));
+        assert_eq!(link.attempts(), 1);
+        assert_eq!(wait_for_attempt(&mut link), 1000.0);
+
+        // so does connecting to a freshly chosen device
+        let mut link = Reconnector::default();
+        link.scan();
+        link.on_link(LinkEvent::Connecting);
+        assert_eq!(link.tick(ATTEMPT_TIMEOUT), (false, Some(Reconnecting)));
+        assert_eq!(wait_for_attempt(&mut link), 1000.0);
+    }
+
+    #[test]
+    fn cancelled_chooser_disconnects() {
+        let mut link = Reconnector::default();
+        link.scan();
+        assert_eq!(link.on_link(LinkEvent::Failed), Some(Disconnected));
+        assert_eq!(link.attempts(), 0);
+        assert_eq!(link.tick(100_000.0), (false, None));
+    }
+}
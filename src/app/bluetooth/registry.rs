@@ -0,0 +1,847 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use js_sys::Date;
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::JsValue;
+use web_sys::console;
+
+use crate::ant::muscle_oxygen::MuscleOxygen;
+use crate::ant::node::{parse_network_key, AntEvent, AntNode, AntProfile};
+use crate::ant::transport::WebSerialTransport;
+use crate::bluetooth::calibration::{Calibration, CalibrationCommand, CalibrationKind, CalibrationStep};
+use crate::bluetooth::csc::{CscSensor, DEFAULT_WHEEL_CIRCUMFERENCE};
+use crate::bluetooth::device_info::{is_low_battery, DeviceDetails, DeviceInformation};
+use crate::bluetooth::ftms::{ControlResponse, IndoorBikeData, MachineStatus, TrainerControl};
+use crate::bluetooth::hrm::{HeartRateMeasurement, HRM};
+use crate::bluetooth::power::{PowerControlResponse, PowerMeasurement, PowerMeter, PowerVector, TrainerEvent};
+use crate::bluetooth::reconnect::{LinkEvent, Reconnector};
+use crate::bluetooth::remembered::{DeviceMemory, RememberedDevice};
+use crate::bluetooth::simulated::SimulatedSensor;
+use crate::bluetooth::thermometer::{TemperatureMeasurement, Thermometer};
+use crate::components::UserEvent;
+use crate::quality::{Channel, Quality};
+use crate::rider::Rider;
+use crate::timedata::RecordField;
+use crate::Store;
+
+const SIMULATED_NAME: &str = "Simulated";
+const SIMULATED_ROLES: [SensorRole; 3] = [SensorRole::HeartRate, SensorRole::Power, SensorRole::SpeedCadence];
+const ANT_NAME: &str = "ANT+";
+const ANT_ROLES: [(SensorRole, AntProfile); 3] = [
+    (SensorRole::HeartRate, AntProfile::HeartRate),
+    (SensorRole::Trainer, AntProfile::FitnessEquipment),
+    (SensorRole::MuscleOxygen, AntProfile::MuscleOxygen),
+];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SensorRole {
+    HeartRate,
+    Power,
+    SpeedCadence,
+    Trainer,
+    Thermometer,
+    MuscleOxygen,
+}
+
+impl SensorRole {
+    pub const ALL: [SensorRole; 6] = [
+        SensorRole::HeartRate, SensorRole::Power, SensorRole::SpeedCadence, SensorRole::Trainer,
+        SensorRole::Thermometer, SensorRole::MuscleOxygen,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            SensorRole::HeartRate => "HR",
+            SensorRole::Power => "Power",
+            SensorRole::SpeedCadence => "Speed/Cadence",
+            SensorRole::Trainer => "Trainer",
+            SensorRole::Thermometer => "Temperature",
+            SensorRole::MuscleOxygen => "SmO2",
+        }
+    }
+
+    /// Only served by ANT+ sensors, there is no Bluetooth profile for it
+    fn ant_only(&self) -> bool {
+        *self == SensorRole::MuscleOxygen
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ConnectionState {
+    Disconnected,
+    Scanning,
+    Connecting,
+    Connected,
+    Reconnecting,
+    Lost,
+}
+
+impl ConnectionState {
+    pub fn label(&self) -> &'static str {
+        match self {
+            ConnectionState::Disconnected => "not connected",
+            ConnectionState::Scanning => "scanning",
+            ConnectionState::Connecting => "connecting",
+            ConnectionState::Connected => "connected",
+            ConnectionState::Reconnecting => "reconnecting",
+            ConnectionState::Lost => "lost",
+        }
+    }
+}
+
+/// What is known about the device currently serving a role
+#[derive(Clone, Debug)]
+pub struct SensorInfo {
+    pub state: ConnectionState,
+    /// Failed attempts since the connection dropped
+    pub reconnect_attempts: u32,
+    /// `BluetoothDevice.id`
+    pub id: Option<String>,
+    pub name: Option<String>,
+    /// Percent
+    pub battery: Option<u8>,
+    pub device: DeviceInformation,
+}
+
+impl Default for SensorInfo {
+    fn default() -> Self {
+        SensorInfo {
+            state: ConnectionState::Disconnected,
+            reconnect_attempts: 0,
+            id: None,
+            name: None,
+            battery: None,
+            device: DeviceInformation::default(),
+        }
+    }
+}
+
+/// `sensorState` object reported by `BLEDevice`
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct SensorStatus {
+    state: Option<String>,
+    id: Option<String>,
+    name: Option<String>,
+}
+
+enum Device {
+    HeartRate(HRM),
+    Power(PowerMeter),
+    SpeedCadence(CscSensor),
+    Thermometer(Thermometer),
+    /// Channel of the registry's ANT stick
+    Ant(Rc<RefCell<AntNode>>, AntProfile),
+    /// Served by the registry's `SimulatedSensor`
+    Simulated,
+}
+
+struct Sensor {
+    role: SensorRole,
+    info: Rc<RefCell<SensorInfo>>,
+    link: Rc<RefCell<Reconnector>>,
+    device: Device,
+}
+
+impl Sensor {
+    fn is_simulated(&self) -> bool {
+        matches!(self.device, Device::Simulated)
+    }
+
+    fn is_ant(&self) -> bool {
+        matches!(self.device, Device::Ant(..))
+    }
+}
+
+/// Where readings end up, shared by real and simulated devices
+#[derive(Clone)]
+struct Sink {
+    store: Rc<RefCell<Store>>,
+    events: Rc<RefCell<Vec<UserEvent>>>,
+    calibration: Rc<RefCell<Option<Calibration>>>,
+}
+
+impl Sink {
+    fn heart_rate(&self, m: &HeartRateMeasurement) {
+        let store = self.store.borrow();
+        let state = &store.state;
+        let mut events = self.events.borrow_mut();
+        if self.accept(Channel::HeartRate, m.heart_rate as f32) {
+            state.get_record().borrow_mut().push(RecordField::HeartRate, m.heart_rate as f32);
+            events.push(UserEvent::HrChanged(m.heart_rate as i32));
+        }
+        if !m.rr_intervals.is_empty() {
+            let rr_data = state.get_rr_data();
+            let mut rr = rr_data.borrow_mut();
+            rr.add_rr(Date::now() as usize, &m.rr_intervals);
+            if let Some(hrv) = rr.hrv() {
+                events.push(UserEvent::HrvChanged(hrv));
+            }
+        }
+    }
+
+    fn power(&self, m: PowerMeasurement) {
+        if !self.accept(Channel::Power, m.instantaneous_power as f32) {
+            return;
+        }
+        let store = self.store.borrow();
+        store.state.get_record().borrow_mut().push(RecordField::Power, m.instantaneous_power as f32);
+        let pedaling = store.state.get_pedaling().borrow_mut().add_power(Date::now() as usize, &m);
+        let mut events = self.events.borrow_mut();
+        events.push(UserEvent::PowerChanged(m));
+        if pedaling {
+            events.push(UserEvent::PedalingChanged);
+        }
+        events.extend(store.state.check_power_records().into_iter().map(UserEvent::PowerRecord));
+    }
+
+    fn vector(&self, v: &PowerVector) {
+        if self.store.borrow().state.get_pedaling().borrow_mut().add_vector(Date::now() as usize, v) {
+            self.events.borrow_mut().push(UserEvent::PedalingChanged);
+        }
+    }
+
+    /// Trainer or wheel speed, reported as the speed in the virtual gear
+    fn speed(&self, speed: f32) {
+        if !self.accept(Channel::Speed, speed) {
+            return;
+        }
+        let store = self.store.borrow();
+        let speed = store.state.get_gearing().borrow().virtual_speed(speed);
+        store.state.get_rider().borrow_mut().set_speed(speed);
+        store.state.get_record().borrow_mut().push(RecordField::Speed, speed);
+        self.events.borrow_mut().push(UserEvent::SpeedChanged(speed));
+    }
+
+    fn cadence(&self, cadence: f32) {
+        if !self.accept(Channel::Cadence, cadence) {
+            return;
+        }
+        self.store.borrow().state.get_record().borrow_mut().push(RecordField::Cadence, cadence);
+        self.events.borrow_mut().push(UserEvent::CadenceChanged(cadence));
+    }
+
+    fn temperature(&self, m: &TemperatureMeasurement) {
+        if !self.accept(Channel::Temperature, m.celsius) {
+            return;
+        }
+        self.store.borrow().state.get_record().borrow_mut().push(RecordField::Temperature, m.celsius);
+        self.events.borrow_mut().push(UserEvent::TemperatureChanged(m.celsius));
+    }
+
+    fn muscle_oxygen(&self, m: MuscleOxygen) {
+        let smo2 = m.smo2.filter(|smo2| self.accept(Channel::SmO2, *smo2));
+        let thb = m.thb.filter(|thb| self.accept(Channel::Thb, *thb));
+        if smo2.is_none() && thb.is_none() {
+            return;
+        }
+        let record = self.store.borrow().state.get_record();
+        let mut record = record.borrow_mut();
+        if let Some(smo2) = smo2 {
+            record.push(RecordField::SmO2, smo2);
+        }
+        if let Some(thb) = thb {
+            record.push(RecordField::THb, thb);
+        }
+        self.events.borrow_mut().push(UserEvent::MuscleOxygenChanged(MuscleOxygen { smo2, thb }));
+    }
+
+    /// Fitness machine data of a trainer without (or before) the dedicated services
+    fn indoor_bike(&self, data: IndoorBikeData) {
+        if let Some(speed) = data.instantaneous_speed {
+            self.speed(speed);
+        }
+        if let Some(cadence) = data.instantaneous_cadence {
+            self.cadence(cadence);
+        }
+        if let Some(power) = data.instantaneous_power {
+            self.power(PowerMeasurement::from_power(power));
+        }
+        if let Some(level) = data.resistance_level {
+            self.events.borrow_mut().push(UserEvent::ResistanceChanged(level));
+        }
+    }
+
+    /// Notification of a power meter or trainer, whichever way it is connected
+    fn trainer_event(&self, trainer: &TrainerControl, event: TrainerEvent) {
+        match event {
+            TrainerEvent::Power(m) => self.power(m),
+            TrainerEvent::Vector(v) => self.vector(&v),
+            TrainerEvent::IndoorBike(data) => self.indoor_bike(data),
+            TrainerEvent::Speed(speed) => self.speed(speed),
+            TrainerEvent::Cadence(cadence) => self.cadence(cadence),
+            TrainerEvent::Control(response) => {
+                trainer.handle_response(&response);
+                self.control_response(response);
+            }
+            TrainerEvent::PowerControl(response) => self.power_control(response),
+            TrainerEvent::Status(status) => self.machine_status(status),
+        }
+    }
+
+    fn control_response(&self, response: ControlResponse) {
+        self.update_calibration(|c| c.control_response(&response));
+        self.events.borrow_mut().push(UserEvent::TrainerResponse(response));
+    }
+
+    fn power_control(&self, response: PowerControlResponse) {
+        self.update_calibration(|c| c.power_control(&response));
+    }
+
+    fn machine_status(&self, status: MachineStatus) {
+        if let MachineStatus::SpinDown(status) = status {
+            self.update_calibration(|c| c.spin_down_status(status));
+        }
+    }
+
+    /// Run a reading through the data quality filter
+    fn accept(&self, channel: Channel, value: f32) -> bool {
+        self.store.borrow().state.get_quality().borrow_mut().accept(channel, value)
+    }
+
+    /// Advance staleness by `dt` ms, close the series of channels that went stale with a gap
+    /// and report quality changes
+    fn check_quality(&self, dt: f32) {
+        let store = self.store.borrow();
+        let quality = store.state.get_quality();
+        let mut quality = quality.borrow_mut();
+        quality.tick(dt);
+        let changes = quality.take_changes();
+        if stop_stale_rider(&mut store.state.get_rider().borrow_mut(), &changes) {
+            self.events.borrow_mut().push(UserEvent::SpeedChanged(0.0));
+        }
+        for (channel, q) in changes {
+            if q == Quality::Stale {
+                match channel {
+                    Channel::HeartRate => store.state.get_record().borrow_mut().push_gap(RecordField::HeartRate),
+                    Channel::Power => store.state.get_record().borrow_mut().push_gap(RecordField::Power),
+                    Channel::Speed => store.state.get_record().borrow_mut().push_gap(RecordField::Speed),
+                    Channel::Cadence => store.state.get_record().borrow_mut().push_gap(RecordField::Cadence),
+                    Channel::Temperature => store.state.get_record().borrow_mut().push_gap(RecordField::Temperature),
+                    Channel::SmO2 => store.state.get_record().borrow_mut().push_gap(RecordField::SmO2),
+                    Channel::Thb => store.state.get_record().borrow_mut().push_gap(RecordField::THb),
+                }
+            }
+            self.events.borrow_mut().push(UserEvent::QualityChanged(channel, q));
+        }
+    }
+
+    /// Apply `f` to the calibration in progress, report and log the step it moved to
+    fn update_calibration<F: FnOnce(&mut Calibration) -> bool>(&self, f: F) {
+        let (step, record) = match self.calibration.borrow_mut().as_mut() {
+            Some(calibration) => {
+                if !f(calibration) {
+                    return;
+                }
+                (calibration.step(), calibration.record(Date::now()))
+            }
+            None => return,
+        };
+        if let Some(record) = record {
+            let store = self.store.borrow();
+            store.state.get_calibrations().borrow_mut().push(record);
+        }
+        self.events.borrow_mut().push(UserEvent::CalibrationChanged(step));
+    }
+}
+
+/// Stop the rider when speed goes stale, whether the sensor fell silent or only sends
+/// rejected readings, so the distance doesn't keep growing at the last speed
+fn stop_stale_rider(rider: &mut Rider, changes: &[(Channel, Quality)]) -> bool {
+    let stale = changes.contains(&(Channel::Speed, Quality::Stale));
+    if stale {
+        rider.set_speed(0.0);
+    }
+    stale
+}
+
+/// Owns connected devices, one per role, and turns their notifications into
+/// store updates and `UserEvent`s. Events are queued until the UI picks them up.
+/// Devices are remembered once connected and reconnected when the link drops.
+pub struct SensorRegistry {
+    sink: Sink,
+    trainer: TrainerControl,
+    memory: Rc<RefCell<DeviceMemory>>,
+    sensors: Vec<Sensor>,
+    simulator: Option<SimulatedSensor>,
+    ant_port: Option<WebSerialTransport>,
+    ant: Option<Rc<RefCell<AntNode>>>,
+    wheel_circumference: f32,
+}
+
+impl SensorRegistry {
+    pub fn new(store: Rc<RefCell<Store>>, trainer: TrainerControl) -> SensorRegistry {
+        SensorRegistry {
+            sink: Sink {
+                store,
+                events: Rc::new(RefCell::new(Vec::new())),
+                calibration: Rc::new(RefCell::new(None)),
+            },
+            trainer,
+            memory: Rc::new(RefCell::new(DeviceMemory::load())),
+            sensors: Vec::new(),
+            simulator: None,
+            ant_port: None,
+            ant: None,
+            wheel_circumference: DEFAULT_WHEEL_CIRCUMFERENCE,
+        }
+    }
+
+    /// Let the user choose a device serving `role`.
+    /// A simulated role is handed over to the real device.
+    pub fn connect(&mut self, role: SensorRole) {
+        if role.ant_only() {
+            self.connect_ant();
+            return;
+        }
+        let index = self.sensor_index(role);
+        let sensor = &self.sensors[index];
+        let changed = sensor.link.borrow_mut().scan();
+        Self::apply_state(role, &sensor.info, &sensor.link, changed, &self.sink.events);
+        Self::pair_device(&sensor.device);
+    }
+
+    /// Let the user choose an ANT USB stick and serve heart rate, trainer and muscle oxygen
+    /// from it, unless a Bluetooth device already does
+    pub fn connect_ant(&mut self) {
+        let key = self.sink.store.borrow().state.get_profile().borrow().ant_network_key.clone();
+        let key = match parse_network_key(&key) {
+            Some(key) => key,
+            None => {
+                console::log_1(&"ANT+ network key missing from the user profile".into());
+                return;
+            }
+        };
+        let port = self.ant_port.get_or_insert_with(WebSerialTransport::new).clone();
+        port.connect();
+        let node = self.ant.get_or_insert_with(|| Rc::new(RefCell::new(AntNode::new(Box::new(port), key)))).clone();
+
+        for (role, profile) in &ANT_ROLES {
+            if self.sensors.iter().any(|s| s.role == *role && !s.is_simulated() && !s.is_ant()) {
+                continue;
+            }
+            self.sensors.retain(|s| !(s.role == *role && s.is_simulated()));
+            let index = match self.sensors.iter().position(|s| s.role == *role) {
+                Some(index) => index,
+                None => {
+                    let info = SensorInfo { name: Some(ANT_NAME.to_string()), ..SensorInfo::default() };
+                    self.sensors.push(Sensor {
+                        role: *role,
+                        info: Rc::new(RefCell::new(info)),
+                        link: Rc::new(RefCell::new(Reconnector::default())),
+                        device: Device::Ant(node.clone(), *profile),
+                    });
+                    self.sink.events.borrow_mut().push(UserEvent::DeviceInfoChanged(*role));
+                    self.sensors.len() - 1
+                }
+            };
+            let sensor = &self.sensors[index];
+            let changed = sensor.link.borrow_mut().scan();
+            Self::apply_state(*role, &sensor.info, &sensor.link, changed, &self.sink.events);
+            Self::pair_device(&sensor.device);
+            if *role == SensorRole::Trainer {
+                let node = node.clone();
+                self.trainer.set_writer(Some(Box::new(move |request| node.borrow_mut().control(request))));
+            }
+        }
+    }
+
+    /// Re-acquire remembered devices, e.g. on startup
+    pub fn restore_remembered(&mut self) {
+        let remembered: Vec<RememberedDevice> = self.memory.borrow().devices().to_vec();
+        for device in remembered {
+            if self.sensors.iter().any(|s| s.role == device.role && !s.is_simulated()) {
+                continue;
+            }
+            let index = self.sensor_index(device.role);
+            let sensor = &self.sensors[index];
+            sensor.info.borrow_mut().name = device.name;
+            let changed = sensor.link.borrow_mut().restore();
+            Self::apply_state(device.role, &sensor.info, &sensor.link, changed, &self.sink.events);
+        }
+    }
+
+    /// Don't reconnect the device serving `role` on next startup
+    pub fn forget(&mut self, role: SensorRole) {
+        self.memory.borrow_mut().forget(role);
+    }
+
+    pub fn remembered(&self, role: SensorRole) -> Option<RememberedDevice> {
+        self.memory.borrow().get(role).cloned()
+    }
+
+    /// Serve heart rate, power and speed/cadence from `simulator` for roles without a real device
+    pub fn simulate(&mut self, simulator: SimulatedSensor) {
+        self.simulator = Some(simulator);
+        for role in &SIMULATED_ROLES {
+            if self.sensors.iter().any(|s| s.role == *role) {
+                continue;
+            }
+            let info = SensorInfo {
+                state: ConnectionState::Connected,
+                name: Some(SIMULATED_NAME.to_string()),
+                ..SensorInfo::default()
+            };
+            self.sensors.push(Sensor {
+                role: *role,
+                info: Rc::new(RefCell::new(info)),
+                link: Rc::new(RefCell::new(Reconnector::default())),
+                device: Device::Simulated,
+            });
+            let mut events = self.sink.events.borrow_mut();
+            events.push(UserEvent::SensorStateChanged(*role, ConnectionState::Connected));
+            events.push(UserEvent::DeviceInfoChanged(*role));
+        }
+    }
+
+    pub fn stop_simulation(&mut self) {
+        self.simulator = None;
+        let mut events = self.sink.events.borrow_mut();
+        self.sensors.retain(|s| {
+            let simulated = s.is_simulated();
+            if simulated {
+                events.push(UserEvent::SensorStateChanged(s.role, ConnectionState::Disconnected));
+            }
+            !simulated
+        });
+    }
+
+    /// Start the guided calibration, the rider confirms once prepared
+    pub fn start_calibration(&mut self, kind: CalibrationKind) {
+        *self.sink.calibration.borrow_mut() = Some(Calibration::new(kind));
+        self.sink.events.borrow_mut().push(UserEvent::CalibrationChanged(CalibrationStep::Instruct(kind)));
+    }
+
+    pub fn confirm_calibration(&mut self) {
+        let command = match self.sink.calibration.borrow_mut().as_mut() {
+            Some(calibration) => calibration.confirm(),
+            None => return,
+        };
+        let sent = match command {
+            Some(CalibrationCommand::StartOffsetCompensation) => match self.connected_power_meter() {
+                Some(power_meter) => {
+                    power_meter.start_offset_compensation();
+                    true
+                }
+                None => false,
+            },
+            Some(CalibrationCommand::StartSpinDown) if self.trainer.is_connected() => {
+                self.trainer.start_spin_down();
+                true
+            }
+            Some(CalibrationCommand::StartSpinDown) => false,
+            None => return,
+        };
+        self.sink.update_calibration(|c| {
+            if !sent {
+                c.fail();
+            }
+            true
+        });
+    }
+
+    /// Abandon the calibration in progress or dismiss its result
+    pub fn close_calibration(&mut self) {
+        *self.sink.calibration.borrow_mut() = None;
+        self.sink.events.borrow_mut().push(UserEvent::CalibrationChanged(CalibrationStep::Idle));
+    }
+
+    pub fn calibration_step(&self) -> CalibrationStep {
+        self.sink.calibration.borrow().as_ref().map(|c| c.step()).unwrap_or(CalibrationStep::Idle)
+    }
+
+    pub fn calibration_instruction(&self) -> &'static str {
+        self.sink.calibration.borrow().as_ref().map(|c| c.instruction()).unwrap_or("")
+    }
+
+    pub fn simulator_mut(&mut self) -> Option<&mut SimulatedSensor> {
+        self.simulator.as_mut()
+    }
+
+    /// Called on every clock advance with elapsed ms
+    pub fn tick(&mut self, dt: f32) {
+        self.sink.update_calibration(|c| c.tick(dt));
+        self.sink.check_quality(dt);
+        for sensor in self.sensors.iter().filter(|s| !s.is_simulated()) {
+            let (attempt, changed) = sensor.link.borrow_mut().tick(dt);
+            Self::apply_state(sensor.role, &sensor.info, &sensor.link, changed, &self.sink.events);
+            if attempt {
+                let id = self.memory.borrow().get(sensor.role).map(|d| d.id.clone());
+                Self::resume_device(&sensor.device, id);
+            }
+        }
+        let ant_events = match &self.ant {
+            Some(node) => node.borrow_mut().poll(dt),
+            None => Vec::new(),
+        };
+        for event in ant_events {
+            self.ant_event(event);
+        }
+
+        if let Some(simulator) = self.simulator.as_mut() {
+            // the simulated rider follows heart rate ERG like a trainer would
+            let hr_control = self.sink.store.borrow().state.get_hr_control();
+            let power = hr_control.borrow().as_ref().map(|c| c.power());
+            simulator.set_power_override(power);
+        }
+        let sample = match self.simulator.as_mut().and_then(|s| s.tick(dt)) {
+            Some(sample) => sample,
+            None => return,
+        };
+        let simulated = |role: SensorRole| self.sensors.iter()
+            .any(|s| s.role == role && s.is_simulated());
+        if simulated(SensorRole::HeartRate) {
+            self.sink.heart_rate(&HeartRateMeasurement {
+                heart_rate: sample.heart_rate,
+                contact: Some(true),
+                energy_expended: None,
+                rr_intervals: Vec::new(),
+            });
+        }
+        if simulated(SensorRole::Power) {
+            self.sink.power(PowerMeasurement::from_power(sample.power));
+        }
+        if simulated(SensorRole::SpeedCadence) {
+            self.sink.speed(sample.speed);
+            self.sink.cadence(sample.cadence);
+        }
+    }
+
+    pub fn info(&self, role: SensorRole) -> SensorInfo {
+        self.sensors.iter()
+            .find(|s| s.role == role)
+            .map(|s| s.info.borrow().clone())
+            .unwrap_or_default()
+    }
+
+    pub fn state(&self, role: SensorRole) -> ConnectionState {
+        self.sensors.iter()
+            .find(|s| s.role == role)
+            .map(|s| s.info.borrow().state)
+            .unwrap_or(ConnectionState::Disconnected)
+    }
+
+    /// Metres
+    pub fn set_wheel_circumference(&mut self, wheel_circumference: f32) {
+        self.wheel_circumference = wheel_circumference;
+        for sensor in &self.sensors {
+            match &sensor.device {
+                Device::Power(power_meter) => power_meter.set_wheel_circumference(wheel_circumference),
+                Device::SpeedCadence(csc) => csc.set_wheel_circumference(wheel_circumference),
+                Device::HeartRate(_) | Device::Thermometer(_) | Device::Ant(..) | Device::Simulated => {}
+            }
+        }
+    }
+
+    pub fn take_events(&self) -> Vec<UserEvent> {
+        self.sink.events.borrow_mut().drain(..).collect()
+    }
+
+    /// Power meter, or a trainer as those often expose the cycling power service too
+    fn connected_power_meter(&self) -> Option<&PowerMeter> {
+        [SensorRole::Power, SensorRole::Trainer].iter().find_map(|role| {
+            let sensor = self.sensors.iter().find(|s| s.role == *role)?;
+            match &sensor.device {
+                Device::Power(power_meter) if sensor.info.borrow().state == ConnectionState::Connected => Some(power_meter),
+                _ => None,
+            }
+        })
+    }
+
+    /// Index of the real sensor serving `role`, created if there is none yet
+    fn sensor_index(&mut self, role: SensorRole) -> usize {
+        self.sensors.retain(|s| !(s.role == role && s.is_simulated()));
+        if let Some(index) = self.sensors.iter().position(|s| s.role == role) {
+            return index;
+        }
+        let info = Rc::new(RefCell::new(SensorInfo::default()));
+        let link = Rc::new(RefCell::new(Reconnector::default()));
+        let device = self.create_device(role, &info, &link);
+        self.sensors.push(Sensor { role, info, link, device });
+        self.sensors.len() - 1
+    }
+
+    fn pair_device(device: &Device) {
+        match device {
+            Device::HeartRate(hrm) => hrm.reconnect_hrm(),
+            Device::Power(power_meter) => power_meter.reconnect(),
+            Device::SpeedCadence(csc) => csc.reconnect(),
+            Device::Thermometer(thermometer) => thermometer.reconnect(),
+            Device::Ant(node, profile) => node.borrow_mut().open(*profile),
+            Device::Simulated => {}
+        }
+    }
+
+    fn resume_device(device: &Device, id: Option<String>) {
+        match device {
+            Device::HeartRate(hrm) => hrm.resume(id),
+            Device::Power(power_meter) => power_meter.resume(id),
+            Device::SpeedCadence(csc) => csc.resume(id),
+            Device::Thermometer(thermometer) => thermometer.resume(id),
+            Device::Ant(node, profile) => node.borrow_mut().open(*profile),
+            Device::Simulated => {}
+        }
+    }
+
+    fn ant_event(&self, event: AntEvent) {
+        match event {
+            AntEvent::Link(profile, link_event) => {
+                let role = match ANT_ROLES.iter().find(|(_, p)| *p == profile) {
+                    Some((role, _)) => *role,
+                    None => return,
+                };
+                let sensor = match self.sensors.iter().find(|s| s.role == role && s.is_ant()) {
+                    Some(sensor) => sensor,
+                    None => return,
+                };
+                let changed = sensor.link.borrow_mut().on_link(link_event);
+                Self::apply_state(role, &sensor.info, &sensor.link, changed, &self.sink.events);
+                if changed == Some(ConnectionState::Connected) && role == SensorRole::Trainer {
+                    self.trainer.restart();
+                }
+            }
+            AntEvent::HeartRate(m) => self.sink.heart_rate(&m),
+            AntEvent::Trainer(event) => self.sink.trainer_event(&self.trainer, event),
+            AntEvent::MuscleOxygen(m) => self.sink.muscle_oxygen(m),
+        }
+    }
+
+    /// Record a state change of the link and report it
+    fn apply_state(role: SensorRole, info: &Rc<RefCell<SensorInfo>>, link: &Rc<RefCell<Reconnector>>,
+                   changed: Option<ConnectionState>, events: &Rc<RefCell<Vec<UserEvent>>>) {
+        if let Some(state) = changed {
+            let mut info = info.borrow_mut();
+            info.state = state;
+            info.reconnect_attempts = link.borrow().attempts();
+            events.borrow_mut().push(UserEvent::SensorStateChanged(role, state));
+        }
+    }
+
+    fn create_device(&self, role: SensorRole, info: &Rc<RefCell<SensorInfo>>, link: &Rc<RefCell<Reconnector>>) -> Device {
+        let on_state = self.status_handler(role, info, link);
+        let on_details = self.details_handler(role, info);
+        let sink = self.sink.clone();
+        match role {
+            SensorRole::HeartRate => Device::HeartRate(HRM::new(move |m| sink.heart_rate(m), on_details, on_state)),
+            SensorRole::Power | SensorRole::Trainer => {
+                let trainer = self.trainer.clone();
+                let power_meter = PowerMeter::new(role == SensorRole::Trainer, move |event| {
+                    sink.trainer_event(&trainer, event)
+                }, on_details, on_state);
+                power_meter.set_wheel_circumference(self.wheel_circumference);
+                if role == SensorRole::Trainer {
+                    power_meter.attach_control(&self.trainer);
+                }
+                Device::Power(power_meter)
+            }
+            SensorRole::SpeedCadence => {
+                Device::SpeedCadence(CscSensor::new(self.wheel_circumference, move |speed, cadence| {
+                    if let Some(speed) = speed {
+                        sink.speed(speed);
+                    }
+                    if let Some(cadence) = cadence {
+                        sink.cadence(cadence);
+                    }
+                }, on_details, on_state))
+            }
+            SensorRole::Thermometer => Device::Thermometer(Thermometer::new(move |m| sink.temperature(m), on_details, on_state)),
+            SensorRole::MuscleOxygen => unreachable!("ANT only roles are served through connect_ant"),
+        }
+    }
+
+    fn status_handler(&self, role: SensorRole, info: &Rc<RefCell<SensorInfo>>,
+                      link: &Rc<RefCell<Reconnector>>) -> impl FnMut(&JsValue) + 'static {
+        let info = info.clone();
+        let link = link.clone();
+        let events = self.sink.events.clone();
+        let memory = self.memory.clone();
+        let trainer = self.trainer.clone();
+        // sensorState is sent whole on every change, only new link states count
+        let mut last_link: Option<String> = None;
+        move |js: &JsValue| {
+            let status: SensorStatus = js.into_serde().unwrap_or_default();
+
+            if status.state.is_some() && status.state != last_link {
+                last_link = status.state.clone();
+                if let Some(event) = status.state.as_deref().and_then(LinkEvent::from_js) {
+                    let changed = link.borrow_mut().on_link(event);
+                    Self::apply_state(role, &info, &link, changed, &events);
+                    if changed == Some(ConnectionState::Connected) {
+                        if role == SensorRole::Trainer {
+                            // control handshake has to be repeated on every connection
+                            trainer.restart();
+                        }
+                        if let Some(id) = status.id.clone() {
+                            memory.borrow_mut().remember(RememberedDevice { role, id, name: status.name.clone() });
+                        }
+                    }
+                }
+            }
+
+            let mut info = info.borrow_mut();
+            info.id = status.id;
+            if status.name != info.name {
+                info.name = status.name;
+                events.borrow_mut().push(UserEvent::DeviceInfoChanged(role));
+            }
+        }
+    }
+
+    fn details_handler(&self, role: SensorRole, info: &Rc<RefCell<SensorInfo>>) -> impl FnMut(DeviceDetails) + 'static {
+        let info = info.clone();
+        let events = self.sink.events.clone();
+        move |details: DeviceDetails| {
+            let mut info = info.borrow_mut();
+            let mut events = events.borrow_mut();
+            match details {
+                DeviceDetails::Battery(level) if info.battery != Some(level) => {
+                    if is_low_battery(info.battery, level) {
+                        events.push(UserEvent::BatteryLow(role, level));
+                    }
+                    info.battery = Some(level);
+                    events.push(UserEvent::BatteryChanged(role, level));
+                }
+                DeviceDetails::Info(device) if info.device != device => {
+                    info.device = device;
+                    events.push(UserEvent::DeviceInfoChanged(role));
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quality::DataQuality;
+    use crate::rider::Course;
+
+    #[test]
+    fn stops_rider_when_speed_goes_stale() {
+        let mut quality = DataQuality::new();
+        let mut rider = Rider::new(Course::rolling());
+        assert!(quality.accept(Channel::Speed, 10.0));
+        rider.set_speed(10.0);
+        // readings keep coming but are out of range
+        for _ in 0..5 {
+            assert!(!quality.accept(Channel::Speed, 40.0));
+            quality.tick(1000.0);
+            rider.advance(1000.0);
+            stop_stale_rider(&mut rider, &quality.take_changes());
+        }
+        assert_eq!(rider.speed(), 0.0);
+        assert!((rider.distance() - 30.0).abs() < 1e-4);
+
+        // a silent sensor stops the rider too
+        assert!(quality.accept(Channel::Speed, 5.0));
+        rider.set_speed(5.0);
+        assert!(!stop_stale_rider(&mut rider, &quality.take_changes()));
+        quality.tick(3000.0);
+        rider.advance(3000.0);
+        assert!(stop_stale_rider(&mut rider, &quality.take_changes()));
+        rider.advance(3000.0);
+        assert!((rider.distance() - 45.0).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,70 @@
+use serde::{Deserialize, Serialize};
+use web_sys::Storage;
+
+use crate::bluetooth::registry::SensorRole;
+
+const STORAGE_KEY: &str = "remembered_devices";
+
+/// Paired device and the role the user connected it for
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RememberedDevice {
+    pub role: SensorRole,
+    /// `BluetoothDevice.id`, stable for the origin while permission is granted
+    pub id: String,
+    pub name: Option<String>,
+}
+
+/// Remembered devices kept in local storage, at most one per role
+#[derive(Default)]
+pub struct DeviceMemory {
+    devices: Vec<RememberedDevice>,
+}
+
+impl DeviceMemory {
+    pub fn load() -> DeviceMemory {
+        let devices = Self::storage()
+            .and_then(|s| s.get_item(STORAGE_KEY).ok().flatten())
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+        DeviceMemory { devices }
+    }
+
+    pub fn devices(&self) -> &[RememberedDevice] {
+        &self.devices
+    }
+
+    pub fn get(&self, role: SensorRole) -> Option<&RememberedDevice> {
+        self.devices.iter().find(|d| d.role == role)
+    }
+
+    pub fn remember(&mut self, device: RememberedDevice) {
+        if self.get(device.role) == Some(&device) {
+            return;
+        }
+        self.devices.retain(|d| d.role != device.role);
+        self.devices.push(device);
+        self.save();
+    }
+
+    pub fn forget(&mut self, role: SensorRole) {
+        let count = self.devices.len();
+        self.devices.retain(|d| d.role != role);
+        if self.devices.len() != count {
+            self.save();
+        }
+    }
+
+    fn save(&self) {
+        let storage = match Self::storage() {
+            Some(storage) => storage,
+            None => return,
+        };
+        if let Ok(json) = serde_json::to_string(&self.devices) {
+            storage.set_item(STORAGE_KEY, &json).ok();
+        }
+    }
+
+    fn storage() -> Option<Storage> {
+        web_sys::window()?.local_storage().ok().flatten()
+    }
+}
@@ -0,0 +1,309 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+/// One reading per second, like most real sensors
+const SAMPLE_INTERVAL: f32 = 1000.0;
+
+/// Simulated rider
+const REST_HR: f32 = 60.0;
+const THRESHOLD_HR: f32 = 170.0;
+const MAX_HR: f32 = 195.0;
+/// Watts
+const THRESHOLD_POWER: f32 = 250.0;
+/// HR time constant, ms
+const HR_RESPONSE: f32 = 30000.0;
+/// kg, rider and bike
+const MASS: f32 = 80.0;
+const CRR: f32 = 0.004;
+/// kg/m
+const CW: f32 = 0.51;
+const G: f32 = 9.81;
+
+/// Scripted power target over time
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+pub enum Profile {
+    /// Watts
+    Steady(f32),
+    /// Alternating work and rest, watts and ms
+    Intervals { work: f32, rest: f32, work_duration: f32, rest_duration: f32 },
+    /// Stepwise increasing power, watts and ms
+    Ramp { start: f32, step: f32, step_duration: f32 },
+}
+
+impl Profile {
+    /// Target power at `t` ms from start
+    pub fn power_at(&self, t: f32) -> f32 {
+        match *self {
+            Profile::Steady(power) => power,
+            Profile::Intervals { work, rest, work_duration, rest_duration } => {
+                let period = work_duration + rest_duration;
+                if period <= 0.0 || t.rem_euclid(period) < work_duration { work } else { rest }
+            }
+            Profile::Ramp { start, step, step_duration } => {
+                if step_duration <= 0.0 {
+                    start
+                } else {
+                    start + step * (t / step_duration).floor()
+                }
+            }
+        }
+    }
+}
+
+/// Periodic signal loss, ms
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+pub struct Dropouts {
+    pub every: f32,
+    pub duration: f32,
+}
+
+/// Script of the demo rider, kept in the user profile
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct SimulatorSettings {
+    pub profile: Profile,
+    /// Relative amplitude of random noise on power
+    pub noise: f32,
+    pub dropouts: Option<Dropouts>,
+}
+
+impl Default for SimulatorSettings {
+    fn default() -> Self {
+        SimulatorSettings {
+            profile: Profile::Steady(180.0),
+            noise: 0.1,
+            dropouts: None,
+        }
+    }
+}
+
+/// Readings produced by the simulated sensor
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SimulatedSample {
+    /// bpm
+    pub heart_rate: u16,
+    /// Watts
+    pub power: i16,
+    /// rpm
+    pub cadence: f32,
+    /// m/s
+    pub speed: f32,
+}
+
+/// Generates heart rate, power, cadence and speed from a scripted profile.
+/// Heart rate follows power with a first order lag, speed is the steady state
+/// speed on flat road for the produced power.
+pub struct SimulatedSensor {
+    profile: Profile,
+    /// Relative amplitude of random noise on power
+    noise: f32,
+    dropouts: Option<Dropouts>,
+    /// Replaces the profile when set, e.g. by a trainer in ERG mode
+    power_override: Option<f32>,
+    rng: StdRng,
+    elapsed: f32,
+    since_sample: f32,
+    heart_rate: f32,
+}
+
+impl SimulatedSensor {
+    pub fn new(profile: Profile) -> SimulatedSensor {
+        SimulatedSensor {
+            profile,
+            noise: 0.0,
+            dropouts: None,
+            power_override: None,
+            rng: StdRng::from_entropy(),
+            elapsed: 0.0,
+            since_sample: 0.0,
+            heart_rate: REST_HR,
+        }
+    }
+
+    pub fn from_settings(settings: &SimulatorSettings) -> SimulatedSensor {
+        let sensor = SimulatedSensor::new(settings.profile).with_noise(settings.noise);
+        match settings.dropouts {
+            Some(d) => sensor.with_dropouts(d.every, d.duration),
+            None => sensor,
+        }
+    }
+
+    /// Fraction of power, e.g. 0.05 for ±5%
+    pub fn with_noise(mut self, noise: f32) -> SimulatedSensor {
+        self.noise = noise;
+        self
+    }
+
+    /// No readings for `duration` ms once every `every` ms
+    pub fn with_dropouts(mut self, every: f32, duration: f32) -> SimulatedSensor {
+        self.dropouts = Some(Dropouts { every, duration });
+        self
+    }
+
+    /// Reproducible noise for tests
+    pub fn with_seed(mut self, seed: u64) -> SimulatedSensor {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+
+    pub fn set_profile(&mut self, profile: Profile) {
+        self.profile = profile;
+        self.elapsed = 0.0;
+    }
+
+    pub fn set_power_override(&mut self, power: Option<f32>) {
+        self.power_override = power;
+    }
+
+    /// ms since start
+    pub fn elapsed(&self) -> f32 {
+        self.elapsed
+    }
+
+    /// Advance by `dt` ms, returns a sample once per `SAMPLE_INTERVAL` unless the signal is lost
+    pub fn tick(&mut self, dt: f32) -> Option<SimulatedSample> {
+        self.since_sample += dt;
+        if self.since_sample < SAMPLE_INTERVAL {
+            self.elapsed += dt;
+            return None;
+        }
+        let step = self.since_sample;
+        self.since_sample = 0.0;
+
+        let target = self.power_override.unwrap_or_else(|| self.profile.power_at(self.elapsed));
+        self.elapsed += dt;
+        let noise = if self.noise > 0.0 { self.rng.gen_range(-self.noise..self.noise) } else { 0.0 };
+        let power = (target * (1.0 + noise)).max(0.0);
+
+        self.heart_rate += (Self::steady_heart_rate(power) - self.heart_rate) * (1.0 - (-step / HR_RESPONSE).exp());
+
+        if self.in_dropout() {
+            return None;
+        }
+        Some(SimulatedSample {
+            heart_rate: self.heart_rate.round() as u16,
+            power: power.round().min(i16::MAX as f32) as i16,
+            cadence: Self::cadence(power),
+            speed: Self::speed(power),
+        })
+    }
+
+    fn in_dropout(&self) -> bool {
+        match self.dropouts {
+            Some(d) if d.every > 0.0 => self.elapsed.rem_euclid(d.every) >= d.every - d.duration,
+            _ => false,
+        }
+    }
+
+    fn steady_heart_rate(power: f32) -> f32 {
+        (REST_HR + (THRESHOLD_HR - REST_HR) * power / THRESHOLD_POWER).min(MAX_HR)
+    }
+
+    fn cadence(power: f32) -> f32 {
+        if power <= 0.0 { 0.0 } else { (75.0 + 20.0 * power / THRESHOLD_POWER).min(110.0) }
+    }
+
+    /// Solve power = v * (crr * m * g + cw * v^2) for v
+    fn speed(power: f32) -> f32 {
+        let rolling = CRR * MASS * G;
+        let mut v: f32 = 10.0;
+        for _ in 0..10 {
+            let f = CW * v * v * v + rolling * v - power;
+            let df = 3.0 * CW * v * v + rolling;
+            v -= f / df;
+        }
+        v.max(0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Samples of `seconds` s ticked at 10 Hz
+    fn run(sensor: &mut SimulatedSensor, seconds: usize) -> Vec<SimulatedSample> {
+        (0..seconds * 10).filter_map(|_| sensor.tick(100.0)).collect()
+    }
+
+    #[test]
+    fn steady_rider_settles_at_threshold() {
+        let mut sensor = SimulatedSensor::new(Profile::Steady(THRESHOLD_POWER)).with_seed(1);
+        let samples = run(&mut sensor, 600);
+        assert_eq!(samples.len(), 600);
+        assert!(samples.iter().all(|s| s.power == 250));
+        // heart rate rises without overshooting
+        assert!(samples.windows(2).all(|w| w[1].heart_rate >= w[0].heart_rate));
+        let last = samples.last().unwrap();
+        assert!((last.heart_rate as i32 - THRESHOLD_HR as i32).abs() <= 1, "{:?}", last);
+        assert_eq!(last.cadence, 95.0);
+        // power matches rolling and air resistance at that speed
+        let v = last.speed;
+        assert!((v * (CRR * MASS * G + CW * v * v) - 250.0).abs() < 0.1, "{}", v);
+    }
+
+    #[test]
+    fn alternates_intervals() {
+        let profile = Profile::Intervals { work: 300.0, rest: 100.0, work_duration: 30_000.0, rest_duration: 60_000.0 };
+        assert_eq!(profile.power_at(29_999.0), 300.0);
+        assert_eq!(profile.power_at(30_000.0), 100.0);
+        assert_eq!(profile.power_at(95_000.0), 300.0);
+
+        let mut sensor = SimulatedSensor::new(profile).with_seed(2);
+        let power: Vec<i16> = run(&mut sensor, 180).iter().map(|s| s.power).collect();
+        assert_eq!(power.len(), 180);
+        assert_eq!(power.iter().filter(|p| **p == 300).count(), 60);
+        assert!(power[..29].iter().all(|p| *p == 300));
+        assert!(power[31..89].iter().all(|p| *p == 100));
+        assert!(power[91..119].iter().all(|p| *p == 300));
+    }
+
+    #[test]
+    fn ramps_up_stepwise() {
+        let profile = Profile::Ramp { start: 100.0, step: 20.0, step_duration: 60_000.0 };
+        assert_eq!(profile.power_at(0.0), 100.0);
+        assert_eq!(profile.power_at(125_000.0), 140.0);
+
+        let mut sensor = SimulatedSensor::new(profile).with_seed(3);
+        let samples = run(&mut sensor, 300);
+        assert_eq!(samples[10].power, 100);
+        assert_eq!(samples[70].power, 120);
+        assert_eq!(samples[290].power, 180);
+        assert!(samples[290].cadence > samples[10].cadence && samples[290].speed > samples[10].speed);
+
+        // ERG on the trainer replaces the script
+        sensor.set_power_override(Some(200.0));
+        assert!(run(&mut sensor, 10).iter().all(|s| s.power == 200));
+        sensor.set_power_override(None);
+        sensor.set_profile(Profile::Steady(0.0));
+        let idle = run(&mut sensor, 10);
+        assert!(idle.iter().all(|s| s.power == 0 && s.cadence == 0.0 && s.speed == 0.0));
+    }
+
+    #[test]
+    fn noise_is_bounded_and_reproducible() {
+        let mut first = SimulatedSensor::new(Profile::Steady(200.0)).with_noise(0.05).with_seed(7);
+        let mut second = SimulatedSensor::new(Profile::Steady(200.0)).with_noise(0.05).with_seed(7);
+        let (first, second) = (run(&mut first, 120), run(&mut second, 120));
+        assert_eq!(first, second);
+        assert!(first.iter().all(|s| s.power >= 190 && s.power <= 210));
+        assert!(first.iter().any(|s| s.power != 200));
+    }
+
+    #[test]
+    fn drops_readings_periodically() {
+        let settings = SimulatorSettings {
+            profile: Profile::Steady(150.0),
+            noise: 0.0,
+            dropouts: Some(Dropouts { every: 20_000.0, duration: 5_000.0 }),
+        };
+        let mut sensor = SimulatedSensor::from_settings(&settings).with_seed(4);
+        let received: Vec<bool> = (0..100).map(|_| sensor.tick(1000.0).is_some()).collect();
+        assert_eq!(received.iter().filter(|r| **r).count(), 75);
+        // the last 5 s of every 20 s are lost
+        assert!(received[..14].iter().all(|r| *r));
+        assert!(received[15..19].iter().all(|r| !*r));
+        assert!(received[20]);
+        assert_eq!(sensor.elapsed(), 100_000.0);
+    }
+}
@@ -0,0 +1,151 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use wasm_bindgen::prelude::*;
+
+use crate::bluetooth::bytes::{bit_test, ByteReader};
+use crate::bluetooth::device_info::DeviceDetails;
+use crate::bluetooth::gatt::{optional, BluetoothDevice, GattTransport, HEALTH_THERMOMETER_SERVICE, INTERMEDIATE_TEMPERATURE,
+                             TEMPERATURE_MEASUREMENT};
+
+const FAHRENHEIT: u32 = 0;
+const TIME_STAMP_PRESENT: u32 = 1;
+const TEMPERATURE_TYPE_PRESENT: u32 = 2;
+
+/// Year, month, day, hours, minutes, seconds
+const TIME_STAMP_LENGTH: usize = 7;
+
+/// Where the temperature is taken, Temperature Type (0x2A1D)
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TemperatureType {
+    Armpit,
+    Body,
+    Ear,
+    Finger,
+    GastroIntestinalTract,
+    Mouth,
+    Rectum,
+    Toe,
+    Tympanum,
+    Reserved(u8),
+}
+
+impl From<u8> for TemperatureType {
+    fn from(v: u8) -> Self {
+        match v {
+            0x01 => TemperatureType::Armpit,
+            0x02 => TemperatureType::Body,
+            0x03 => TemperatureType::Ear,
+            0x04 => TemperatureType::Finger,
+            0x05 => TemperatureType::GastroIntestinalTract,
+            0x06 => TemperatureType::Mouth,
+            0x07 => TemperatureType::Rectum,
+            0x08 => TemperatureType::Toe,
+            0x09 => TemperatureType::Tympanum,
+            other => TemperatureType::Reserved(other),
+        }
+    }
+}
+
+/// Decoded Temperature Measurement (0x2A1C) or Intermediate Temperature (0x2A1E)
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TemperatureMeasurement {
+    /// Degrees Celsius, converted if the thermometer reports Fahrenheit
+    pub celsius: f32,
+    pub temperature_type: Option<TemperatureType>,
+}
+
+impl TemperatureMeasurement {
+    pub fn parse(data: &[u8]) -> Option<TemperatureMeasurement> {
+        let mut r = ByteReader::new(data);
+        let flags = r.u8()? as u32;
+        let value = r.float()?;
+        let celsius = if bit_test(flags, FAHRENHEIT) { (value - 32.0) * 5.0 / 9.0 } else { value };
+        if bit_test(flags, TIME_STAMP_PRESENT) {
+            r.skip(TIME_STAMP_LENGTH)?;
+        }
+        let temperature_type = if bit_test(flags, TEMPERATURE_TYPE_PRESENT) {
+            Some(TemperatureType::from(r.u8()?))
+        } else { None };
+        Some(TemperatureMeasurement { celsius, temperature_type })
+    }
+}
+
+/// Health Thermometer (0x1809) profile on top of a connected transport
+#[derive(Clone)]
+pub struct ThermometerProfile {
+    on_update: Rc<RefCell<dyn FnMut(&TemperatureMeasurement)>>,
+}
+
+impl ThermometerProfile {
+    pub fn new<F: 'static>(on_update: F) -> ThermometerProfile
+    where F: FnMut(&TemperatureMeasurement) {
+        ThermometerProfile { on_update: Rc::new(RefCell::new(on_update)) }
+    }
+
+    /// Final readings are indicated, intermediate ones notified while measuring
+    pub fn attach(&self, gatt: &dyn GattTransport) {
+        for &characteristic in &[TEMPERATURE_MEASUREMENT, INTERMEDIATE_TEMPERATURE] {
+            let on_update = self.on_update.clone();
+            gatt.subscribe(HEALTH_THERMOMETER_SERVICE, characteristic, Box::new(move |bytes| {
+                if let Some(measurement) = TemperatureMeasurement::parse(bytes) {
+                    (on_update.borrow_mut())(&measurement);
+                }
+            }), optional());
+        }
+    }
+}
+
+/// Health Thermometer (0x1809), e.g. a core body temperature sensor
+pub struct Thermometer {
+    thermometer: BluetoothDevice,
+}
+
+impl Thermometer {
+    pub fn new<F: 'static, D: 'static, G: 'static>(on_update: F, on_details: D, on_state: G) -> Thermometer
+    where F: FnMut(&TemperatureMeasurement), D: FnMut(DeviceDetails), G: FnMut(&JsValue) {
+        let profile = ThermometerProfile::new(on_update);
+        let thermometer = BluetoothDevice::new(&[HEALTH_THERMOMETER_SERVICE], &[], move |gatt| profile.attach(gatt),
+                                               on_details, on_state);
+        Thermometer { thermometer }
+    }
+
+    pub fn reconnect(&self) {
+        self.thermometer.connect();
+    }
+
+    pub fn resume(&self, id: Option<String>) {
+        self.thermometer.resume(id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_celsius_with_type() {
+        // 3752 * 10^-2, body
+        let m = TemperatureMeasurement::parse(&[0x04, 0xA8, 0x0E, 0x00, 0xFE, 0x02]).unwrap();
+        assert!((m.celsius - 37.52).abs() < 1e-4);
+        assert_eq!(m.temperature_type, Some(TemperatureType::Body));
+    }
+
+    #[test]
+    fn converts_fahrenheit_and_skips_time_stamp() {
+        // 995 * 10^-1 F, time stamp 2024-05-01 10:20:30
+        let m = TemperatureMeasurement::parse(&[
+            0x03, 0xE3, 0x03, 0x00, 0xFF, 0xE8, 0x07, 0x05, 0x01, 0x0A, 0x14, 0x1E,
+        ]).unwrap();
+        assert!((m.celsius - 37.5).abs() < 1e-4);
+        assert_eq!(m.temperature_type, None);
+    }
+
+    #[test]
+    fn rejects_special_values() {
+        // NaN, then negative mantissa -1 * 10^0 is a valid reading
+        assert_eq!(TemperatureMeasurement::parse(&[0x00, 0xFF, 0xFF, 0x7F, 0x00]), None);
+        let m = TemperatureMeasurement::parse(&[0x00, 0xFF, 0xFF, 0xFF, 0x00]).unwrap();
+        assert_eq!(m.celsius, -1.0);
+    }
+}
@@ -0,0 +1,131 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::{ElemBuilder, FieldSelector, HandlerImpact, Msg, SizedStr, Sizing, Vec4};
+use crate::bluetooth::calibration::{CalibrationKind, CalibrationStep};
+use crate::bluetooth::registry::SensorRegistry;
+use crate::components::{Component, UserEvent};
+use crate::messaging::HandlersBean;
+
+const WIDTH: i32 = 420;
+const ROW_HEIGHT: i32 = 24;
+const BUTTON_WIDTH: i32 = 120;
+
+/// Guided power meter and trainer calibration
+pub struct CalibrationPanel {
+    registry: Rc<RefCell<SensorRegistry>>,
+    root: usize,
+    status: usize,
+    primary: usize,
+    secondary: usize,
+}
+
+impl CalibrationPanel {
+    pub fn new(registry: Rc<RefCell<SensorRegistry>>) -> CalibrationPanel {
+        CalibrationPanel {
+            registry,
+            root: 0,
+            status: 0,
+            primary: 0,
+            secondary: 0,
+        }
+    }
+
+    fn status_text(&self, step: CalibrationStep) -> String {
+        let instruction = self.registry.borrow().calibration_instruction();
+        match step {
+            CalibrationStep::Idle => "Calibration".to_string(),
+            CalibrationStep::Instruct(kind) => format!("{}: {}", kind.label(), instruction),
+            CalibrationStep::WaitForSpeed(target) =>
+                format!("{} {:.0}-{:.0} km/h", instruction, target.low * 3.6, target.high * 3.6),
+            CalibrationStep::Done(result) => {
+                let mut text = format!("{}: {}", result.kind.label(), instruction);
+                if let Some(offset) = result.offset {
+                    text = format!("{}, offset {}", text, offset);
+                }
+                if let Some(coast_time) = result.coast_time {
+                    text = format!("{}, coasted {:.1} s", text, coast_time / 1000.0);
+                }
+                text
+            }
+            _ => instruction.to_string(),
+        }
+    }
+
+    /// Labels of the two buttons
+    fn button_texts(step: CalibrationStep) -> (&'static str, &'static str) {
+        match step {
+            CalibrationStep::Idle => ("Zero offset", "Spin-down"),
+            CalibrationStep::Instruct(_) => ("Ready", "Cancel"),
+            CalibrationStep::Done(_) => ("Close", ""),
+            _ => ("", "Cancel"),
+        }
+    }
+
+    fn update(&self, step: CalibrationStep, ui: &HandlersBean) {
+        let (primary, secondary) = Self::button_texts(step);
+        ui.set(self.status, FieldSelector::LabelText(SizedStr::sizify(&self.status_text(step))));
+        ui.set(self.primary, FieldSelector::LabelText(SizedStr::sizify(primary)));
+        ui.set(self.secondary, FieldSelector::LabelText(SizedStr::sizify(secondary)));
+    }
+
+    fn add_row(&self, ui: &mut HandlersBean, x: i32, width: i32, text: &str) -> usize {
+        let row = ElemBuilder::new(x, 0, width, ROW_HEIGHT)
+            .with_background(&[0.0, 0.0, 0.0, 1.0])
+            .with_label(text, "Roboto-Light", 16.0, Vec4::from([1.0, 1.0, 1.0, 1.0]))
+            .build();
+        let id = ui.add_element(row, self.root).unwrap();
+        ui.add_bind(self.root, id, Box::new(move |fs: &FieldSelector| {
+            if let FieldSelector::X(root_x) = *fs {
+                return Some(vec![FieldSelector::X(root_x + x)]);
+            } else if let FieldSelector::Y(y) = *fs {
+                return Some(vec![FieldSelector::Y(y)]);
+            }
+            None
+        }));
+        id
+    }
+}
+
+impl Component for CalibrationPanel {
+    fn initialize(&mut self, parent: usize, ui: &mut HandlersBean) -> usize {
+        let root = ElemBuilder::new(0, 0, WIDTH + 2 * BUTTON_WIDTH, ROW_HEIGHT).build();
+        self.root = ui.add_element(root, parent).unwrap();
+
+        let (primary, secondary) = Self::button_texts(CalibrationStep::Idle);
+        self.status = self.add_row(ui, 0, WIDTH, &self.status_text(CalibrationStep::Idle));
+        self.primary = self.add_row(ui, WIDTH, BUTTON_WIDTH, primary);
+        self.secondary = self.add_row(ui, WIDTH + BUTTON_WIDTH, BUTTON_WIDTH, secondary);
+
+        let registry = self.registry.clone();
+        ui.register_handler(self.primary, Msg::MouseDown(0, 0), Box::new(move |_msg| {
+            let mut registry = registry.borrow_mut();
+            match registry.calibration_step() {
+                CalibrationStep::Idle => registry.start_calibration(CalibrationKind::ZeroOffset),
+                CalibrationStep::Instruct(_) => registry.confirm_calibration(),
+                CalibrationStep::Done(_) => registry.close_calibration(),
+                _ => {}
+            }
+            HandlerImpact::None
+        }));
+        let registry = self.registry.clone();
+        ui.register_handler(self.secondary, Msg::MouseDown(0, 0), Box::new(move |_msg| {
+            let mut registry = registry.borrow_mut();
+            match registry.calibration_step() {
+                CalibrationStep::Idle => registry.start_calibration(CalibrationKind::SpinDown),
+                CalibrationStep::Done(_) => {}
+                _ => registry.close_calibration(),
+            }
+            HandlerImpact::None
+        }));
+
+        self.root
+    }
+
+    fn handle(&mut self, event: &UserEvent, ui: &HandlersBean) -> Option<Vec<UserEvent>> {
+        if let UserEvent::CalibrationChanged(step) = event {
+            self.update(*step, ui);
+        }
+        None
+    }
+}
@@ -0,0 +1,144 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::{ElemBuilder, FieldSelector, HandlerImpact, Msg, SizedStr, Sizing, Store, Vec4};
+use crate::fields::duration_text;
+use crate::components::{Component, UserEvent};
+use crate::cp_model::{CpFit, CpModel};
+use crate::messaging::HandlersBean;
+use crate::power_curve::RECENT_DAYS;
+
+/// 'F' fits the critical power models
+pub const FIT_KEY: u32 = 70;
+
+const WIDTH: i32 = 540;
+const ROW_HEIGHT: i32 = 24;
+const BUTTON_WIDTH: i32 = 80;
+
+/// Critical power models fitted to the recent bests, one of them can be taken over into the profile
+pub struct CpFitPanel {
+    store: Rc<RefCell<Store>>,
+    /// 2- and 3-parameter fit of the last request
+    fits: Rc<RefCell<[Option<CpFit>; 2]>>,
+    root: usize,
+    status: usize,
+    summaries: Vec<usize>,
+    efforts: Vec<usize>,
+    buttons: Vec<usize>,
+}
+
+impl CpFitPanel {
+    pub fn new(store: Rc<RefCell<Store>>) -> CpFitPanel {
+        CpFitPanel {
+            store,
+            fits: Rc::new(RefCell::new([None, None])),
+            root: 0,
+            status: 0,
+            summaries: Vec::new(),
+            efforts: Vec::new(),
+            buttons: Vec::new(),
+        }
+    }
+
+    fn model_name(model: CpModel) -> &'static str {
+        match model {
+            CpModel::TwoParameter => "2-parameter",
+            CpModel::ThreeParameter => "3-parameter",
+        }
+    }
+
+    fn summary_text(model: CpModel, fit: &Option<CpFit>) -> String {
+        let fit = match fit {
+            Some(fit) => fit,
+            None => return format!("{}: not enough efforts", Self::model_name(model)),
+        };
+        let pmax = fit.pmax.map(|p| format!(", Pmax {:.0} W", p)).unwrap_or_default();
+        format!("{}: CP {:.0} W, W' {:.1} kJ{}, FTP {:.0} W, R² {:.3}, RMSE {:.1} W",
+                Self::model_name(model), fit.cp, fit.w_prime / 1000.0, pmax, fit.ftp, fit.r_squared, fit.rmse)
+    }
+
+    fn efforts_text(fit: &Option<CpFit>) -> String {
+        let efforts = match fit {
+            Some(fit) => fit.efforts.iter()
+                .map(|(duration, power)| format!("{} {:.0} W", duration_text(*duration), power))
+                .collect::<Vec<_>>()
+                .join(", "),
+            None => String::new(),
+        };
+        format!("  {}", efforts)
+    }
+
+    fn applied_text(fit: &CpFit) -> String {
+        format!("Applied {} fit: CP {:.0} W, W' {:.1} kJ, FTP {:.0} W, W' balance of this ride recomputed",
+                Self::model_name(fit.model), fit.cp, fit.w_prime / 1000.0, fit.ftp)
+    }
+
+    fn update(&self, ui: &HandlersBean) {
+        let fits = self.fits.borrow();
+        let models = [CpModel::TwoParameter, CpModel::ThreeParameter];
+        for (k, fit) in fits.iter().enumerate() {
+            ui.set(self.summaries[k], FieldSelector::LabelText(SizedStr::sizify(&Self::summary_text(models[k], fit))));
+            ui.set(self.efforts[k], FieldSelector::LabelText(SizedStr::sizify(&Self::efforts_text(fit))));
+            let button = if fit.is_some() { "Apply" } else { "" };
+            ui.set(self.buttons[k], FieldSelector::LabelText(SizedStr::sizify(button)));
+        }
+        let status = format!("Critical power of the last {:.0} days, apply a fit to update CP, W' and FTP", RECENT_DAYS);
+        ui.set(self.status, FieldSelector::LabelText(SizedStr::sizify(&status)));
+    }
+
+    fn add_row(&self, ui: &mut HandlersBean, x: i32, y: i32, width: i32, text: &str) -> usize {
+        let row = ElemBuilder::new(x, y, width, ROW_HEIGHT)
+            .with_background(&[0.0, 0.0, 0.0, 1.0])
+            .with_label(text, "Roboto-Light", 16.0, Vec4::from([1.0, 1.0, 1.0, 1.0]))
+            .build();
+        let id = ui.add_element(row, self.root).unwrap();
+        ui.add_bind(self.root, id, Box::new(move |fs: &FieldSelector| {
+            if let FieldSelector::X(root_x) = *fs {
+                return Some(vec![FieldSelector::X(root_x + x)]);
+            } else if let FieldSelector::Y(root_y) = *fs {
+                return Some(vec![FieldSelector::Y(root_y + y)]);
+            }
+            None
+        }));
+        id
+    }
+}
+
+impl Component for CpFitPanel {
+    fn initialize(&mut self, parent: usize, ui: &mut HandlersBean) -> usize {
+        let root = ElemBuilder::new(0, 0, WIDTH + BUTTON_WIDTH, 5 * ROW_HEIGHT).build();
+        self.root = ui.add_element(root, parent).unwrap();
+
+        self.status = self.add_row(ui, 0, 4 * ROW_HEIGHT, WIDTH + BUTTON_WIDTH, "Critical power: F to fit");
+        for k in 0..2 {
+            let y = (3 - 2 * k) * ROW_HEIGHT;
+            let summary = self.add_row(ui, 0, y, WIDTH, " ");
+            let efforts = self.add_row(ui, 0, y - ROW_HEIGHT, WIDTH + BUTTON_WIDTH, " ");
+            let button = self.add_row(ui, WIDTH, y, BUTTON_WIDTH, " ");
+            self.summaries.push(summary);
+            self.efforts.push(efforts);
+            self.buttons.push(button);
+
+            let (store, fits, status) = (self.store.clone(), self.fits.clone(), self.status);
+            ui.register_handler(button, Msg::MouseDown(0, 0), Box::new(move |_msg| {
+                let fit = match &fits.borrow()[k as usize] {
+                    Some(fit) => fit.clone(),
+                    None => return HandlerImpact::None,
+                };
+                store.borrow().state.apply_cp_fit(&fit);
+                HandlerImpact::Set(status, FieldSelector::LabelText(SizedStr::sizify(&Self::applied_text(&fit))))
+            }));
+        }
+
+        self.root
+    }
+
+    fn handle(&mut self, event: &UserEvent, ui: &HandlersBean) -> Option<Vec<UserEvent>> {
+        if let UserEvent::CpFitRequested = event {
+            let (two_parameter, three_parameter) = self.store.borrow().state.fit_critical_power();
+            *self.fits.borrow_mut() = [two_parameter, three_parameter];
+            self.update(ui);
+        }
+        None
+    }
+}
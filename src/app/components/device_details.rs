@@ -0,0 +1,103 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::{ElemBuilder, FieldSelector, SizedStr, Sizing, Vec4};
+use crate::bluetooth::device_info::LOW_BATTERY;
+use crate::bluetooth::registry::{SensorInfo, SensorRegistry, SensorRole};
+use crate::components::{Component, UserEvent};
+use crate::messaging::HandlersBean;
+
+const ROW_WIDTH: i32 = 520;
+const ROW_HEIGHT: i32 = 24;
+
+/// Device information and battery level of the sensor serving each role,
+/// rows of sensors with a low battery are highlighted
+pub struct DeviceDetailsPanel {
+    registry: Rc<RefCell<SensorRegistry>>,
+    root: usize,
+    rows: Vec<(SensorRole, usize)>,
+}
+
+impl DeviceDetailsPanel {
+    pub fn new(registry: Rc<RefCell<SensorRegistry>>) -> DeviceDetailsPanel {
+        DeviceDetailsPanel {
+            registry,
+            root: 0,
+            rows: Vec::new(),
+        }
+    }
+
+    fn row_text(role: SensorRole, info: &SensorInfo) -> String {
+        let device = &info.device;
+        let mut text = role.label().to_string();
+        for value in [&info.name, &device.manufacturer, &device.model].iter().filter_map(|v| v.as_ref()) {
+            text = format!("{}  {}", text, value);
+        }
+        if let Some(serial) = &device.serial {
+            text = format!("{}  SN {}", text, serial);
+        }
+        if let Some(firmware) = &device.firmware_revision {
+            text = format!("{}  fw {}", text, firmware);
+        }
+        if let Some(hardware) = &device.hardware_revision {
+            text = format!("{}  hw {}", text, hardware);
+        }
+        if let Some(battery) = info.battery {
+            text = format!("{}  battery {}%", text, battery);
+        }
+        text
+    }
+
+    fn battery_color(battery: Option<u8>) -> Vec4 {
+        match battery {
+            Some(level) if level < LOW_BATTERY => Vec4::from([1.0, 0.3, 0.3, 1.0]),
+            _ => Vec4::from([0.8, 0.8, 0.8, 1.0]),
+        }
+    }
+
+    fn update_row(&self, role: SensorRole, ui: &HandlersBean) {
+        if let Some((_, row)) = self.rows.iter().find(|(r, _)| *r == role) {
+            let info = self.registry.borrow().info(role);
+            ui.set(*row, FieldSelector::LabelText(SizedStr::sizify(&Self::row_text(role, &info))));
+            ui.set(*row, FieldSelector::LabelColor(Self::battery_color(info.battery)));
+        }
+    }
+}
+
+impl Component for DeviceDetailsPanel {
+    fn initialize(&mut self, parent: usize, ui: &mut HandlersBean) -> usize {
+        let height = ROW_HEIGHT * SensorRole::ALL.len() as i32;
+        let root = ElemBuilder::new(0, 0, ROW_WIDTH, height).build();
+        self.root = ui.add_element(root, parent).unwrap();
+
+        for (k, role) in SensorRole::ALL.iter().enumerate() {
+            let offset = k as i32 * ROW_HEIGHT;
+            let row = ElemBuilder::new(0, offset, ROW_WIDTH, ROW_HEIGHT)
+                .with_background(&[0.0, 0.0, 0.0, 1.0])
+                .with_label(role.label(), "Roboto-Light", 14.0, Self::battery_color(None))
+                .build();
+            let row_id = ui.add_element(row, self.root).unwrap();
+            ui.add_bind(self.root, row_id, Box::new(move |fs: &FieldSelector| {
+                if let FieldSelector::X(x) = *fs {
+                    return Some(vec![FieldSelector::X(x)]);
+                } else if let FieldSelector::Y(y) = *fs {
+                    return Some(vec![FieldSelector::Y(y + offset)]);
+                }
+                None
+            }));
+            self.rows.push((*role, row_id));
+        }
+
+        self.root
+    }
+
+    fn handle(&mut self, event: &UserEvent, ui: &HandlersBean) -> Option<Vec<UserEvent>> {
+        match event {
+            UserEvent::BatteryChanged(role, _)
+            | UserEvent::BatteryLow(role, _)
+            | UserEvent::DeviceInfoChanged(role) => self.update_row(*role, ui),
+            _ => {}
+        }
+        None
+    }
+}
@@ -0,0 +1,89 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::{ElemBuilder, FieldSelector, SizedStr, Sizing, Vec4};
+use crate::components::{Component, UserEvent};
+use crate::gearing::{Gear, VirtualGearing};
+use crate::messaging::HandlersBean;
+
+const WIDTH: i32 = 200;
+const GEAR_HEIGHT: i32 = 40;
+const DETAIL_HEIGHT: i32 = 24;
+
+/// Current virtual gear: chainring x sprocket, ratio and development
+pub struct GearIndicator {
+    gearing: Rc<RefCell<VirtualGearing>>,
+    root: usize,
+    gear: usize,
+    detail: usize,
+}
+
+impl GearIndicator {
+    pub fn new(gearing: Rc<RefCell<VirtualGearing>>) -> GearIndicator {
+        GearIndicator {
+            gearing,
+            root: 0,
+            gear: 0,
+            detail: 0,
+        }
+    }
+
+    fn gear_text(gear: &Gear) -> String {
+        format!("{} x {}", gear.chainring, gear.sprocket)
+    }
+
+    fn detail_text(&self, gear: &Gear) -> String {
+        if !gear.enabled {
+            return "Virtual gearing off".to_string();
+        }
+        format!("{:.2}  {:.1} m", gear.ratio(), self.gearing.borrow().development())
+    }
+
+    fn gear_color(gear: &Gear) -> Vec4 {
+        if gear.enabled {
+            Vec4::from([1.0, 1.0, 1.0, 1.0])
+        } else {
+            Vec4::from([0.5, 0.5, 0.5, 1.0])
+        }
+    }
+
+    fn add_label(&self, ui: &mut HandlersBean, y: i32, height: i32, text: &str, size: f32, color: Vec4) -> usize {
+        let label = ElemBuilder::new(0, y, WIDTH, height)
+            .with_background(&[0.0, 0.0, 0.0, 1.0])
+            .with_label(text, "Roboto-Light", size, color)
+            .build();
+        let id = ui.add_element(label, self.root).unwrap();
+        ui.add_bind(self.root, id, Box::new(move |fs: &FieldSelector| {
+            if let FieldSelector::X(x) = *fs {
+                return Some(vec![FieldSelector::X(x)]);
+            } else if let FieldSelector::Y(root_y) = *fs {
+                return Some(vec![FieldSelector::Y(root_y + y)]);
+            }
+            None
+        }));
+        id
+    }
+}
+
+impl Component for GearIndicator {
+    fn initialize(&mut self, parent: usize, ui: &mut HandlersBean) -> usize {
+        let root = ElemBuilder::new(0, 0, WIDTH, GEAR_HEIGHT + DETAIL_HEIGHT).build();
+        self.root = ui.add_element(root, parent).unwrap();
+
+        let gear = self.gearing.borrow().gear();
+        self.gear = self.add_label(ui, DETAIL_HEIGHT, GEAR_HEIGHT, &Self::gear_text(&gear), 32.0, Self::gear_color(&gear));
+        self.detail = self.add_label(ui, 0, DETAIL_HEIGHT, &self.detail_text(&gear), 16.0, Self::gear_color(&gear));
+
+        self.root
+    }
+
+    fn handle(&mut self, event: &UserEvent, ui: &HandlersBean) -> Option<Vec<UserEvent>> {
+        if let UserEvent::GearChanged(gear) = event {
+            ui.set(self.gear, FieldSelector::LabelText(SizedStr::sizify(&Self::gear_text(gear))));
+            ui.set(self.gear, FieldSelector::LabelColor(Self::gear_color(gear)));
+            ui.set(self.detail, FieldSelector::LabelText(SizedStr::sizify(&self.detail_text(gear))));
+            ui.set(self.detail, FieldSelector::LabelColor(Self::gear_color(gear)));
+        }
+        None
+    }
+}
@@ -2,6 +2,7 @@ use crate::{ElemBuilder, FieldSelector, SizedStr, Sizing, Vec4};
 use crate::components::{Component, UserEvent};
 use crate::components::UserEvent::HrChanged;
 use crate::messaging::HandlersBean;
+use crate::quality::{Channel, Quality};
 
 pub struct HRMDisplay {
     root_el: usize,
@@ -18,6 +19,10 @@ impl Component for HRMDisplay {
     fn handle(&mut self, event: &UserEvent, ui: &HandlersBean) -> Option<Vec<UserEvent>> {
         if let HrChanged(hr) = event {
             ui.set(self.text, FieldSelector::LabelText(SizedStr::sizify(format!("{}", hr).as_str())))
+        } else if let UserEvent::QualityChanged(Channel::HeartRate, quality) = event {
+            // stale reading stays visible but greyed out
+            let color = if *quality == Quality::Good { [1.0, 1.0, 1.0, 1.0] } else { [0.5, 0.5, 0.5, 1.0] };
+            ui.set(self.text, FieldSelector::LabelColor(Vec4::from(color)))
         }
         None
     }
@@ -0,0 +1,143 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::{ElemBuilder, FieldSelector, SizedStr, Sizing, Vec4};
+use crate::components::{Component, UserEvent};
+use crate::messaging::HandlersBean;
+use crate::quality::{Channel, Quality};
+use crate::timedata::{Aggregates, RecordField, RideRecord, HR_WINDOW, POWER_WINDOWS};
+
+const TILE_WIDTH: i32 = 90;
+const CAPTION_HEIGHT: i32 = 20;
+const VALUE_HEIGHT: i32 = 44;
+const NO_VALUE: &str = "--";
+
+#[derive(Copy, Clone, PartialEq)]
+enum Metric {
+    /// Mean over the window, ms
+    Rolling(RecordField, usize),
+    SessionMean(RecordField),
+    SessionMax(RecordField),
+}
+
+impl Metric {
+    fn field(&self) -> RecordField {
+        match self {
+            Metric::Rolling(field, _) | Metric::SessionMean(field) | Metric::SessionMax(field) => *field,
+        }
+    }
+
+    fn value(&self, aggregates: &Aggregates) -> Option<f32> {
+        match self {
+            Metric::Rolling(field, duration) => aggregates.window(*field, *duration).and_then(|w| w.mean()),
+            Metric::SessionMean(field) => aggregates.session(*field).and_then(|s| s.mean()),
+            Metric::SessionMax(field) => aggregates.session(*field).and_then(|s| s.max),
+        }
+    }
+}
+
+/// Caption and the metric a tile shows
+const TILES: [(&str, Metric); 8] = [
+    ("3s W", Metric::Rolling(RecordField::Power, POWER_WINDOWS[0])),
+    ("10s W", Metric::Rolling(RecordField::Power, POWER_WINDOWS[1])),
+    ("30s W", Metric::Rolling(RecordField::Power, POWER_WINDOWS[2])),
+    ("Avg W", Metric::SessionMean(RecordField::Power)),
+    ("Max W", Metric::SessionMax(RecordField::Power)),
+    ("HR", Metric::Rolling(RecordField::HeartRate, HR_WINDOW)),
+    ("Avg HR", Metric::SessionMean(RecordField::HeartRate)),
+    ("Max HR", Metric::SessionMax(RecordField::HeartRate)),
+];
+
+/// Tiles with rolling average power and heart rate and their session averages and maxima,
+/// read from the aggregates the ride record keeps up to date
+pub struct MetricTiles {
+    record: Rc<RefCell<RideRecord>>,
+    root: usize,
+    values: Vec<(Metric, usize)>,
+}
+
+impl MetricTiles {
+    pub fn new(record: Rc<RefCell<RideRecord>>) -> MetricTiles {
+        MetricTiles {
+            record,
+            root: 0,
+            values: Vec::new(),
+        }
+    }
+
+    fn add_label(&self, ui: &mut HandlersBean, x: i32, y: i32, height: i32, text: &str, size: f32) -> usize {
+        let label = ElemBuilder::new(x, y, TILE_WIDTH, height)
+            .with_background(&[0.0, 0.0, 0.0, 1.0])
+            .with_label(text, "Roboto-Light", size, Self::quality_color(Quality::NoData))
+            .build();
+        let id = ui.add_element(label, self.root).unwrap();
+        ui.add_bind(self.root, id, Box::new(move |fs: &FieldSelector| {
+            if let FieldSelector::X(root_x) = *fs {
+                return Some(vec![FieldSelector::X(root_x + x)]);
+            } else if let FieldSelector::Y(root_y) = *fs {
+                return Some(vec![FieldSelector::Y(root_y + y)]);
+            }
+            None
+        }));
+        id
+    }
+
+    fn quality_color(quality: Quality) -> Vec4 {
+        if quality == Quality::Good {
+            Vec4::from([1.0, 1.0, 1.0, 1.0])
+        } else {
+            Vec4::from([0.5, 0.5, 0.5, 1.0])
+        }
+    }
+
+    fn update(&self, field: RecordField, ui: &HandlersBean) {
+        let record = self.record.borrow();
+        for (metric, id) in self.values.iter().filter(|(m, _)| m.field() == field) {
+            let text = metric.value(record.aggregates()).map_or(NO_VALUE.to_string(), |v| format!("{:.0}", v));
+            ui.set(*id, FieldSelector::LabelText(SizedStr::sizify(&text)));
+        }
+    }
+
+    fn field_of(channel: Channel) -> Option<RecordField> {
+        match channel {
+            Channel::HeartRate => Some(RecordField::HeartRate),
+            Channel::Power => Some(RecordField::Power),
+            _ => None,
+        }
+    }
+}
+
+impl Component for MetricTiles {
+    fn initialize(&mut self, parent: usize, ui: &mut HandlersBean) -> usize {
+        let width = TILE_WIDTH * TILES.len() as i32;
+        let root = ElemBuilder::new(0, 0, width, CAPTION_HEIGHT + VALUE_HEIGHT).build();
+        self.root = ui.add_element(root, parent).unwrap();
+
+        for (k, (caption, metric)) in TILES.iter().enumerate() {
+            let x = k as i32 * TILE_WIDTH;
+            self.add_label(ui, x, 0, CAPTION_HEIGHT, caption, 14.0);
+            let value = self.add_label(ui, x, CAPTION_HEIGHT, VALUE_HEIGHT, NO_VALUE, 28.0);
+            self.values.push((*metric, value));
+        }
+
+        self.root
+    }
+
+    fn handle(&mut self, event: &UserEvent, ui: &HandlersBean) -> Option<Vec<UserEvent>> {
+        match event {
+            UserEvent::HrChanged(_) => self.update(RecordField::HeartRate, ui),
+            UserEvent::PowerChanged(_) => self.update(RecordField::Power, ui),
+            UserEvent::QualityChanged(channel, quality) => {
+                if let Some(field) = Self::field_of(*channel) {
+                    // a gap empties the rolling windows, the session values stay
+                    self.update(field, ui);
+                    for (_, id) in self.values.iter().filter(|(m, _)| m.field() == field) {
+                        ui.set(*id, FieldSelector::LabelColor(Self::quality_color(*quality)));
+                    }
+                }
+            }
+            _ => {}
+        }
+        None
+    }
+}
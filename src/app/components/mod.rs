@@ -1,11 +1,58 @@
+use crate::ant::muscle_oxygen::MuscleOxygen;
+use crate::bluetooth::calibration::CalibrationStep;
+use crate::bluetooth::ftms::ControlResponse;
+use crate::bluetooth::power::PowerMeasurement;
+use crate::bluetooth::registry::{ConnectionState, SensorRole};
+use crate::gearing::Gear;
+use crate::power_curve::PowerRecord;
+use crate::quality::{Channel, Quality};
 use crate::messaging::HandlersBean;
+use crate::timedata::Hrv;
 
+pub mod calibration_panel;
+pub mod cp_fit_panel;
+pub mod device_details;
+pub mod gear_indicator;
 pub mod hrm_display;
+pub mod metric_tiles;
+pub mod pedaling_display;
+pub mod physiology_display;
+pub mod record_banner;
+pub mod sensor_panel;
 pub mod slidebox;
+pub mod training_display;
+pub mod wbal_gauge;
 
 #[derive(Copy, Clone, Debug)]
 pub enum UserEvent {
     HrChanged(i32),
+    HrvChanged(Hrv),
+    PowerChanged(PowerMeasurement),
+    /// m/s
+    SpeedChanged(f32),
+    /// rpm
+    CadenceChanged(f32),
+    /// °C
+    TemperatureChanged(f32),
+    MuscleOxygenChanged(MuscleOxygen),
+    GearChanged(Gear),
+    /// Trainer resistance level, unitless
+    ResistanceChanged(i16),
+    QualityChanged(Channel, Quality),
+    /// Balance, torque or force vectors in `PedalingData` were updated
+    PedalingChanged,
+    /// New best at one of `PR_DURATIONS`
+    PowerRecord(PowerRecord),
+    /// Rider asked to fit the critical power models to the recent bests
+    CpFitRequested,
+    TrainerResponse(ControlResponse),
+    SensorStateChanged(SensorRole, ConnectionState),
+    /// Percent
+    BatteryChanged(SensorRole, u8),
+    /// Battery level in percent dropped below `LOW_BATTERY`
+    BatteryLow(SensorRole, u8),
+    DeviceInfoChanged(SensorRole),
+    CalibrationChanged(CalibrationStep),
     ProcessDrag((usize, i32, i32)),
     ProcessDrop((usize, i32, i32)),
     Clicked(usize),
@@ -0,0 +1,138 @@
+use std::cell::RefCell;
+use std::f32::consts::PI;
+use std::rc::Rc;
+
+use crate::{ElemBuilder, FieldSelector, LineStyle, ShapeSegment, SizedStr, Sizing, Vec4};
+use crate::components::{Component, UserEvent};
+use crate::messaging::HandlersBean;
+use crate::timedata::{ForceVector, PedalingData};
+
+const LABEL_WIDTH: i32 = 120;
+const LABEL_HEIGHT: i32 = 24;
+const PLOT_SIZE: i32 = 160;
+/// Force vectors drawn in the pedal stroke plot, ms
+const STROKE_DURATION: usize = 3000;
+/// Angular resolution of the plot, degrees
+const SECTOR: f32 = 10.0;
+
+/// Left/right balance, torque per revolution and a polar plot of the pedal stroke
+pub struct PedalingDisplay {
+    pedaling: Rc<RefCell<PedalingData>>,
+    root: usize,
+    balance: usize,
+    torque: usize,
+    plot: usize,
+}
+
+impl PedalingDisplay {
+    pub fn new(pedaling: Rc<RefCell<PedalingData>>) -> PedalingDisplay {
+        PedalingDisplay {
+            pedaling,
+            root: 0,
+            balance: 0,
+            torque: 0,
+            plot: 0,
+        }
+    }
+
+    /// Average force of each sector, top dead centre up and clockwise as seen from the drive side.
+    /// Radius is relative to the strongest sector, pulling up is drawn at the centre.
+    fn stroke_shape(stroke: &[ForceVector]) -> Vec<ShapeSegment> {
+        let sectors = (360.0 / SECTOR) as usize;
+        let mut sums = vec![(0.0, 0); sectors];
+        for v in stroke {
+            let k = (v.angle / SECTOR) as usize % sectors;
+            sums[k].0 += v.force;
+            sums[k].1 += 1;
+        }
+        let forces: Vec<(f32, f32)> = sums.iter().enumerate()
+            .filter(|(_, (_, n))| *n > 0)
+            .map(|(k, (sum, n))| ((k as f32 + 0.5) * SECTOR, (sum / *n as f32).max(0.0)))
+            .collect();
+        let max = forces.iter().map(|(_, f)| *f).fold(0.0, f32::max);
+        if max <= 0.0 {
+            return Vec::new();
+        }
+        let mut shape: Vec<ShapeSegment> = forces.iter()
+            .map(|(angle, force)| {
+                let r = 0.5 * force / max;
+                let theta = angle * PI / 180.0;
+                ShapeSegment {
+                    x: 0.5 + r * theta.sin(),
+                    y: 0.5 + r * theta.cos(),
+                    style: None,
+                    event_id: None,
+                }
+            })
+            .collect();
+        shape.push(shape[0]);
+        shape
+    }
+
+    fn update(&self, ui: &HandlersBean) {
+        let pedaling = self.pedaling.borrow();
+        if let Some(balance) = pedaling.balance.last() {
+            let text = if pedaling.balance_left {
+                format!("L {:.0} / R {:.0}", balance, 100.0 - balance)
+            } else {
+                format!("{:.0} / {:.0}", balance, 100.0 - balance)
+            };
+            ui.set(self.balance, FieldSelector::LabelText(SizedStr::sizify(&text)));
+        }
+        if let Some(torque) = pedaling.torque.last() {
+            let text = format!("{:.0} Nm", torque);
+            ui.set(self.torque, FieldSelector::LabelText(SizedStr::sizify(&text)));
+        }
+        ui.set_shape(self.plot, Self::stroke_shape(&pedaling.stroke(STROKE_DURATION)));
+    }
+
+    fn add_label(&self, ui: &mut HandlersBean, x: i32, text: &str) -> usize {
+        let label = ElemBuilder::new(x, 0, LABEL_WIDTH, LABEL_HEIGHT)
+            .with_background(&[0.0, 0.0, 0.0, 1.0])
+            .with_label(text, "Roboto-Light", 16.0, Vec4::from([1.0, 1.0, 1.0, 1.0]))
+            .build();
+        let id = ui.add_element(label, self.root).unwrap();
+        ui.add_bind(self.root, id, Box::new(move |fs: &FieldSelector| {
+            if let FieldSelector::X(root_x) = *fs {
+                return Some(vec![FieldSelector::X(root_x + x)]);
+            } else if let FieldSelector::Y(y) = *fs {
+                return Some(vec![FieldSelector::Y(y + PLOT_SIZE)]);
+            }
+            None
+        }));
+        id
+    }
+}
+
+impl Component for PedalingDisplay {
+    fn initialize(&mut self, parent: usize, ui: &mut HandlersBean) -> usize {
+        let root = ElemBuilder::new(0, 0, 2 * LABEL_WIDTH, PLOT_SIZE + LABEL_HEIGHT).build();
+        self.root = ui.add_element(root, parent).unwrap();
+
+        let plot = ElemBuilder::new(0, 0, PLOT_SIZE, PLOT_SIZE)
+            .with_background(&[0.2, 0.6, 1.0, 0.5])
+            .with_line_style(&LineStyle { color: [0.4, 0.8, 1.0, 1.0], width: 1.0, dashed: false })
+            .build();
+        self.plot = ui.add_element(plot, self.root).unwrap();
+        ui.add_bind(self.root, self.plot, Box::new(|fs: &FieldSelector| {
+            if let FieldSelector::X(x) = *fs {
+                return Some(vec![FieldSelector::X(x + LABEL_WIDTH - PLOT_SIZE / 2)]);
+            } else if let FieldSelector::Y(y) = *fs {
+                return Some(vec![FieldSelector::Y(y)]);
+            }
+            None
+        }));
+
+        self.balance = self.add_label(ui, 0, "L -- / R --");
+        self.torque = self.add_label(ui, LABEL_WIDTH, "-- Nm");
+
+        self.root
+    }
+
+    fn handle(&mut self, event: &UserEvent, ui: &HandlersBean) -> Option<Vec<UserEvent>> {
+        if let UserEvent::PedalingChanged = event {
+            self.update(ui);
+        }
+        None
+    }
+}
@@ -0,0 +1,101 @@
+use crate::{ElemBuilder, FieldSelector, SizedStr, Sizing, Vec4};
+use crate::components::{Component, UserEvent};
+use crate::messaging::HandlersBean;
+use crate::quality::{Channel, Quality};
+
+const TILE_WIDTH: i32 = 120;
+const CAPTION_HEIGHT: i32 = 20;
+const VALUE_HEIGHT: i32 = 44;
+const NO_VALUE: &str = "--";
+
+/// Caption and the channel whose reading a tile shows
+const TILES: [(&str, Channel); 3] = [
+    ("SmO2 %", Channel::SmO2),
+    ("tHb g/dl", Channel::Thb),
+    ("Temp °C", Channel::Temperature),
+];
+
+/// Tiles with the latest muscle oxygen, total hemoglobin and body temperature
+pub struct PhysiologyDisplay {
+    root: usize,
+    values: Vec<(Channel, usize)>,
+}
+
+impl PhysiologyDisplay {
+    pub fn new() -> PhysiologyDisplay {
+        PhysiologyDisplay {
+            root: 0,
+            values: Vec::new(),
+        }
+    }
+
+    fn add_label(&self, ui: &mut HandlersBean, x: i32, y: i32, height: i32, text: &str, size: f32) -> usize {
+        let label = ElemBuilder::new(x, y, TILE_WIDTH, height)
+            .with_background(&[0.0, 0.0, 0.0, 1.0])
+            .with_label(text, "Roboto-Light", size, Self::quality_color(Quality::NoData))
+            .build();
+        let id = ui.add_element(label, self.root).unwrap();
+        ui.add_bind(self.root, id, Box::new(move |fs: &FieldSelector| {
+            if let FieldSelector::X(root_x) = *fs {
+                return Some(vec![FieldSelector::X(root_x + x)]);
+            } else if let FieldSelector::Y(root_y) = *fs {
+                return Some(vec![FieldSelector::Y(root_y + y)]);
+            }
+            None
+        }));
+        id
+    }
+
+    fn quality_color(quality: Quality) -> Vec4 {
+        if quality == Quality::Good {
+            Vec4::from([1.0, 1.0, 1.0, 1.0])
+        } else {
+            Vec4::from([0.5, 0.5, 0.5, 1.0])
+        }
+    }
+
+    fn set_value(&self, channel: Channel, text: &str, ui: &HandlersBean) {
+        if let Some((_, id)) = self.values.iter().find(|(c, _)| *c == channel) {
+            ui.set(*id, FieldSelector::LabelText(SizedStr::sizify(text)));
+        }
+    }
+}
+
+impl Component for PhysiologyDisplay {
+    fn initialize(&mut self, parent: usize, ui: &mut HandlersBean) -> usize {
+        let width = TILE_WIDTH * TILES.len() as i32;
+        let root = ElemBuilder::new(0, 0, width, CAPTION_HEIGHT + VALUE_HEIGHT).build();
+        self.root = ui.add_element(root, parent).unwrap();
+
+        for (k, (caption, channel)) in TILES.iter().enumerate() {
+            let x = k as i32 * TILE_WIDTH;
+            self.add_label(ui, x, 0, CAPTION_HEIGHT, caption, 14.0);
+            let value = self.add_label(ui, x, CAPTION_HEIGHT, VALUE_HEIGHT, NO_VALUE, 32.0);
+            self.values.push((*channel, value));
+        }
+
+        self.root
+    }
+
+    fn handle(&mut self, event: &UserEvent, ui: &HandlersBean) -> Option<Vec<UserEvent>> {
+        match event {
+            UserEvent::TemperatureChanged(celsius) => self.set_value(Channel::Temperature, &format!("{:.1}", celsius), ui),
+            UserEvent::MuscleOxygenChanged(m) => {
+                if let Some(smo2) = m.smo2 {
+                    self.set_value(Channel::SmO2, &format!("{:.1}", smo2), ui);
+                }
+                if let Some(thb) = m.thb {
+                    self.set_value(Channel::Thb, &format!("{:.2}", thb), ui);
+                }
+            }
+            UserEvent::QualityChanged(channel, quality) => {
+                // stale readings stay visible but greyed out
+                if let Some((_, id)) = self.values.iter().find(|(c, _)| c == channel) {
+                    ui.set(*id, FieldSelector::LabelColor(Self::quality_color(*quality)));
+                }
+            }
+            _ => {}
+        }
+        None
+    }
+}
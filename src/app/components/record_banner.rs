@@ -0,0 +1,53 @@
+use crate::{ElemBuilder, FieldSelector, SizedStr, Sizing, Vec4};
+use crate::fields::duration_text;
+use crate::components::{Component, UserEvent};
+use crate::messaging::HandlersBean;
+use crate::power_curve::{PowerRecord, RecordScope};
+
+const WIDTH: i32 = 540;
+const HEIGHT: i32 = 24;
+
+/// Latest personal power record set during the ride
+pub struct RecordBanner {
+    label: usize,
+}
+
+impl RecordBanner {
+    pub fn new() -> RecordBanner {
+        RecordBanner { label: 0 }
+    }
+
+    fn record_text(record: &PowerRecord) -> String {
+        let scope = match record.scope {
+            RecordScope::AllTime => "all-time",
+            RecordScope::Recent => "90-day",
+        };
+        format!("New {} {} best: {:.0} W", scope, duration_text(record.duration), record.power)
+    }
+
+    fn scope_color(scope: RecordScope) -> Vec4 {
+        match scope {
+            RecordScope::AllTime => Vec4::from([1.0, 0.84, 0.0, 1.0]),
+            RecordScope::Recent => Vec4::from([0.6, 0.9, 1.0, 1.0]),
+        }
+    }
+}
+
+impl Component for RecordBanner {
+    fn initialize(&mut self, parent: usize, ui: &mut HandlersBean) -> usize {
+        let label = ElemBuilder::new(0, 0, WIDTH, HEIGHT)
+            .with_background(&[0.0, 0.0, 0.0, 1.0])
+            .with_label(" ", "Roboto-Light", 16.0, Self::scope_color(RecordScope::Recent))
+            .build();
+        self.label = ui.add_element(label, parent).unwrap();
+        self.label
+    }
+
+    fn handle(&mut self, event: &UserEvent, ui: &HandlersBean) -> Option<Vec<UserEvent>> {
+        if let UserEvent::PowerRecord(record) = event {
+            ui.set(self.label, FieldSelector::LabelText(SizedStr::sizify(&Self::record_text(record))));
+            ui.set(self.label, FieldSelector::LabelColor(Self::scope_color(record.scope)));
+        }
+        None
+    }
+}
@@ -0,0 +1,127 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::{ElemBuilder, FieldSelector, HandlerImpact, Msg, SizedStr, Sizing, Vec4};
+use crate::bluetooth::registry::{ConnectionState, SensorRegistry, SensorRole};
+use crate::components::{Component, UserEvent};
+use crate::messaging::HandlersBean;
+
+const ROW_WIDTH: i32 = 360;
+const ROW_HEIGHT: i32 = 24;
+const ANT_LABEL: &str = "ANT+ USB stick";
+
+/// One row per sensor role, click a row to connect.
+/// The last row serves the roles it can from an ANT stick.
+pub struct SensorPanel {
+    registry: Rc<RefCell<SensorRegistry>>,
+    root: usize,
+    rows: Vec<(SensorRole, usize)>,
+}
+
+impl SensorPanel {
+    pub fn new(registry: Rc<RefCell<SensorRegistry>>) -> SensorPanel {
+        SensorPanel {
+            registry,
+            root: 0,
+            rows: Vec::new(),
+        }
+    }
+
+    fn row_text(&self, role: SensorRole) -> String {
+        let info = self.registry.borrow().info(role);
+        let mut text = format!("{}  {}", role.label(), info.state.label());
+        if info.state == ConnectionState::Reconnecting && info.reconnect_attempts > 0 {
+            text = format!("{} ({})", text, info.reconnect_attempts);
+        }
+        if let Some(name) = info.name {
+            text = format!("{}  {}", text, name);
+        }
+        if let Some(battery) = info.battery {
+            text = format!("{}  {}%", text, battery);
+        }
+        text
+    }
+
+    fn state_color(state: ConnectionState) -> Vec4 {
+        match state {
+            ConnectionState::Connected => Vec4::from([0.4, 1.0, 0.4, 1.0]),
+            ConnectionState::Scanning | ConnectionState::Connecting | ConnectionState::Reconnecting =>
+                Vec4::from([1.0, 0.8, 0.2, 1.0]),
+            ConnectionState::Lost => Vec4::from([1.0, 0.3, 0.3, 1.0]),
+            ConnectionState::Disconnected => Vec4::from([0.7, 0.7, 0.7, 1.0]),
+        }
+    }
+
+    fn update_row(&self, role: SensorRole, ui: &HandlersBean) {
+        if let Some((_, row)) = self.rows.iter().find(|(r, _)| *r == role) {
+            ui.set(*row, FieldSelector::LabelText(SizedStr::sizify(&self.row_text(role))));
+            ui.set(*row, FieldSelector::LabelColor(Self::state_color(self.registry.borrow().state(role))));
+        }
+    }
+}
+
+impl Component for SensorPanel {
+    fn initialize(&mut self, parent: usize, ui: &mut HandlersBean) -> usize {
+        let height = ROW_HEIGHT * (SensorRole::ALL.len() as i32 + 1);
+        let root = ElemBuilder::new(0, 0, ROW_WIDTH, height).build();
+        self.root = ui.add_element(root, parent).unwrap();
+
+        for (k, role) in SensorRole::ALL.iter().enumerate() {
+            let offset = k as i32 * ROW_HEIGHT;
+            let row = ElemBuilder::new(0, offset, ROW_WIDTH, ROW_HEIGHT)
+                .with_background(&[0.0, 0.0, 0.0, 1.0])
+                .with_label(&self.row_text(*role), "Roboto-Light", 16.0, Self::state_color(ConnectionState::Disconnected))
+                .build();
+            let row_id = ui.add_element(row, self.root).unwrap();
+
+            ui.add_bind(self.root, row_id, Box::new(move |fs: &FieldSelector| {
+                if let FieldSelector::X(x) = *fs {
+                    return Some(vec![FieldSelector::X(x)]);
+                } else if let FieldSelector::Y(y) = *fs {
+                    return Some(vec![FieldSelector::Y(y + offset)]);
+                }
+                None
+            }));
+
+            let registry = self.registry.clone();
+            let role = *role;
+            ui.register_handler(row_id, Msg::MouseDown(0, 0), Box::new(move |_msg| {
+                registry.borrow_mut().connect(role);
+                HandlerImpact::None
+            }));
+            self.rows.push((role, row_id));
+        }
+
+        let offset = ROW_HEIGHT * SensorRole::ALL.len() as i32;
+        let ant_row = ElemBuilder::new(0, offset, ROW_WIDTH, ROW_HEIGHT)
+            .with_background(&[0.0, 0.0, 0.0, 1.0])
+            .with_label(ANT_LABEL, "Roboto-Light", 16.0, Self::state_color(ConnectionState::Disconnected))
+            .build();
+        let ant_id = ui.add_element(ant_row, self.root).unwrap();
+        ui.add_bind(self.root, ant_id, Box::new(move |fs: &FieldSelector| {
+            if let FieldSelector::X(x) = *fs {
+                return Some(vec![FieldSelector::X(x)]);
+            } else if let FieldSelector::Y(y) = *fs {
+                return Some(vec![FieldSelector::Y(y + offset)]);
+            }
+            None
+        }));
+        let registry = self.registry.clone();
+        ui.register_handler(ant_id, Msg::MouseDown(0, 0), Box::new(move |_msg| {
+            registry.borrow_mut().connect_ant();
+            HandlerImpact::None
+        }));
+
+        self.root
+    }
+
+    fn handle(&mut self, event: &UserEvent, ui: &HandlersBean) -> Option<Vec<UserEvent>> {
+        match event {
+            UserEvent::SensorStateChanged(role, _)
+            | UserEvent::BatteryChanged(role, _)
+            | UserEvent::DeviceInfoChanged(role) => self.update_row(*role, ui),
+            _ => {}
+        }
+        None
+    }
+}
@@ -0,0 +1,98 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::{ElemBuilder, FieldSelector, SizedStr, Sizing, Vec4};
+use crate::components::{Component, UserEvent};
+use crate::messaging::HandlersBean;
+use crate::profile::UserProfile;
+use crate::quality::Channel;
+use crate::timedata::RideRecord;
+use crate::training::TrainingMetrics;
+
+const TILE_WIDTH: i32 = 90;
+const CAPTION_HEIGHT: i32 = 20;
+const VALUE_HEIGHT: i32 = 44;
+const NO_VALUE: &str = "--";
+
+/// Caption and how a tile shows its metric
+const TILES: [(&str, fn(&TrainingMetrics) -> Option<String>); 6] = [
+    ("NP", |m| m.normalized_power.map(|v| format!("{:.0}", v))),
+    ("IF", |m| m.intensity_factor.map(|v| format!("{:.2}", v))),
+    ("TSS", |m| m.training_stress_score.map(|v| format!("{:.0}", v))),
+    ("VI", |m| m.variability_index.map(|v| format!("{:.2}", v))),
+    ("kJ", |m| Some(format!("{:.0}", m.work))),
+    ("W/kg", |m| m.watts_per_kg.map(|v| format!("{:.1}", v))),
+];
+
+/// Tiles with normalized power, intensity factor, training stress, variability index,
+/// work and power-to-weight of the ride so far
+pub struct TrainingDisplay {
+    record: Rc<RefCell<RideRecord>>,
+    profile: Rc<RefCell<UserProfile>>,
+    root: usize,
+    values: Vec<usize>,
+}
+
+impl TrainingDisplay {
+    pub fn new(record: Rc<RefCell<RideRecord>>, profile: Rc<RefCell<UserProfile>>) -> TrainingDisplay {
+        TrainingDisplay {
+            record,
+            profile,
+            root: 0,
+            values: Vec::new(),
+        }
+    }
+
+    fn add_label(&self, ui: &mut HandlersBean, x: i32, y: i32, height: i32, text: &str, size: f32) -> usize {
+        let label = ElemBuilder::new(x, y, TILE_WIDTH, height)
+            .with_background(&[0.0, 0.0, 0.0, 1.0])
+            .with_label(text, "Roboto-Light", size, Vec4::from([1.0, 1.0, 1.0, 1.0]))
+            .build();
+        let id = ui.add_element(label, self.root).unwrap();
+        ui.add_bind(self.root, id, Box::new(move |fs: &FieldSelector| {
+            if let FieldSelector::X(root_x) = *fs {
+                return Some(vec![FieldSelector::X(root_x + x)]);
+            } else if let FieldSelector::Y(root_y) = *fs {
+                return Some(vec![FieldSelector::Y(root_y + y)]);
+            }
+            None
+        }));
+        id
+    }
+
+    fn update(&self, ui: &HandlersBean) {
+        let metrics = {
+            let profile = self.profile.borrow();
+            self.record.borrow().training_metrics(profile.ftp, profile.weight)
+        };
+        for ((_, format), id) in TILES.iter().zip(self.values.iter()) {
+            let text = format(&metrics).unwrap_or_else(|| NO_VALUE.to_string());
+            ui.set(*id, FieldSelector::LabelText(SizedStr::sizify(&text)));
+        }
+    }
+}
+
+impl Component for TrainingDisplay {
+    fn initialize(&mut self, parent: usize, ui: &mut HandlersBean) -> usize {
+        let width = TILE_WIDTH * TILES.len() as i32;
+        let root = ElemBuilder::new(0, 0, width, CAPTION_HEIGHT + VALUE_HEIGHT).build();
+        self.root = ui.add_element(root, parent).unwrap();
+
+        for (k, (caption, _)) in TILES.iter().enumerate() {
+            let x = k as i32 * TILE_WIDTH;
+            self.add_label(ui, x, 0, CAPTION_HEIGHT, caption, 14.0);
+            let value = self.add_label(ui, x, CAPTION_HEIGHT, VALUE_HEIGHT, NO_VALUE, 28.0);
+            self.values.push(value);
+        }
+
+        self.root
+    }
+
+    fn handle(&mut self, event: &UserEvent, ui: &HandlersBean) -> Option<Vec<UserEvent>> {
+        match event {
+            UserEvent::PowerChanged(_) | UserEvent::QualityChanged(Channel::Power, _) => self.update(ui),
+            _ => {}
+        }
+        None
+    }
+}
@@ -0,0 +1,95 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::{ElemBuilder, FieldSelector, SizedStr, Sizing, Vec4};
+use crate::components::{Component, UserEvent};
+use crate::element::Element;
+use crate::messaging::HandlersBean;
+use crate::timedata::RideRecord;
+
+const TRACK_WIDTH: i32 = 240;
+const LABEL_WIDTH: i32 = 120;
+const HEIGHT: i32 = 24;
+
+/// Bar of the W' left, draining above critical power and refilling below
+pub struct WBalanceGauge {
+    record: Rc<RefCell<RideRecord>>,
+    root: usize,
+    bar: usize,
+    label: usize,
+}
+
+impl WBalanceGauge {
+    pub fn new(record: Rc<RefCell<RideRecord>>) -> WBalanceGauge {
+        WBalanceGauge {
+            record,
+            root: 0,
+            bar: 0,
+            label: 0,
+        }
+    }
+
+    fn add_child(&self, ui: &mut HandlersBean, x: i32, element: Element) -> usize {
+        let id = ui.add_element(element, self.root).unwrap();
+        ui.add_bind(self.root, id, Box::new(move |fs: &FieldSelector| {
+            if let FieldSelector::X(root_x) = *fs {
+                return Some(vec![FieldSelector::X(root_x + x)]);
+            } else if let FieldSelector::Y(root_y) = *fs {
+                return Some(vec![FieldSelector::Y(root_y)]);
+            }
+            None
+        }));
+        id
+    }
+
+    fn level_color(percent: f32) -> [f32; 4] {
+        if percent > 50.0 {
+            [0.3, 0.8, 0.3, 1.0]
+        } else if percent > 25.0 {
+            [1.0, 0.7, 0.0, 1.0]
+        } else {
+            [1.0, 0.3, 0.3, 1.0]
+        }
+    }
+
+    fn update(&self, ui: &HandlersBean) {
+        let (balance, percent) = {
+            let record = self.record.borrow();
+            let w_balance = record.w_balance();
+            match w_balance.percent() {
+                Some(percent) => (w_balance.balance(), percent),
+                None => return,
+            }
+        };
+        let width = (TRACK_WIDTH as f32 * percent.max(0.0).min(100.0) / 100.0) as i32;
+        ui.set(self.bar, FieldSelector::Width(width));
+        ui.set(self.bar, FieldSelector::BGColor(Vec4::from(Self::level_color(percent))));
+        ui.set(self.label, FieldSelector::LabelText(SizedStr::sizify(&format!("W' {:.0}% {:.1} kJ", percent, balance / 1000.0))));
+        ui.set(self.label, FieldSelector::LabelColor(Vec4::from(Self::level_color(percent))));
+    }
+}
+
+impl Component for WBalanceGauge {
+    fn initialize(&mut self, parent: usize, ui: &mut HandlersBean) -> usize {
+        let root = ElemBuilder::new(0, 0, TRACK_WIDTH + LABEL_WIDTH, HEIGHT).build();
+        self.root = ui.add_element(root, parent).unwrap();
+
+        self.add_child(ui, 0, ElemBuilder::new(0, 0, TRACK_WIDTH, HEIGHT).with_background(&[0.2, 0.2, 0.2, 1.0]).build());
+        self.bar = self.add_child(ui, 0, ElemBuilder::new(0, 0, TRACK_WIDTH, HEIGHT)
+            .with_background(&Self::level_color(100.0))
+            .build());
+        self.label = self.add_child(ui, TRACK_WIDTH, ElemBuilder::new(TRACK_WIDTH, 0, LABEL_WIDTH, HEIGHT)
+            .with_background(&[0.0, 0.0, 0.0, 1.0])
+            .with_label("W' 100%", "Roboto-Light", 14.0, Vec4::from(Self::level_color(100.0)))
+            .build());
+
+        self.root
+    }
+
+    fn handle(&mut self, event: &UserEvent, ui: &HandlersBean) -> Option<Vec<UserEvent>> {
+        if let UserEvent::PowerChanged(_) = event {
+            self.update(ui);
+        }
+        None
+    }
+}
@@ -1,6 +1,11 @@
 use std::cell::RefCell;
 use std::rc::Rc;
 
+use crate::bluetooth::ftms::TrainerControl;
+use crate::bluetooth::simulated::{SimulatedSensor, SimulatorSettings};
+use crate::bluetooth::registry::SensorRegistry;
+use crate::gearing::{Drivetrain, Gear};
+
 mod store;
 pub use self::store::*;
 
@@ -8,6 +13,7 @@ mod assets;
 pub use self::assets::*;
 
 pub mod ui;
+pub mod ant;
 pub mod bluetooth;
 pub mod components;
 
@@ -16,15 +22,56 @@ pub use self::ui::*;
 /// Used to instantiate our application
 pub struct App {
     pub store: Rc<RefCell<Store>>,
+    pub trainer: TrainerControl,
+    pub sensors: Rc<RefCell<SensorRegistry>>,
 }
 
 impl App {
     /// Create a new instance of our WebGL Water application
     pub fn new(w: i32, h: i32, dw: i32, dh: i32) -> App {
-
+        let trainer = TrainerControl::new();
+        let store = Rc::new(RefCell::new(Store::new(w, h, dw, dh, trainer.clone())));
+        let sensors = Rc::new(RefCell::new(SensorRegistry::new(store.clone(), trainer.clone())));
+        let wheel_circumference = store.borrow().state.get_profile().borrow().drivetrain.wheel_circumference;
+        sensors.borrow_mut().set_wheel_circumference(wheel_circumference);
         App {
-            store: Rc::new(RefCell::new(Store::new(w, h, dw, dh))),
+            store,
+            trainer,
+            sensors,
         }
     }
 
+    /// Apply a shift binding, returns the new gear if the key is bound and the gear changed
+    pub fn shift(&self, key: u32) -> Option<Gear> {
+        let (profile, gearing) = {
+            let store = self.store.borrow();
+            (store.state.get_profile(), store.state.get_gearing())
+        };
+        let control = profile.borrow().shift_bindings.control(key)?;
+        let mut gearing = gearing.borrow_mut();
+        gearing.control(control)
+    }
+
+    /// Save a new bike setup, the wheel size also applies to measured speed
+    pub fn set_drivetrain(&self, drivetrain: Drivetrain) {
+        let (profile, gearing) = {
+            let store = self.store.borrow();
+            (store.state.get_profile(), store.state.get_gearing())
+        };
+        self.sensors.borrow_mut().set_wheel_circumference(drivetrain.wheel_circumference);
+        gearing.borrow_mut().set_drivetrain(drivetrain.clone());
+        let mut profile = profile.borrow_mut();
+        profile.drivetrain = drivetrain;
+        profile.save();
+    }
+
+    /// Save and start a new script for the demo rider
+    pub fn set_simulator(&self, settings: SimulatorSettings) {
+        let profile = self.store.borrow().state.get_profile();
+        self.sensors.borrow_mut().simulate(SimulatedSensor::from_settings(&settings));
+        let mut profile = profile.borrow_mut();
+        profile.simulator = settings;
+        profile.save();
+    }
+
 }
@@ -0,0 +1,195 @@
+use serde::Serialize;
+
+/// s, efforts the 2-parameter model is fitted to, shorter ones are limited by Pmax
+/// and longer ones fall below CP
+pub const TWO_PARAMETER_RANGE: (usize, usize) = (120, 1200);
+/// s, the 3-parameter model also covers short efforts
+pub const THREE_PARAMETER_RANGE: (usize, usize) = (5, 1200);
+
+/// s, FTP is estimated as the power the model predicts for an hour
+const FTP_DURATION: f32 = 3600.0;
+
+/// s, bounds of the time constant W' / (Pmax - CP) searched by the 3-parameter fit
+const MIN_K: f64 = 0.1;
+const MAX_K: f64 = 600.0;
+
+#[derive(Serialize, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CpModel {
+    /// Monod and Scherrer: work is linear in time, P = CP + W' / t
+    TwoParameter,
+    /// Morton: P = CP + W' / (t + W' / (Pmax - CP))
+    ThreeParameter,
+}
+
+/// Critical power model fitted to mean-maximal efforts
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct CpFit {
+    pub model: CpModel,
+    /// W
+    pub cp: f32,
+    /// J
+    pub w_prime: f32,
+    /// W, 3-parameter model only
+    pub pmax: Option<f32>,
+    /// W, predicted power for an hour
+    pub ftp: f32,
+    /// Of the predicted against the effort power
+    pub r_squared: f32,
+    /// W
+    pub rmse: f32,
+    /// Duration in s and watts of the efforts fitted to
+    pub efforts: Vec<(usize, f32)>,
+}
+
+impl CpFit {
+    /// W, power the model predicts can be held for `duration` s
+    pub fn power_at(&self, duration: f32) -> f32 {
+        match self.pmax {
+            Some(pmax) => self.cp + self.w_prime / (duration + self.w_prime / (pmax - self.cp)),
+            None => self.cp + self.w_prime / duration,
+        }
+    }
+
+    fn new(model: CpModel, cp: f64, w_prime: f64, pmax: Option<f64>, efforts: Vec<(usize, f32)>) -> CpFit {
+        let mut fit = CpFit {
+            model,
+            cp: cp as f32,
+            w_prime: w_prime as f32,
+            pmax: pmax.map(|p| p as f32),
+            ftp: 0.0,
+            r_squared: 0.0,
+            rmse: 0.0,
+            efforts,
+        };
+        fit.ftp = fit.power_at(FTP_DURATION);
+        let n = fit.efforts.len() as f64;
+        let mean = fit.efforts.iter().map(|(_, p)| *p as f64).sum::<f64>() / n;
+        let total: f64 = fit.efforts.iter().map(|(_, p)| (*p as f64 - mean).powi(2)).sum();
+        let residual: f64 = fit.efforts.iter()
+            .map(|(t, p)| (*p as f64 - fit.power_at(*t as f32) as f64).powi(2))
+            .sum();
+        fit.r_squared = if total > 0.0 { (1.0 - residual / total) as f32 } else { 1.0 };
+        fit.rmse = (residual / n).sqrt() as f32;
+        fit
+    }
+}
+
+/// Efforts within `range` s, one per duration, `None` if there are fewer than `min_count`
+fn efforts_in(efforts: &[(usize, f32)], range: (usize, usize), min_count: usize) -> Option<Vec<(usize, f32)>> {
+    let mut used: Vec<(usize, f32)> = efforts.iter()
+        .filter(|(t, p)| *t >= range.0 && *t <= range.1 && *p > 0.0)
+        .copied()
+        .collect();
+    used.sort_by_key(|(t, _)| *t);
+    used.dedup_by_key(|(t, _)| *t);
+    if used.len() < min_count { None } else { Some(used) }
+}
+
+/// Least squares line y = a + b x, `None` if all x are the same
+fn linear_fit(points: impl Iterator<Item=(f64, f64)> + Clone) -> Option<(f64, f64)> {
+    let n = points.clone().count() as f64;
+    let (sx, sy) = points.clone().fold((0.0, 0.0), |(sx, sy), (x, y)| (sx + x, sy + y));
+    let (mx, my) = (sx / n, sy / n);
+    let (sxx, sxy) = points.fold((0.0, 0.0), |(sxx, sxy), (x, y)| (sxx + (x - mx).powi(2), sxy + (x - mx) * (y - my)));
+    if sxx <= 0.0 {
+        return None;
+    }
+    let b = sxy / sxx;
+    Some((my - b * mx, b))
+}
+
+/// Fit work = CP t + W' to the efforts within `TWO_PARAMETER_RANGE`
+pub fn fit_two_parameter(efforts: &[(usize, f32)]) -> Option<CpFit> {
+    let used = efforts_in(efforts, TWO_PARAMETER_RANGE, 2)?;
+    let (w_prime, cp) = linear_fit(used.iter().map(|(t, p)| (*t as f64, *p as f64 * *t as f64)))?;
+    if cp <= 0.0 || w_prime <= 0.0 {
+        return None;
+    }
+    Some(CpFit::new(CpModel::TwoParameter, cp, w_prime, None, used))
+}
+
+/// Fit the 3-parameter model to the efforts within `THREE_PARAMETER_RANGE`. For a given
+/// time constant k = W' / (Pmax - CP) power is linear in 1 / (t + k), k is searched for.
+pub fn fit_three_parameter(efforts: &[(usize, f32)]) -> Option<CpFit> {
+    let used = efforts_in(efforts, THREE_PARAMETER_RANGE, 3)?;
+    let fit_k = |k: f64| -> Option<(f64, f64, f64)> {
+        let points = used.iter().map(move |(t, p)| (1.0 / (*t as f64 + k), *p as f64));
+        let (cp, w_prime) = linear_fit(points.clone())?;
+        let residual = points.map(|(x, p)| (p - cp - w_prime * x).powi(2)).sum();
+        Some((residual, cp, w_prime))
+    };
+    let residual = |k: f64| fit_k(k).map_or(f64::INFINITY, |(r, _, _)| r);
+
+    // coarse scan in log space, then golden section around the best
+    let steps = 200;
+    let ratio = (MAX_K / MIN_K).powf(1.0 / steps as f64);
+    let best = (0..=steps)
+        .map(|i| MIN_K * ratio.powi(i))
+        .min_by(|a, b| residual(*a).partial_cmp(&residual(*b)).unwrap())?;
+    let (mut low, mut high) = (best / ratio, best * ratio);
+    let golden = (5f64.sqrt() - 1.0) / 2.0;
+    for _ in 0..60 {
+        let a = high - golden * (high - low);
+        let b = low + golden * (high - low);
+        if residual(a) < residual(b) {
+            high = b;
+        } else {
+            low = a;
+        }
+    }
+    let k = (low + high) / 2.0;
+    let (_, cp, w_prime) = fit_k(k)?;
+    if cp <= 0.0 || w_prime <= 0.0 {
+        return None;
+    }
+    Some(CpFit::new(CpModel::ThreeParameter, cp, w_prime, Some(cp + w_prime / k), used))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::power_curve::CURVE_DURATIONS;
+
+    fn efforts(model: impl Fn(f32) -> f32) -> Vec<(usize, f32)> {
+        CURVE_DURATIONS.iter().map(|t| (*t, model(*t as f32))).collect()
+    }
+
+    #[test]
+    fn recovers_two_parameter_model() {
+        let fit = fit_two_parameter(&efforts(|t| 280.0 + 20_000.0 / t)).unwrap();
+        assert_eq!(fit.model, CpModel::TwoParameter);
+        assert!((fit.cp - 280.0).abs() < 0.01, "{}", fit.cp);
+        assert!((fit.w_prime - 20_000.0).abs() < 1.0, "{}", fit.w_prime);
+        assert!((fit.ftp - (280.0 + 20_000.0 / 3600.0)).abs() < 0.01);
+        assert!(fit.r_squared > 0.9999 && fit.rmse < 0.01);
+        assert_eq!(fit.efforts.iter().map(|(t, _)| *t).collect::<Vec<_>>(), vec![120, 180, 240, 300, 480, 600, 720, 900, 1200]);
+    }
+
+    #[test]
+    fn recovers_three_parameter_model() {
+        // CP 300 W, W' 18 kJ, Pmax 1200 W
+        let k = 18_000.0 / 900.0;
+        let fit = fit_three_parameter(&efforts(|t| 300.0 + 18_000.0 / (t + k))).unwrap();
+        assert!((fit.cp - 300.0).abs() < 0.1, "{}", fit.cp);
+        assert!((fit.w_prime - 18_000.0).abs() < 20.0, "{}", fit.w_prime);
+        assert!((fit.pmax.unwrap() - 1200.0).abs() < 2.0, "{:?}", fit.pmax);
+        assert!(fit.r_squared > 0.9999);
+        assert_eq!(fit.efforts.len(), 14);
+    }
+
+    #[test]
+    fn reports_fit_of_scattered_efforts() {
+        // a rider's bests, not every duration ridden all out
+        let bests = [(5, 1010.0), (60, 560.0), (180, 400.0), (300, 350.0), (600, 310.0), (1200, 290.0), (3600, 250.0)];
+        let two = fit_two_parameter(&bests).unwrap();
+        assert_eq!(two.efforts.len(), 4);
+        assert!(two.cp > 265.0 && two.cp < 285.0, "{}", two.cp);
+        assert!(two.r_squared > 0.95 && two.r_squared < 1.0);
+        let three = fit_three_parameter(&bests).unwrap();
+        assert!(three.rmse > 0.0 && three.pmax.unwrap() > three.cp);
+        assert!(three.ftp < three.cp + 10.0);
+
+        assert!(fit_two_parameter(&[(300, 350.0)]).is_none());
+        assert!(fit_three_parameter(&[(60, 500.0), (300, 350.0)]).is_none());
+    }
+}
@@ -0,0 +1,373 @@
+use serde::{Deserialize, Serialize};
+
+use crate::bluetooth::ftms::SimulationParameters;
+
+const GRAVITY: f32 = 9.81;
+/// Highest level the resistance request can carry
+const MAX_RESISTANCE_LEVEL: f32 = 25.5;
+/// Force at the trainer wheel mapped to the highest resistance level, N
+const FULL_RESISTANCE_FORCE: f32 = 120.0;
+/// Minimal interval between resistance updates, ms
+const RESISTANCE_INTERVAL: f32 = 1000.0;
+
+/// Bike set up for virtual shifting
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Drivetrain {
+    /// Teeth, smallest first
+    pub chainrings: Vec<u8>,
+    /// Teeth, largest first, so that a higher index is always a harder gear
+    pub cassette: Vec<u8>,
+    /// m
+    pub wheel_circumference: f32,
+    /// Gear the bike is left in on the trainer, teeth
+    pub trainer_chainring: u8,
+    pub trainer_sprocket: u8,
+}
+
+impl Default for Drivetrain {
+    fn default() -> Self {
+        Drivetrain {
+            chainrings: vec![34, 50],
+            cassette: vec![28, 25, 23, 21, 19, 17, 15, 14, 13, 12, 11],
+            wheel_circumference: 2.105,
+            trainer_chainring: 50,
+            trainer_sprocket: 17,
+        }
+    }
+}
+
+/// Currently selected combination
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Gear {
+    /// Teeth
+    pub chainring: u8,
+    /// Teeth
+    pub sprocket: u8,
+    /// Counted from the smallest chainring, from 0
+    pub front: usize,
+    /// Counted from the largest sprocket, from 0
+    pub rear: usize,
+    /// Virtual gearing applies to the trainer
+    pub enabled: bool,
+}
+
+impl Gear {
+    pub fn ratio(&self) -> f32 {
+        self.chainring as f32 / self.sprocket as f32
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum GearControl {
+    /// One sprocket harder
+    Up,
+    /// One sprocket easier
+    Down,
+    FrontUp,
+    FrontDown,
+    /// Switch virtual gearing on or off
+    Toggle,
+}
+
+/// Key codes of the shift buttons
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+pub struct ShiftBindings {
+    pub up: u32,
+    pub down: u32,
+    pub front_up: u32,
+    pub front_down: u32,
+    pub toggle: u32,
+}
+
+impl ShiftBindings {
+    pub fn control(&self, key: u32) -> Option<GearControl> {
+        match key {
+            k if k == self.up => Some(GearControl::Up),
+            k if k == self.down => Some(GearControl::Down),
+            k if k == self.front_up => Some(GearControl::FrontUp),
+            k if k == self.front_down => Some(GearControl::FrontDown),
+            k if k == self.toggle => Some(GearControl::Toggle),
+            _ => None,
+        }
+    }
+}
+
+impl Default for ShiftBindings {
+    fn default() -> Self {
+        ShiftBindings {
+            up: 38, // ArrowUp
+            down: 40, // ArrowDown
+            front_up: 39, // ArrowRight
+            front_down: 37, // ArrowLeft
+            toggle: 71, // 'G'
+        }
+    }
+}
+
+/// Shifts gears the bike on the trainer doesn't have. The trainer is made to feel
+/// as if the rider was in the virtual gear while the bike stays in `trainer_*` gear.
+pub struct VirtualGearing {
+    drivetrain: Drivetrain,
+    front: usize,
+    rear: usize,
+    enabled: bool,
+    since_resistance: f32,
+    sent_resistance: Option<f32>,
+}
+
+impl VirtualGearing {
+    /// Starts in the trainer gear, or the closest one the drivetrain has
+    pub fn new(drivetrain: Drivetrain) -> VirtualGearing {
+        let mut gearing = VirtualGearing {
+            drivetrain: Drivetrain::default(),
+            front: 0,
+            rear: 0,
+            enabled: false,
+            since_resistance: RESISTANCE_INTERVAL,
+            sent_resistance: None,
+        };
+        gearing.set_drivetrain(drivetrain);
+        gearing
+    }
+
+    pub fn set_drivetrain(&mut self, drivetrain: Drivetrain) {
+        self.front = Self::closest(&drivetrain.chainrings, drivetrain.trainer_chainring);
+        self.rear = Self::closest(&drivetrain.cassette, drivetrain.trainer_sprocket);
+        self.drivetrain = drivetrain;
+    }
+
+    pub fn drivetrain(&self) -> &Drivetrain {
+        &self.drivetrain
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn gear(&self) -> Gear {
+        Gear {
+            chainring: self.drivetrain.chainrings.get(self.front).copied().unwrap_or(1),
+            sprocket: self.drivetrain.cassette.get(self.rear).copied().unwrap_or(1),
+            front: self.front,
+            rear: self.rear,
+            enabled: self.enabled,
+        }
+    }
+
+    /// Send the resistance again on the next update, e.g. after the trainer left another mode
+    pub fn resend(&mut self) {
+        self.sent_resistance = None;
+    }
+
+    /// Returns the new gear, `None` if already at the end of the range
+    pub fn control(&mut self, control: GearControl) -> Option<Gear> {
+        let (front, rear) = (self.front, self.rear);
+        match control {
+            GearControl::Up => self.rear = (self.rear + 1).min(self.drivetrain.cassette.len().saturating_sub(1)),
+            GearControl::Down => self.rear = self.rear.saturating_sub(1),
+            GearControl::FrontUp => self.front = (self.front + 1).min(self.drivetrain.chainrings.len().saturating_sub(1)),
+            GearControl::FrontDown => self.front = self.front.saturating_sub(1),
+            GearControl::Toggle => {
+                self.enabled = !self.enabled;
+                self.sent_resistance = None;
+                return Some(self.gear());
+            }
+        }
+        if (front, rear) == (self.front, self.rear) {
+            return None;
+        }
+        Some(self.gear())
+    }
+
+    /// Metres travelled per crank revolution
+    pub fn development(&self) -> f32 {
+        self.gear().ratio() * self.drivetrain.wheel_circumference
+    }
+
+    /// Virtual over trainer gear ratio, 1 while disabled
+    pub fn scale(&self) -> f32 {
+        if !self.enabled || self.drivetrain.trainer_sprocket == 0 {
+            return 1.0;
+        }
+        let trainer_ratio = self.drivetrain.trainer_chainring as f32 / self.drivetrain.trainer_sprocket as f32;
+        self.gear().ratio() / trainer_ratio
+    }
+
+    /// Speed the rider would go in the virtual gear, m/s
+    pub fn virtual_speed(&self, trainer_speed: f32) -> f32 {
+        trainer_speed * self.scale()
+    }
+
+    /// Parameters that make the trainer, spinning at its own speed, resist as the course
+    /// does at the virtual speed: wheel force has to grow with the ratio `k`, so the linear
+    /// terms are scaled by `k` and drag by `k^3` with the wind slowed down by `k`
+    pub fn simulation_parameters(&self, params: SimulationParameters) -> SimulationParameters {
+        let k = self.scale();
+        SimulationParameters {
+            wind_speed: params.wind_speed / k,
+            grade: params.grade * k,
+            crr: params.crr * k,
+            cw: params.cw * k.powi(3),
+        }
+    }
+
+    /// Resistance level for trainers without simulation, from the force needed to hold
+    /// `speed` m/s on the course with `mass` kg
+    pub fn resistance_level(&self, params: SimulationParameters, mass: f32, speed: f32) -> f32 {
+        let air_speed = speed + params.wind_speed;
+        let force = mass * GRAVITY * (params.crr + params.grade / 100.0)
+            + params.cw * air_speed * air_speed.abs();
+        let wheel_force = force * self.scale();
+        (wheel_force / FULL_RESISTANCE_FORCE).max(0.0).min(1.0) * MAX_RESISTANCE_LEVEL
+    }
+
+    /// Advance by `dt` ms, returns a resistance level to send when it changed,
+    /// at most once per `RESISTANCE_INTERVAL`
+    pub fn resistance_update(&mut self, dt: f32, params: SimulationParameters, mass: f32, speed: f32) -> Option<f32> {
+        self.since_resistance += dt;
+        if !self.enabled || self.since_resistance < RESISTANCE_INTERVAL {
+            return None;
+        }
+        let level = (self.resistance_level(params, mass, speed) * 10.0).round() / 10.0;
+        if self.sent_resistance == Some(level) {
+            return None;
+        }
+        self.since_resistance = 0.0;
+        self.sent_resistance = Some(level);
+        Some(level)
+    }
+
+    fn closest(teeth: &[u8], target: u8) -> usize {
+        teeth.iter().enumerate()
+            .min_by_key(|(_, t)| (**t as i32 - target as i32).abs())
+            .map(|(k, _)| k)
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FLAT: SimulationParameters = SimulationParameters { wind_speed: 0.0, grade: 0.0, crr: 0.004, cw: 0.51 };
+
+    fn enabled() -> VirtualGearing {
+        let mut gearing = VirtualGearing::new(Drivetrain::default());
+        gearing.control(GearControl::Toggle);
+        gearing
+    }
+
+    #[test]
+    fn starts_in_closest_trainer_gear() {
+        let gearing = VirtualGearing::new(Drivetrain::default());
+        let gear = gearing.gear();
+        assert_eq!((gear.chainring, gear.sprocket, gear.front, gear.rear), (50, 17, 1, 5));
+        assert!(!gear.enabled);
+        assert!((gearing.development() - 50.0 / 17.0 * 2.105).abs() < 1e-4);
+
+        let drivetrain = Drivetrain { trainer_chainring: 53, trainer_sprocket: 16, ..Drivetrain::default() };
+        let gear = VirtualGearing::new(drivetrain).gear();
+        assert_eq!((gear.chainring, gear.sprocket), (50, 17));
+    }
+
+    #[test]
+    fn shifts_within_the_drivetrain() {
+        let mut gearing = VirtualGearing::new(Drivetrain::default());
+        assert_eq!(gearing.control(GearControl::Up).unwrap().sprocket, 15);
+        for _ in 0..10 {
+            gearing.control(GearControl::Up);
+        }
+        assert_eq!(gearing.gear().sprocket, 11);
+        assert_eq!(gearing.control(GearControl::Up), None);
+        assert_eq!(gearing.control(GearControl::FrontUp), None);
+        assert_eq!(gearing.control(GearControl::FrontDown).unwrap().chainring, 34);
+        assert_eq!(gearing.control(GearControl::FrontDown), None);
+        for _ in 0..20 {
+            gearing.control(GearControl::Down);
+        }
+        assert_eq!(gearing.gear().sprocket, 28);
+        assert_eq!(gearing.control(GearControl::Down), None);
+        // toggling always reports the gear
+        assert!(gearing.control(GearControl::Toggle).unwrap().enabled);
+        assert!(!gearing.control(GearControl::Toggle).unwrap().enabled);
+    }
+
+    #[test]
+    fn maps_shift_keys() {
+        let bindings = ShiftBindings::default();
+        assert_eq!(bindings.control(38), Some(GearControl::Up));
+        assert_eq!(bindings.control(40), Some(GearControl::Down));
+        assert_eq!(bindings.control(39), Some(GearControl::FrontUp));
+        assert_eq!(bindings.control(37), Some(GearControl::FrontDown));
+        assert_eq!(bindings.control(71), Some(GearControl::Toggle));
+        assert_eq!(bindings.control(65), None);
+    }
+
+    #[test]
+    fn scales_by_virtual_over_trainer_ratio() {
+        let mut gearing = VirtualGearing::new(Drivetrain::default());
+        gearing.control(GearControl::Up);
+        // disabled gearing leaves the trainer alone
+        assert_eq!(gearing.scale(), 1.0);
+        assert_eq!(gearing.simulation_parameters(FLAT), FLAT);
+        gearing.control(GearControl::Toggle);
+        assert!((gearing.scale() - 17.0 / 15.0).abs() < 1e-5);
+        assert!((gearing.virtual_speed(9.0) - 10.2).abs() < 1e-4);
+        gearing.control(GearControl::FrontDown);
+        assert!((gearing.scale() - 34.0 / 15.0 / (50.0 / 17.0)).abs() < 1e-5);
+    }
+
+    #[test]
+    fn simulates_the_virtual_gear() {
+        let mut gearing = enabled();
+        gearing.control(GearControl::Up);
+        gearing.control(GearControl::Up);
+        let k = gearing.scale();
+        let course = SimulationParameters { wind_speed: 2.0, grade: 5.0, crr: 0.004, cw: 0.51 };
+        let trainer = gearing.simulation_parameters(course);
+        assert!((trainer.grade - 5.0 * k).abs() < 1e-4);
+        assert!((trainer.crr - 0.004 * k).abs() < 1e-6);
+        assert!((trainer.cw - 0.51 * k.powi(3)).abs() < 1e-5);
+        assert!((trainer.wind_speed - 2.0 / k).abs() < 1e-5);
+        // force at the trainer's speed is k times the force at the virtual speed
+        let force = |p: SimulationParameters, v: f32| 80.0 * GRAVITY * (p.crr + p.grade / 100.0) + p.cw * (v + p.wind_speed).powi(2);
+        for v in &[3.0, 8.0, 12.0] {
+            assert!((force(trainer, *v) - k * force(course, k * v)).abs() < 1e-2);
+        }
+    }
+
+    #[test]
+    fn maps_force_to_resistance_level() {
+        let mut gearing = VirtualGearing::new(Drivetrain::default());
+        // 3.1 N rolling and 51 N drag at 10 m/s
+        let level = gearing.resistance_level(FLAT, 80.0, 10.0);
+        assert!((level - 54.1392 / FULL_RESISTANCE_FORCE * MAX_RESISTANCE_LEVEL).abs() < 1e-3, "{}", level);
+        let headwind = gearing.resistance_level(SimulationParameters { wind_speed: 5.0, ..FLAT }, 80.0, 10.0);
+        assert!(headwind > level);
+        assert_eq!(gearing.resistance_level(SimulationParameters { grade: -10.0, ..FLAT }, 80.0, 5.0), 0.0);
+        assert_eq!(gearing.resistance_level(SimulationParameters { grade: 20.0, ..FLAT }, 80.0, 5.0), MAX_RESISTANCE_LEVEL);
+        gearing.control(GearControl::Toggle);
+        gearing.control(GearControl::Up);
+        assert!((gearing.resistance_level(FLAT, 80.0, 10.0) - level * 17.0 / 15.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn sends_changed_resistance_once_per_interval() {
+        let mut gearing = VirtualGearing::new(Drivetrain::default());
+        assert_eq!(gearing.resistance_update(RESISTANCE_INTERVAL, FLAT, 80.0, 10.0), None);
+        gearing.control(GearControl::Toggle);
+        assert_eq!(gearing.resistance_update(0.0, FLAT, 80.0, 10.0), Some(11.5));
+        let climb = SimulationParameters { grade: 2.0, ..FLAT };
+        assert_eq!(gearing.resistance_update(RESISTANCE_INTERVAL - 100.0, climb, 80.0, 10.0), None);
+        let level = gearing.resistance_update(100.0, climb, 80.0, 10.0).unwrap();
+        assert!(level > 11.5);
+        // unchanged levels are not sent, a change goes out once the interval is over
+        assert_eq!(gearing.resistance_update(RESISTANCE_INTERVAL, climb, 80.0, 10.0), None);
+        assert_eq!(gearing.resistance_update(100.0, FLAT, 80.0, 10.0), Some(11.5));
+        gearing.resistance_update(RESISTANCE_INTERVAL, climb, 80.0, 10.0);
+        // the same level is sent again after toggling
+        gearing.control(GearControl::Toggle);
+        gearing.control(GearControl::Toggle);
+        assert_eq!(gearing.resistance_update(RESISTANCE_INTERVAL, climb, 80.0, 10.0), Some(level));
+    }
+}
@@ -0,0 +1,205 @@
+use serde::{Deserialize, Serialize};
+
+/// Interval between power adjustments, ms
+const UPDATE_INTERVAL: f32 = 1000.0;
+/// Without heart rate for this long power is eased down to the minimum, ms
+const HR_TIMEOUT: f32 = 10000.0;
+/// Heart rate window the controller reacts to, ms
+pub const HR_AVERAGE_WINDOW: f32 = 5000.0;
+
+/// Heart rate ERG tuning and safety limits
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct HrControlSettings {
+    /// bpm
+    pub target_hr: f32,
+    /// W per bpm of error
+    pub kp: f32,
+    /// W per bpm of error and second
+    pub ki: f32,
+    /// W per bpm/s
+    pub kd: f32,
+    /// Watts
+    pub min_power: f32,
+    /// Watts, never exceeded
+    pub max_power: f32,
+    /// Fastest power increase, W/s
+    pub max_rise: f32,
+    /// Fastest power decrease, W/s
+    pub max_fall: f32,
+    /// Power drops to `min_power` at once when heart rate is this far above target, bpm
+    pub ceiling: f32,
+}
+
+impl Default for HrControlSettings {
+    fn default() -> Self {
+        HrControlSettings {
+            target_hr: 130.0,
+            kp: 1.0,
+            ki: 0.05,
+            kd: 0.0,
+            min_power: 50.0,
+            max_power: 250.0,
+            max_rise: 1.0,
+            max_fall: 5.0,
+            ceiling: 10.0,
+        }
+    }
+}
+
+/// Closed loop ERG: adjusts target power so that heart rate settles at the target.
+/// PID on the heart rate error with the output slew limited and capped.
+pub struct HrController {
+    settings: HrControlSettings,
+    /// Watts, current target
+    power: f32,
+    /// Integral term, watts
+    integral: f32,
+    last_error: Option<f32>,
+    since_update: f32,
+    /// Time without heart rate readings, ms
+    since_hr: f32,
+}
+
+impl HrController {
+    pub fn new(settings: HrControlSettings, start_power: f32) -> HrController {
+        let power = start_power.max(settings.min_power).min(settings.max_power);
+        HrController {
+            settings,
+            power,
+            integral: power,
+            last_error: None,
+            since_update: 0.0,
+            since_hr: 0.0,
+        }
+    }
+
+    pub fn settings(&self) -> &HrControlSettings {
+        &self.settings
+    }
+
+    pub fn set_target(&mut self, target_hr: f32) {
+        self.settings.target_hr = target_hr;
+        self.last_error = None;
+    }
+
+    /// Watts
+    pub fn power(&self) -> f32 {
+        self.power
+    }
+
+    /// Advance by `dt` ms with the averaged heart rate, `None` if no reading is recent.
+    /// Returns the target power to send once per `UPDATE_INTERVAL`
+    pub fn update(&mut self, hr: Option<f32>, dt: f32) -> Option<i16> {
+        self.since_update += dt;
+        if self.since_update < UPDATE_INTERVAL {
+            return None;
+        }
+        let step = self.since_update / 1000.0;
+        self.since_update = 0.0;
+
+        let s = self.settings;
+        let hr = match hr {
+            Some(hr) => {
+                self.since_hr = 0.0;
+                hr
+            }
+            None => {
+                self.since_hr += step * 1000.0;
+                if self.since_hr >= HR_TIMEOUT {
+                    self.last_error = None;
+                    self.apply(s.min_power, step);
+                    self.integral = self.power;
+                }
+                return Some(self.power.round() as i16);
+            }
+        };
+        if hr >= s.target_hr + s.ceiling {
+            self.power = s.min_power;
+            self.integral = s.min_power;
+            self.last_error = None;
+            return Some(self.power.round() as i16);
+        }
+
+        let error = s.target_hr - hr;
+        let derivative = self.last_error.map(|last| (error - last) / step).unwrap_or(0.0);
+        self.last_error = Some(error);
+        let integral = self.integral + s.ki * error * step;
+        let wanted = s.kp * error + integral + s.kd * derivative;
+        self.apply(wanted, step);
+        // anti-windup: keep integrating only while the output follows
+        let saturated = (wanted > self.power && error > 0.0) || (wanted < self.power && error < 0.0);
+        if !saturated {
+            self.integral = integral;
+        }
+        Some(self.power.round() as i16)
+    }
+
+    /// Move towards `wanted` within the slew limits and power caps
+    fn apply(&mut self, wanted: f32, step: f32) {
+        let s = &self.settings;
+        let change = (wanted - self.power).max(-s.max_fall * step).min(s.max_rise * step);
+        self.power = (self.power + change).max(s.min_power).min(s.max_power);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bluetooth::simulated::{Profile, SimulatedSensor};
+
+    /// Ride the simulated sensor with the controller in the loop for `duration` ms,
+    /// returns (heart rate, power) of every second
+    fn ride(controller: &mut HrController, duration: f32) -> Vec<(f32, f32)> {
+        let mut sensor = SimulatedSensor::new(Profile::Steady(0.0)).with_seed(1);
+        let mut hr = None;
+        let mut res = Vec::new();
+        let mut t = 0.0;
+        while t < duration {
+            sensor.set_power_override(Some(controller.power()));
+            if let Some(sample) = sensor.tick(100.0) {
+                hr = Some(sample.heart_rate as f32);
+                res.push((sample.heart_rate as f32, sample.power as f32));
+            }
+            controller.update(hr, 100.0);
+            t += 100.0;
+        }
+        res
+    }
+
+    #[test]
+    fn settles_at_target_heart_rate() {
+        let mut controller = HrController::new(HrControlSettings::default(), 100.0);
+        let res = ride(&mut controller, 1_200_000.0);
+        let settled = &res[res.len() - 300..];
+        for (hr, _) in settled {
+            assert!((hr - 130.0).abs() <= 2.0, "heart rate {}", hr);
+        }
+    }
+
+    #[test]
+    fn respects_slew_and_caps() {
+        let settings = HrControlSettings { target_hr: 190.0, max_power: 200.0, ..HrControlSettings::default() };
+        let mut controller = HrController::new(settings, 100.0);
+        let res = ride(&mut controller, 600_000.0);
+        for w in res.windows(2) {
+            assert!(w[1].1 - w[0].1 <= settings.max_rise + 1.0, "{} -> {}", w[0].1, w[1].1);
+        }
+        assert!(res.iter().all(|(_, p)| *p <= 200.0));
+    }
+
+    #[test]
+    fn drops_power_above_ceiling() {
+        let mut controller = HrController::new(HrControlSettings::default(), 200.0);
+        assert_eq!(controller.update(Some(145.0), 1000.0), Some(50));
+    }
+
+    #[test]
+    fn eases_down_without_heart_rate() {
+        let mut controller = HrController::new(HrControlSettings::default(), 200.0);
+        for _ in 0..9 {
+            assert_eq!(controller.update(None, 1000.0), Some(200));
+        }
+        assert_eq!(controller.update(None, 1000.0), Some(195));
+    }
+}
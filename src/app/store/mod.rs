@@ -2,24 +2,50 @@ use std::cell::RefCell;
 use std::ops::Deref;
 use std::rc::Rc;
 
+use js_sys::Date;
+
 use self::camera::*;
+use self::cp_model::{fit_three_parameter, fit_two_parameter, CpFit};
+use self::gearing::VirtualGearing;
+use self::hr_control::{HrController, HR_AVERAGE_WINDOW};
 use self::mouse::*;
+use self::power_curve::{PersonalRecords, PowerHistory, PowerRecord, RECENT_DAYS};
+use self::profile::UserProfile;
+use self::quality::DataQuality;
+use self::rider::*;
+use self::wbal::WBalance;
+use crate::bluetooth::calibration::CalibrationRecord;
+use crate::bluetooth::ftms::TrainerControl;
 use crate::messaging::Msg;
 use crate::app::ui::messaging::EventTarget;
-use crate::timedata::HrmData;
+use crate::timedata::{PedalingData, RecordField, RideRecord, RrData, HRV_WINDOW};
+use crate::training::TrainingMetrics;
 
 mod camera;
+pub mod cp_model;
+pub mod gearing;
+pub mod hr_control;
 mod mouse;
+pub mod power_curve;
+pub mod profile;
+pub mod quality;
+pub mod rider;
 pub mod timedata;
+pub mod training;
+pub mod wbal;
+
+/// Range of the heart rate ERG target, bpm
+const MIN_HR_TARGET: f32 = 80.0;
+const MAX_HR_TARGET: f32 = 190.0;
 
 pub struct Store {
     pub state: StateWrapper,
 }
 
 impl Store {
-    pub fn new(w: i32, h: i32, dw: i32, dh: i32) -> Store {
+    pub fn new(w: i32, h: i32, dw: i32, dh: i32, trainer: TrainerControl) -> Store {
         Store {
-            state: StateWrapper(State::new(w, h, dw, dh)),
+            state: StateWrapper(State::new(w, h, dw, dh, trainer)),
         }
     }
 }
@@ -42,11 +68,31 @@ pub struct State {
     d_width: i32,
     d_height: i32,
     show_pick: bool,
-    hr_data: Rc<RefCell<HrmData>>,
+    /// Live readings of every sensor and the rider's progress on the course
+    record: Rc<RefCell<RideRecord>>,
+    rr_data: Rc<RefCell<RrData>>,
+    quality: Rc<RefCell<DataQuality>>,
+    pedaling: Rc<RefCell<PedalingData>>,
+    trainer: TrainerControl,
+    rider: Rc<RefCell<Rider>>,
+    simulation: bool,
+    calibrations: Rc<RefCell<Vec<CalibrationRecord>>>,
+    profile: Rc<RefCell<UserProfile>>,
+    gearing: Rc<RefCell<VirtualGearing>>,
+    /// Heart rate ERG, active when set
+    hr_control: Rc<RefCell<Option<HrController>>>,
+    /// Simulation was on when heart rate ERG started, restored when it stops
+    resume_simulation: bool,
+    /// Bests of this ride against the stored ones
+    records: Rc<RefCell<PersonalRecords>>,
 }
 
 impl State {
-    fn new(w: i32, h: i32, dw: i32, dh: i32) -> State {
+    fn new(w: i32, h: i32, dw: i32, dh: i32, trainer: TrainerControl) -> State {
+        let profile = UserProfile::load();
+        let gearing = VirtualGearing::new(profile.drivetrain.clone());
+        let record = RideRecord::default()
+            .with_w_balance(WBalance::new(profile.cp, profile.w_prime, profile.w_balance_model));
         State {
             /// Time elapsed since the application started, in milliseconds
             clock: 0.,
@@ -58,9 +104,19 @@ impl State {
             d_width: dw,
             d_height: dh,
             show_pick: false,
-            hr_data: Rc::new(RefCell::new( HrmData {
-                data : Vec::new()
-            })),
+            record: Rc::new(RefCell::new(record)),
+            rr_data: Rc::new(RefCell::new(RrData::new(HRV_WINDOW))),
+            quality: Rc::new(RefCell::new(DataQuality::new())),
+            pedaling: Rc::new(RefCell::new(PedalingData::default())),
+            trainer,
+            rider: Rc::new(RefCell::new(profile.rider())),
+            simulation: false,
+            calibrations: Rc::new(RefCell::new(Vec::new())),
+            profile: Rc::new(RefCell::new(profile)),
+            gearing: Rc::new(RefCell::new(gearing)),
+            hr_control: Rc::new(RefCell::new(None)),
+            resume_simulation: false,
+            records: Rc::new(RefCell::new(PersonalRecords::new(PowerHistory::load(), Date::now()))),
         }
     }
 
@@ -97,14 +153,159 @@ impl State {
         self.show_pick
     }
 
-    pub fn get_hr_data(&self) -> Rc<RefCell<HrmData>> {
-        self.hr_data.clone()
+    pub fn get_record(&self) -> Rc<RefCell<RideRecord>> {
+        self.record.clone()
+    }
+
+    /// NP, IF, TSS and the like of the ride so far against the profile FTP and weight
+    pub fn training_metrics(&self) -> TrainingMetrics {
+        let profile = self.profile.borrow();
+        self.record.borrow().training_metrics(profile.ftp, profile.weight)
+    }
+
+    /// New personal records set by the power recorded so far, the bests of
+    /// this ride are saved along the way
+    pub fn check_power_records(&self) -> Vec<PowerRecord> {
+        let now = Date::now();
+        let mut records = self.records.borrow_mut();
+        let new_records = records.update(self.record.borrow().power_curve(), now);
+        if let Some(history) = records.take_unsaved(now) {
+            history.save();
+        }
+        new_records
+    }
+
+    /// 2- and 3-parameter critical power models fitted to the bests of the last `RECENT_DAYS`,
+    /// this ride included
+    pub fn fit_critical_power(&self) -> (Option<CpFit>, Option<CpFit>) {
+        let since = Date::now() - RECENT_DAYS * 24.0 * 3600.0 * 1000.0;
+        let efforts = self.records.borrow().efforts(since);
+        (fit_two_parameter(&efforts), fit_three_parameter(&efforts))
+    }
+
+    /// Update CP, W' and FTP of the profile from a fit, W' balance of this ride is recomputed
+    pub fn apply_cp_fit(&self, fit: &CpFit) {
+        let mut profile = self.profile.borrow_mut();
+        profile.apply_cp_fit(fit);
+        profile.save();
+        self.record.borrow_mut()
+            .set_w_balance(WBalance::new(profile.cp, profile.w_prime, profile.w_balance_model));
+    }
+
+    pub fn get_rr_data(&self) -> Rc<RefCell<RrData>> {
+        self.rr_data.clone()
+    }
+
+    /// Per channel quality of live readings
+    pub fn get_quality(&self) -> Rc<RefCell<DataQuality>> {
+        self.quality.clone()
+    }
+
+    pub fn get_pedaling(&self) -> Rc<RefCell<PedalingData>> {
+        self.pedaling.clone()
+    }
+
+    pub fn trainer(&self) -> &TrainerControl {
+        &self.trainer
+    }
+
+    pub fn get_rider(&self) -> Rc<RefCell<Rider>> {
+        self.rider.clone()
+    }
+
+    /// Calibrations run during this session
+    pub fn get_calibrations(&self) -> Rc<RefCell<Vec<CalibrationRecord>>> {
+        self.calibrations.clone()
+    }
+
+    pub fn get_profile(&self) -> Rc<RefCell<UserProfile>> {
+        self.profile.clone()
+    }
+
+    pub fn get_gearing(&self) -> Rc<RefCell<VirtualGearing>> {
+        self.gearing.clone()
+    }
+
+    pub fn get_hr_control(&self) -> Rc<RefCell<Option<HrController>>> {
+        self.hr_control.clone()
+    }
+
+    /// Start heart rate ERG from the trainer's current target, or stop it and
+    /// put the trainer back the way it was
+    pub fn toggle_hr_control(&mut self) {
+        let mut hr_control = self.hr_control.borrow_mut();
+        if hr_control.take().is_some() {
+            if self.resume_simulation {
+                self.simulation = true;
+            } else {
+                // leave ERG, virtual gearing sets the resistance again if enabled
+                self.trainer.reset();
+                self.gearing.borrow_mut().resend();
+            }
+            return;
+        }
+        self.resume_simulation = self.simulation;
+        self.simulation = false;
+        self.trainer.clear_simulation();
+        let start_power = self.trainer.target_power().unwrap_or(0) as f32;
+        *hr_control = Some(HrController::new(self.profile.borrow().hr_control, start_power));
+    }
+
+    /// Change the heart rate ERG target by `delta` bpm, kept in the profile for the next start
+    pub fn adjust_hr_target(&self, delta: f32) {
+        let mut profile = self.profile.borrow_mut();
+        let target = (profile.hr_control.target_hr + delta).max(MIN_HR_TARGET).min(MAX_HR_TARGET);
+        profile.hr_control.target_hr = target;
+        profile.save();
+        if let Some(controller) = self.hr_control.borrow_mut().as_mut() {
+            controller.set_target(target);
+        }
+    }
+
+    /// Trainer resistance follows the virtual course
+    pub fn simulation(&self) -> bool {
+        self.simulation
+    }
+
+    /// Position of the virtual rider goes into the ride record while the rider is moving
+    fn record_progress(&self, rider: &Rider) {
+        if rider.speed() <= 0.0 {
+            return;
+        }
+        let mut record = self.record.borrow_mut();
+        let now = Date::now() as usize;
+        record.set_lap(rider.lap());
+        record.record(now, RecordField::Distance, rider.distance());
+        record.record(now, RecordField::Altitude, rider.altitude());
+        record.record(now, RecordField::Grade, rider.grade());
+        record.record_position(now, rider.position());
     }
 
     pub fn msg(&mut self, msg: &Msg) -> bool {
         match msg {
             Msg::AdvanceClock(dt) => {
                 self.clock += dt;
+                let mut rider = self.rider.borrow_mut();
+                rider.advance(*dt);
+                self.record_progress(&rider);
+                let mut gearing = self.gearing.borrow_mut();
+                if self.simulation {
+                    self.trainer.set_simulation(gearing.simulation_parameters(rider.simulation_parameters()));
+                } else if self.hr_control.borrow().is_none() {
+                    // heart rate ERG holds the trainer in target power mode otherwise
+                    if let Some(level) = gearing.resistance_update(*dt, rider.simulation_parameters(),
+                                                                   self.profile.borrow().mass, rider.speed()) {
+                        self.trainer.set_target_resistance(level);
+                    }
+                }
+                if let Some(controller) = self.hr_control.borrow_mut().as_mut() {
+                    let since = (Date::now() - HR_AVERAGE_WINDOW as f64) as usize;
+                    let hr = self.record.borrow().mean_since(RecordField::HeartRate, since);
+                    if let Some(watts) = controller.update(hr, *dt) {
+                        self.trainer.set_target_power(watts);
+                    }
+                }
+                self.trainer.tick(*dt);
                 false
             }
             Msg::MouseDown(x, y) => {
@@ -149,6 +350,19 @@ impl State {
             Msg::KeyDown(key) => {
                 if *key == 82 { //'R'
                     self.show_pick = true;
+                } else if *key == 83 { //'S'
+                    self.simulation = !self.simulation;
+                    if !self.simulation {
+                        self.trainer.clear_simulation();
+                    } else {
+                        self.hr_control.borrow_mut().take();
+                    }
+                } else if *key == 72 { //'H'
+                    self.toggle_hr_control();
+                } else if *key == 33 { //PageUp
+                    self.adjust_hr_target(1.0);
+                } else if *key == 34 { //PageDown
+                    self.adjust_hr_target(-1.0);
                 }
                 false
             }
@@ -0,0 +1,312 @@
+use serde::{Deserialize, Serialize};
+
+use crate::timedata::PerSecond;
+
+/// s, durations a new personal record is announced for
+pub const PR_DURATIONS: [usize; 5] = [5, 60, 300, 1200, 3600];
+
+/// s, durations the best efforts of every ride are kept for
+pub const CURVE_DURATIONS: [usize; 18] = [
+    1, 5, 10, 15, 30, 60, 120, 180, 240, 300, 480, 600, 720, 900, 1200, 1800, 2400, 3600,
+];
+
+/// Days the recent bests go back
+pub const RECENT_DAYS: f64 = 90.0;
+
+/// ms between saves of the bests of the current ride
+const SAVE_INTERVAL: f64 = 60_000.0;
+
+const DAY: f64 = 24.0 * 3600.0 * 1000.0;
+
+/// Mean-maximal power of the ride: the best average power for every duration from 1 s
+/// to the ride length. Every second of power updates all durations from a running sum,
+/// efforts don't span gaps in the data.
+pub struct PowerCurve {
+    resampled: PerSecond,
+    /// Sum of the power of the seconds before each index
+    prefix: Vec<f64>,
+    /// Index in `prefix` where the current run of contiguous seconds starts
+    run_start: usize,
+    last_second: Option<usize>,
+    /// Watts, `best[k]` for `k + 1` seconds
+    best: Vec<f32>,
+}
+
+impl PowerCurve {
+    pub fn new() -> PowerCurve {
+        PowerCurve {
+            resampled: PerSecond::default(),
+            prefix: vec![0.0],
+            run_start: 0,
+            last_second: None,
+            best: Vec::new(),
+        }
+    }
+
+    /// Power reading received at `time` ms
+    pub fn push(&mut self, time: usize, power: f32) {
+        for (second, power) in self.resampled.push(time, power) {
+            self.add_second(second, power);
+        }
+    }
+
+    fn add_second(&mut self, second: usize, power: f32) {
+        let seconds = self.prefix.len() - 1;
+        if self.last_second.map_or(false, |last| second != last + 1) {
+            self.run_start = seconds;
+        }
+        self.last_second = Some(second);
+        let total = self.prefix[seconds] + power as f64;
+        self.prefix.push(total);
+
+        let end = seconds + 1;
+        for duration in 1..=end - self.run_start {
+            let average = ((total - self.prefix[end - duration]) / duration as f64) as f32;
+            match self.best.get_mut(duration - 1) {
+                Some(best) => *best = best.max(average),
+                None => self.best.push(average),
+            }
+        }
+    }
+
+    /// Watts, `None` if there was no effort that long
+    pub fn best(&self, duration: usize) -> Option<f32> {
+        duration.checked_sub(1).and_then(|k| self.best.get(k)).copied()
+    }
+
+    /// Watts for 1 s up to the longest effort
+    pub fn curve(&self) -> &[f32] {
+        &self.best
+    }
+}
+
+impl Default for PowerCurve {
+    fn default() -> Self {
+        PowerCurve::new()
+    }
+}
+
+/// Best efforts of one ride at `CURVE_DURATIONS`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RideBests {
+    /// ms since the epoch when the ride started
+    pub date: f64,
+    /// Duration in s and watts
+    pub efforts: Vec<(usize, f32)>,
+}
+
+impl RideBests {
+    pub fn effort(&self, duration: usize) -> Option<f32> {
+        self.efforts.iter().find(|(d, _)| *d == duration).map(|(_, p)| *p)
+    }
+}
+
+/// Best efforts of past rides, kept in local storage
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct PowerHistory {
+    pub rides: Vec<RideBests>,
+}
+
+impl PowerHistory {
+    /// Watts, best of the rides started at or after `since` ms
+    pub fn best(&self, duration: usize, since: f64) -> Option<f32> {
+        self.rides.iter()
+            .filter(|ride| ride.date >= since)
+            .filter_map(|ride| ride.effort(duration))
+            .fold(None, |best: Option<f32>, p| Some(best.map_or(p, |b| b.max(p))))
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RecordScope {
+    AllTime,
+    /// Best of the last `RECENT_DAYS`
+    Recent,
+}
+
+/// New best at one of `PR_DURATIONS`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PowerRecord {
+    /// s
+    pub duration: usize,
+    /// Watts
+    pub power: f32,
+    pub scope: RecordScope,
+}
+
+/// Bests of the current ride against the stored ones
+pub struct PersonalRecords {
+    /// Past rides
+    history: PowerHistory,
+    ride: RideBests,
+    unsaved: bool,
+    last_saved: f64,
+}
+
+impl PersonalRecords {
+    /// `start` ms since the epoch
+    pub fn new(history: PowerHistory, start: f64) -> PersonalRecords {
+        PersonalRecords {
+            history,
+            ride: RideBests {
+                date: start,
+                efforts: Vec::new(),
+            },
+            unsaved: false,
+            last_saved: start,
+        }
+    }
+
+    /// Take the bests of the ride's curve, returns the ones beating the all-time or recent
+    /// best at a PR duration. Durations without a stored effort have nothing to beat.
+    pub fn update(&mut self, curve: &PowerCurve, now: f64) -> Vec<PowerRecord> {
+        let mut records = Vec::new();
+        for &duration in &CURVE_DURATIONS {
+            let power = match curve.best(duration) {
+                Some(power) => power,
+                None => continue,
+            };
+            match self.ride.efforts.iter_mut().find(|(d, _)| *d == duration) {
+                Some((_, best)) if power <= *best => continue,
+                Some((_, best)) => *best = power,
+                None => self.ride.efforts.push((duration, power)),
+            }
+            self.unsaved = true;
+
+            if !PR_DURATIONS.contains(&duration) {
+                continue;
+            }
+            let scope = if self.all_time(duration).map_or(false, |best| power > best) {
+                RecordScope::AllTime
+            } else if self.recent(duration, now).map_or(false, |best| power > best) {
+                RecordScope::Recent
+            } else {
+                continue;
+            };
+            records.push(PowerRecord { duration, power, scope });
+        }
+        records
+    }
+
+    /// Watts, best of the past rides
+    pub fn all_time(&self, duration: usize) -> Option<f32> {
+        self.history.best(duration, f64::NEG_INFINITY)
+    }
+
+    /// Watts, best of the past rides of the last `RECENT_DAYS` before `now`
+    pub fn recent(&self, duration: usize, now: f64) -> Option<f32> {
+        self.history.best(duration, now - RECENT_DAYS * DAY)
+    }
+
+    /// Best efforts at `CURVE_DURATIONS` of the past rides started at or after `since` ms
+    /// and of the current ride
+    pub fn efforts(&self, since: f64) -> Vec<(usize, f32)> {
+        CURVE_DURATIONS.iter()
+            .filter_map(|&duration| {
+                let past = self.history.best(duration, since);
+                let best = match (past, self.ride.effort(duration)) {
+                    (Some(past), Some(ride)) => past.max(ride),
+                    (past, ride) => past.or(ride)?,
+                };
+                Some((duration, best))
+            })
+            .collect()
+    }
+
+    /// Past rides and the current one to be saved, if the current one improved
+    /// and it wasn't saved for a while
+    pub fn take_unsaved(&mut self, now: f64) -> Option<PowerHistory> {
+        if !self.unsaved || now - self.last_saved < SAVE_INTERVAL {
+            return None;
+        }
+        self.unsaved = false;
+        self.last_saved = now;
+        let mut history = self.history.clone();
+        history.rides.push(self.ride.clone());
+        Some(history)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timedata::GAP;
+
+    fn curve(power: &[f32]) -> PowerCurve {
+        let mut curve = PowerCurve::new();
+        for (k, p) in power.iter().enumerate() {
+            curve.push(k * 1000, *p);
+        }
+        curve.push(power.len() * 1000, GAP);
+        curve
+    }
+
+    /// Best average straight from the definition
+    fn reference(power: &[f32], duration: usize) -> f32 {
+        power.windows(duration).map(|w| w.iter().sum::<f32>() / duration as f32).fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn matches_brute_force() {
+        let power: Vec<f32> = (0..600).map(|k| 150.0 + ((k * 37) % 101) as f32 + if k % 97 < 8 { 500.0 } else { 0.0 }).collect();
+        let curve = curve(&power);
+        assert_eq!(curve.curve().len(), 600);
+        for duration in &[1, 5, 8, 60, 300, 600] {
+            assert!((curve.best(*duration).unwrap() - reference(&power, *duration)).abs() < 0.01, "{} s", duration);
+        }
+        assert_eq!(curve.best(601), None);
+        assert_eq!(curve.best(0), None);
+    }
+
+    #[test]
+    fn efforts_dont_span_gaps() {
+        let mut curve = PowerCurve::new();
+        for k in 0..10 {
+            curve.push(k * 1000, 300.0);
+        }
+        curve.push(10_000, GAP);
+        for k in 30..40 {
+            curve.push(k * 1000, 400.0);
+        }
+        curve.push(40_000, GAP);
+        assert_eq!(curve.best(10), Some(400.0));
+        assert_eq!(curve.best(5), Some(400.0));
+        assert_eq!(curve.best(11), None);
+    }
+
+    #[test]
+    fn announces_records_against_history() {
+        let now = 1000.0 * DAY;
+        let history = PowerHistory {
+            rides: vec![
+                RideBests { date: now - 200.0 * DAY, efforts: vec![(5, 900.0), (60, 500.0)] },
+                RideBests { date: now - 30.0 * DAY, efforts: vec![(5, 700.0), (60, 400.0)] },
+            ],
+        };
+        let mut records = PersonalRecords::new(history, now);
+        assert_eq!(records.all_time(5), Some(900.0));
+        assert_eq!(records.recent(5, now), Some(700.0));
+
+        let mut power = vec![200.0; 55];
+        power.extend_from_slice(&[800.0; 5]);
+        let prs = records.update(&curve(&power), now);
+        assert_eq!(prs, vec![PowerRecord { duration: 5, power: 800.0, scope: RecordScope::Recent }]);
+        // nothing new, nothing announced
+        assert!(records.update(&curve(&power), now).is_empty());
+
+        power.extend_from_slice(&[1000.0; 5]);
+        let prs = records.update(&curve(&power), now);
+        assert_eq!(prs[0], PowerRecord { duration: 5, power: 1000.0, scope: RecordScope::AllTime });
+
+        assert!(records.take_unsaved(now).is_none());
+        let saved = records.take_unsaved(now + SAVE_INTERVAL).unwrap();
+        assert_eq!(saved.rides.len(), 3);
+        assert_eq!(saved.best(5, now), Some(1000.0));
+        assert!(records.take_unsaved(now + 2.0 * SAVE_INTERVAL).is_none());
+
+        assert_eq!(records.efforts(now - RECENT_DAYS * DAY)[..2], [(1, 1000.0), (5, 1000.0)]);
+        assert!(records.efforts(now - RECENT_DAYS * DAY).contains(&(60, 400.0)));
+        assert!(records.efforts(0.0).contains(&(60, 500.0)));
+    }
+}
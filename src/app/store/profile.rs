@@ -0,0 +1,127 @@
+use serde::{Deserialize, Serialize};
+use web_sys::Storage;
+
+use crate::bluetooth::simulated::SimulatorSettings;
+use crate::gearing::{Drivetrain, ShiftBindings};
+use crate::cp_model::CpFit;
+use crate::hr_control::HrControlSettings;
+use crate::power_curve::PowerHistory;
+use crate::rider::{Course, Rider, DEFAULT_CRR, DEFAULT_CW};
+use crate::wbal::{WbalModel, DEFAULT_CP, DEFAULT_W_PRIME};
+
+const STORAGE_KEY: &str = "user_profile";
+const POWER_HISTORY_KEY: &str = "power_history";
+
+/// Rider settings kept in local storage
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct UserProfile {
+    /// Rider and bike, kg
+    pub mass: f32,
+    /// Rider alone, kg
+    pub weight: f32,
+    /// Functional threshold power, W
+    pub ftp: f32,
+    /// Critical power, W
+    pub cp: f32,
+    /// Work capacity above critical power, J
+    pub w_prime: f32,
+    pub w_balance_model: WbalModel,
+    /// Elevation profile of the course ridden in simulation, (distance m, elevation m)
+    /// starting at 0 m, the velodrome if it isn't valid
+    pub course: Vec<(f32, f32)>,
+    /// Rolling resistance coefficient of tyres and surface
+    pub crr: f32,
+    /// Wind resistance coefficient of rider and bike, kg/m
+    pub cw: f32,
+    /// m/s, positive is headwind
+    pub wind_speed: f32,
+    pub drivetrain: Drivetrain,
+    pub shift_bindings: ShiftBindings,
+    pub hr_control: HrControlSettings,
+    /// Demo rider serving roles without a real device
+    pub simulator: SimulatorSettings,
+    /// ANT+ network key as hex digits. It is licensed per application and not
+    /// shipped with the app, the rider has to enter it before using an ANT stick.
+    pub ant_network_key: String,
+}
+
+impl UserProfile {
+    pub fn load() -> UserProfile {
+        Self::storage()
+            .and_then(|s| s.get_item(STORAGE_KEY).ok().flatten())
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) {
+        let storage = match Self::storage() {
+            Some(storage) => storage,
+            None => return,
+        };
+        if let Ok(json) = serde_json::to_string(self) {
+            storage.set_item(STORAGE_KEY, &json).ok();
+        }
+    }
+
+    /// Take CP, W' and FTP from a model fitted to the rider's efforts
+    pub fn apply_cp_fit(&mut self, fit: &CpFit) {
+        self.cp = fit.cp;
+        self.w_prime = fit.w_prime;
+        self.ftp = fit.ftp;
+    }
+
+    /// Rider on the course of the profile with its resistance and wind
+    pub fn rider(&self) -> Rider {
+        let course = Course::from_points(self.course.clone()).unwrap_or_else(Course::velodrome);
+        let mut rider = Rider::new(course);
+        rider.set_resistance(self.crr, self.cw);
+        rider.set_wind_speed(self.wind_speed);
+        rider
+    }
+
+    fn storage() -> Option<Storage> {
+        web_sys::window()?.local_storage().ok().flatten()
+    }
+}
+
+impl Default for UserProfile {
+    fn default() -> Self {
+        UserProfile {
+            mass: 80.0,
+            weight: 72.0,
+            ftp: 200.0,
+            cp: DEFAULT_CP,
+            w_prime: DEFAULT_W_PRIME,
+            w_balance_model: WbalModel::default(),
+            course: Course::rolling().points().to_vec(),
+            crr: DEFAULT_CRR,
+            cw: DEFAULT_CW,
+            wind_speed: 0.0,
+            drivetrain: Drivetrain::default(),
+            shift_bindings: ShiftBindings::default(),
+            hr_control: HrControlSettings::default(),
+            simulator: SimulatorSettings::default(),
+            ant_network_key: String::new(),
+        }
+    }
+}
+
+impl PowerHistory {
+    pub fn load() -> PowerHistory {
+        UserProfile::storage()
+            .and_then(|s| s.get_item(POWER_HISTORY_KEY).ok().flatten())
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) {
+        let storage = match UserProfile::storage() {
+            Some(storage) => storage,
+            None => return,
+        };
+        if let Ok(json) = serde_json::to_string(self) {
+            storage.set_item(POWER_HISTORY_KEY, &json).ok();
+        }
+    }
+}
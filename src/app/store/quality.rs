@@ -0,0 +1,246 @@
+/// Live measurement checked by `DataQuality`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Channel {
+    HeartRate,
+    Power,
+    Speed,
+    Cadence,
+    /// Body temperature
+    Temperature,
+    /// Muscle oxygen saturation
+    SmO2,
+    /// Total hemoglobin
+    Thb,
+}
+
+impl Channel {
+    pub const ALL: [Channel; 7] = [
+        Channel::HeartRate, Channel::Power, Channel::Speed, Channel::Cadence,
+        Channel::Temperature, Channel::SmO2, Channel::Thb,
+    ];
+
+    fn limits(&self) -> ChannelLimits {
+        match self {
+            Channel::HeartRate => ChannelLimits { min: 25.0, max: 240.0, max_rate: Some(20.0), stale_after: 5000.0 },
+            Channel::Power => ChannelLimits { min: 0.0, max: 2500.0, max_rate: None, stale_after: 3000.0 },
+            // m/s
+            Channel::Speed => ChannelLimits { min: 0.0, max: 28.0, max_rate: Some(5.0), stale_after: 3000.0 },
+            Channel::Cadence => ChannelLimits { min: 0.0, max: 220.0, max_rate: None, stale_after: 3000.0 },
+            // °C, thermometers may report only every few seconds
+            Channel::Temperature => ChannelLimits { min: 30.0, max: 45.0, max_rate: Some(0.5), stale_after: 30000.0 },
+            // percent
+            Channel::SmO2 => ChannelLimits { min: 0.0, max: 100.0, max_rate: Some(10.0), stale_after: 5000.0 },
+            // g/dl
+            Channel::Thb => ChannelLimits { min: 0.0, max: 40.0, max_rate: None, stale_after: 5000.0 },
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Quality {
+    /// Nothing received yet
+    NoData,
+    Good,
+    /// No update within the channel's stale time, the last value shouldn't be shown as current
+    Stale,
+}
+
+/// Bounds of physiologically or physically possible readings
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ChannelLimits {
+    pub min: f32,
+    pub max: f32,
+    /// Largest plausible change per second, `None` if any jump is possible
+    pub max_rate: Option<f32>,
+    /// ms
+    pub stale_after: f32,
+}
+
+/// Rejected readings in a row after which the new level is taken as real
+const MAX_REJECTIONS: u32 = 3;
+
+struct ChannelFilter {
+    limits: ChannelLimits,
+    quality: Quality,
+    last: Option<f32>,
+    /// Time since the last accepted reading, ms
+    since_accepted: f32,
+    rejected: u32,
+    changed: bool,
+}
+
+impl ChannelFilter {
+    fn new(limits: ChannelLimits) -> ChannelFilter {
+        ChannelFilter {
+            limits,
+            quality: Quality::NoData,
+            last: None,
+            since_accepted: 0.0,
+            rejected: 0,
+            changed: false,
+        }
+    }
+
+    fn accept(&mut self, value: f32) -> bool {
+        if !value.is_finite() || value < self.limits.min || value > self.limits.max {
+            return false;
+        }
+        if let (Some(last), Some(rate), Quality::Good) = (self.last, self.limits.max_rate, self.quality) {
+            let allowed = rate * (self.since_accepted / 1000.0).max(1.0);
+            if (value - last).abs() > allowed && self.rejected < MAX_REJECTIONS {
+                self.rejected += 1;
+                return false;
+            }
+        }
+        self.rejected = 0;
+        self.last = Some(value);
+        self.since_accepted = 0.0;
+        self.set_quality(Quality::Good);
+        true
+    }
+
+    fn tick(&mut self, dt: f32) {
+        self.since_accepted += dt;
+        if self.quality == Quality::Good && self.since_accepted >= self.limits.stale_after {
+            self.set_quality(Quality::Stale);
+        }
+    }
+
+    fn set_quality(&mut self, quality: Quality) {
+        if self.quality != quality {
+            self.quality = quality;
+            self.changed = true;
+        }
+    }
+}
+
+/// Filters live readings before they reach the time series: rejects impossible values
+/// and spikes, and tracks which channels went stale
+pub struct DataQuality {
+    filters: Vec<(Channel, ChannelFilter)>,
+}
+
+impl DataQuality {
+    pub fn new() -> DataQuality {
+        DataQuality {
+            filters: Channel::ALL.iter().map(|c| (*c, ChannelFilter::new(c.limits()))).collect(),
+        }
+    }
+
+    /// Whether `value` should be recorded
+    pub fn accept(&mut self, channel: Channel, value: f32) -> bool {
+        self.filter(channel).accept(value)
+    }
+
+    pub fn quality(&self, channel: Channel) -> Quality {
+        self.filters.iter()
+            .find(|(c, _)| *c == channel)
+            .map(|(_, f)| f.quality)
+            .unwrap_or(Quality::NoData)
+    }
+
+    /// Advance by `dt` ms
+    pub fn tick(&mut self, dt: f32) {
+        for (_, filter) in self.filters.iter_mut() {
+            filter.tick(dt);
+        }
+    }
+
+    /// Channels whose quality changed since the last call
+    pub fn take_changes(&mut self) -> Vec<(Channel, Quality)> {
+        self.filters.iter_mut()
+            .filter(|(_, f)| f.changed)
+            .map(|(c, f)| {
+                f.changed = false;
+                (*c, f.quality)
+            })
+            .collect()
+    }
+
+    fn filter(&mut self, channel: Channel) -> &mut ChannelFilter {
+        &mut self.filters.iter_mut().find(|(c, _)| *c == channel).unwrap().1
+    }
+}
+
+impl Default for DataQuality {
+    fn default() -> Self {
+        DataQuality::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_out_of_range_readings() {
+        let mut quality = DataQuality::new();
+        assert!(!quality.accept(Channel::HeartRate, 20.0));
+        assert!(!quality.accept(Channel::HeartRate, 250.0));
+        assert!(!quality.accept(Channel::Power, std::f32::NAN));
+        assert!(!quality.accept(Channel::Temperature, 29.0));
+        // rejected readings don't count as data
+        assert_eq!(quality.quality(Channel::HeartRate), Quality::NoData);
+        assert!(quality.take_changes().is_empty());
+        assert!(quality.accept(Channel::HeartRate, 25.0));
+        assert!(quality.accept(Channel::Power, 2500.0));
+        assert!(quality.accept(Channel::Temperature, 37.0));
+    }
+
+    #[test]
+    fn rejects_spikes_until_the_new_level_persists() {
+        let mut quality = DataQuality::new();
+        assert!(quality.accept(Channel::HeartRate, 120.0));
+        quality.tick(1000.0);
+        assert!(!quality.accept(Channel::HeartRate, 180.0));
+        // the allowed change grows with the time since the last accepted reading
+        assert!(quality.accept(Channel::HeartRate, 140.0));
+        quality.tick(3000.0);
+        assert!(quality.accept(Channel::HeartRate, 195.0));
+
+        for _ in 0..MAX_REJECTIONS {
+            assert!(!quality.accept(Channel::HeartRate, 100.0));
+        }
+        assert!(quality.accept(Channel::HeartRate, 100.0));
+        // channels without a rate limit take any jump
+        assert!(quality.accept(Channel::Power, 100.0));
+        assert!(quality.accept(Channel::Power, 1200.0));
+    }
+
+    #[test]
+    fn goes_stale_without_readings() {
+        let mut quality = DataQuality::new();
+        quality.accept(Channel::Power, 200.0);
+        quality.tick(2999.0);
+        assert_eq!(quality.quality(Channel::Power), Quality::Good);
+        quality.tick(1.0);
+        assert_eq!(quality.quality(Channel::Power), Quality::Stale);
+        // a channel without data never goes stale
+        assert_eq!(quality.quality(Channel::HeartRate), Quality::NoData);
+
+        // the first reading after a dropout may be at any level
+        assert!(quality.accept(Channel::Speed, 5.0));
+        quality.tick(3000.0);
+        assert!(quality.accept(Channel::Speed, 15.0));
+        assert_eq!(quality.quality(Channel::Speed), Quality::Good);
+    }
+
+    #[test]
+    fn reports_each_transition_once() {
+        let mut quality = DataQuality::new();
+        quality.accept(Channel::HeartRate, 120.0);
+        quality.accept(Channel::Cadence, 90.0);
+        assert_eq!(quality.take_changes(), vec![(Channel::HeartRate, Quality::Good), (Channel::Cadence, Quality::Good)]);
+        quality.accept(Channel::HeartRate, 121.0);
+        assert!(quality.take_changes().is_empty());
+
+        quality.tick(3000.0);
+        assert_eq!(quality.take_changes(), vec![(Channel::Cadence, Quality::Stale)]);
+        quality.tick(2000.0);
+        assert_eq!(quality.take_changes(), vec![(Channel::HeartRate, Quality::Stale)]);
+        quality.tick(10000.0);
+        assert!(quality.take_changes().is_empty());
+        quality.accept(Channel::Cadence, 90.0);
+        assert_eq!(quality.take_changes(), vec![(Channel::Cadence, Quality::Good)]);
+    }
+}
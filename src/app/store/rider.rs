@@ -0,0 +1,261 @@
+use std::f64::consts::PI;
+
+use crate::bluetooth::ftms::SimulationParameters;
+use crate::timedata::Position;
+
+const VELODROME_LAP: f32 = 250.0;
+pub const DEFAULT_CRR: f32 = 0.004;
+pub const DEFAULT_CW: f32 = 0.51;
+/// Where courses are laid out on the map, they have no coordinates of their own
+const COURSE_ORIGIN: Position = Position { latitude: 46.5, longitude: 7.9 };
+/// m per degree of latitude
+const METERS_PER_DEGREE: f64 = 111_320.0;
+
+/// (distance m, elevation m) of the built-in 4 km loop with two climbs
+const ROLLING_LOOP: [(f32, f32); 9] = [
+    (0.0, 0.0), (500.0, 0.0), (1000.0, 30.0), (1500.0, 40.0), (2000.0, 40.0),
+    (2500.0, 10.0), (3000.0, 10.0), (3500.0, 25.0), (4000.0, 0.0),
+];
+
+/// Elevation profile of the ridden course, repeated every lap
+pub struct Course {
+    /// (distance m, elevation m) sorted by distance, starting at 0
+    profile: Vec<(f32, f32)>,
+    lap: f32,
+}
+
+impl Course {
+    pub fn new(profile: Vec<(f32, f32)>) -> Course {
+        let lap = profile.last().map(|p| p.0).unwrap_or(0.0);
+        Course { profile, lap }
+    }
+
+    /// Elevation profile as loaded from the user profile, `None` unless it starts at 0 m
+    /// and the distance increases from point to point
+    pub fn from_points(profile: Vec<(f32, f32)>) -> Option<Course> {
+        if profile.len() < 2 || profile[0].0 != 0.0 || profile.windows(2).any(|w| w[1].0 <= w[0].0) {
+            return None;
+        }
+        Some(Course::new(profile))
+    }
+
+    /// 250 m track. The measurement line is level all the way round, the banking tilts
+    /// the track across the direction of travel only, so there is no grade to simulate.
+    pub fn velodrome() -> Course {
+        Course::new(vec![(0.0, 0.0), (VELODROME_LAP, 0.0)])
+    }
+
+    /// Built-in 4 km loop with grades up to 6 %
+    pub fn rolling() -> Course {
+        Course::new(ROLLING_LOOP.to_vec())
+    }
+
+    /// (distance m, elevation m)
+    pub fn points(&self) -> &[(f32, f32)] {
+        &self.profile
+    }
+
+    pub fn lap(&self) -> f32 {
+        self.lap
+    }
+
+    /// Elevation in m at the given distance from start, interpolated between profile points
+    pub fn elevation_at(&self, distance: f32) -> f32 {
+        if self.lap <= 0.0 {
+            return self.profile.first().map(|p| p.1).unwrap_or(0.0);
+        }
+        let d = distance.rem_euclid(self.lap);
+        self.profile.windows(2)
+            .find(|w| d >= w[0].0 && d < w[1].0)
+            .map(|w| w[0].1 + (w[1].1 - w[0].1) * (d - w[0].0) / (w[1].0 - w[0].0))
+            .unwrap_or(0.0)
+    }
+
+    /// Map position at the given distance from start. The lap is laid out as a circle
+    /// starting south of `COURSE_ORIGIN` and ridden counterclockwise.
+    pub fn position_at(&self, distance: f32) -> Position {
+        if self.lap <= 0.0 {
+            return COURSE_ORIGIN;
+        }
+        let radius = self.lap as f64 / (2.0 * PI);
+        let angle = distance.rem_euclid(self.lap) as f64 / radius;
+        let latitude = COURSE_ORIGIN.latitude - radius * angle.cos() / METERS_PER_DEGREE;
+        let longitude = COURSE_ORIGIN.longitude
+            + radius * angle.sin() / (METERS_PER_DEGREE * COURSE_ORIGIN.latitude.to_radians().cos());
+        Position { latitude, longitude }
+    }
+
+    /// Gradient in percent at the given distance from start
+    pub fn grade_at(&self, distance: f32) -> f32 {
+        if self.lap <= 0.0 {
+            return 0.0;
+        }
+        let d = distance.rem_euclid(self.lap);
+        self.profile.windows(2)
+            .find(|w| d >= w[0].0 && d < w[1].0)
+            .map(|w| (w[1].1 - w[0].1) / (w[1].0 - w[0].0) * 100.0)
+            .unwrap_or(0.0)
+    }
+}
+
+/// Virtual rider position on the course
+pub struct Rider {
+    course: Course,
+    /// m
+    distance: f32,
+    /// m/s
+    speed: f32,
+    /// m/s, positive is headwind
+    wind_speed: f32,
+    crr: f32,
+    cw: f32,
+}
+
+impl Rider {
+    pub fn new(course: Course) -> Rider {
+        Rider {
+            course,
+            distance: 0.0,
+            speed: 0.0,
+            wind_speed: 0.0,
+            crr: DEFAULT_CRR,
+            cw: DEFAULT_CW,
+        }
+    }
+
+    pub fn set_course(&mut self, course: Course) {
+        self.course = course;
+        self.distance = 0.0;
+    }
+
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed;
+    }
+
+    pub fn set_wind_speed(&mut self, wind_speed: f32) {
+        self.wind_speed = wind_speed;
+    }
+
+    pub fn set_resistance(&mut self, crr: f32, cw: f32) {
+        self.crr = crr;
+        self.cw = cw;
+    }
+
+    pub fn distance(&self) -> f32 {
+        self.distance
+    }
+
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    pub fn grade(&self) -> f32 {
+        self.course.grade_at(self.distance)
+    }
+
+    /// m
+    pub fn altitude(&self) -> f32 {
+        self.course.elevation_at(self.distance)
+    }
+
+    pub fn position(&self) -> Position {
+        self.course.position_at(self.distance)
+    }
+
+    /// Laps of the course completed
+    pub fn lap(&self) -> u32 {
+        if self.course.lap() <= 0.0 {
+            return 0;
+        }
+        (self.distance / self.course.lap()) as u32
+    }
+
+    /// Move along the course, `dt` in ms
+    pub fn advance(&mut self, dt: f32) {
+        self.distance += self.speed * dt / 1000.0;
+    }
+
+    pub fn simulation_parameters(&self) -> SimulationParameters {
+        SimulationParameters {
+            wind_speed: self.wind_speed,
+            grade: self.grade(),
+            crr: self.crr,
+            cw: self.cw,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interpolates_elevation_and_grade() {
+        let course = Course::from_points(vec![(0.0, 0.0), (100.0, 5.0), (200.0, 0.0)]).unwrap();
+        assert_eq!(course.lap(), 200.0);
+        assert_eq!(course.elevation_at(50.0), 2.5);
+        assert_eq!(course.grade_at(50.0), 5.0);
+        assert_eq!(course.grade_at(150.0), -5.0);
+        // the course repeats every lap
+        assert_eq!(course.elevation_at(250.0), 2.5);
+        assert_eq!(course.grade_at(350.0), -5.0);
+        assert_eq!(Course::velodrome().grade_at(120.0), 0.0);
+        assert!(Course::rolling().points().windows(2).any(|w| w[1].1 != w[0].1));
+    }
+
+    #[test]
+    fn rejects_invalid_profiles() {
+        assert!(Course::from_points(vec![]).is_none());
+        assert!(Course::from_points(vec![(0.0, 10.0)]).is_none());
+        assert!(Course::from_points(vec![(10.0, 0.0), (100.0, 5.0)]).is_none());
+        assert!(Course::from_points(vec![(0.0, 0.0), (100.0, 5.0), (100.0, 6.0)]).is_none());
+        assert!(Course::from_points(ROLLING_LOOP.to_vec()).is_some());
+    }
+
+    #[test]
+    fn simulates_grade_at_rider_position() {
+        let mut rider = Rider::new(Course::rolling());
+        rider.set_speed(10.0);
+        rider.advance(60_000.0);
+        // 600 m in, on the first climb
+        assert_eq!(rider.distance(), 600.0);
+        assert_eq!(rider.lap(), 0);
+        assert!((rider.altitude() - 6.0).abs() < 1e-4);
+        assert_eq!(rider.simulation_parameters(),
+                   SimulationParameters { wind_speed: 0.0, grade: 6.0, crr: DEFAULT_CRR, cw: DEFAULT_CW });
+
+        rider.set_wind_speed(-3.0);
+        rider.set_resistance(0.006, 0.35);
+        rider.advance(200_000.0);
+        // 2600 m in, past the descent
+        assert_eq!(rider.simulation_parameters(),
+                   SimulationParameters { wind_speed: -3.0, grade: 0.0, crr: 0.006, cw: 0.35 });
+        rider.advance(200_000.0);
+        assert_eq!(rider.lap(), 1);
+        assert!((rider.grade() - 6.0).abs() < 1e-4);
+
+        rider.set_course(Course::velodrome());
+        assert_eq!(rider.distance(), 0.0);
+        assert_eq!(rider.simulation_parameters().grade, 0.0);
+    }
+
+    #[test]
+    fn lays_the_lap_out_on_the_map() {
+        let course = Course::velodrome();
+        let start = course.position_at(0.0);
+        assert_eq!(course.position_at(VELODROME_LAP), start);
+        let opposite = course.position_at(VELODROME_LAP / 2.0);
+        // the lap is a circle, half way round is one diameter north of the start
+        let diameter = (opposite.latitude - start.latitude) * METERS_PER_DEGREE;
+        assert!((diameter - VELODROME_LAP as f64 / PI).abs() < 1e-6, "{}", diameter);
+        assert!((opposite.longitude - start.longitude).abs() < 1e-9);
+        let quarter = course.position_at(VELODROME_LAP / 4.0);
+        assert!(quarter.longitude > start.longitude);
+
+        let mut rider = Rider::new(Course::rolling());
+        assert_eq!(rider.position(), Course::rolling().position_at(0.0));
+        rider.set_speed(10.0);
+        rider.advance(100_000.0);
+        assert_eq!(rider.position(), Course::rolling().position_at(1000.0));
+    }
+}
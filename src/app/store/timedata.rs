@@ -1,68 +1,1311 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
 use std::rc::Rc;
 use js_sys::Date;
 
+use crate::bluetooth::power::{PowerMeasurement, PowerVector, TorqueSource};
+use crate::power_curve::PowerCurve;
+use crate::training::{TrainingLoad, TrainingMetrics};
+use crate::wbal::{WBalance, WbalModel, DEFAULT_CP, DEFAULT_W_PRIME};
+
+/// Default HRV window, ms
+pub const HRV_WINDOW: f32 = 60000.0;
+
+/// Value marking where a series has no data because the sensor went stale,
+/// nothing is interpolated across it
+pub const GAP: f32 = f32::NAN;
+
+pub fn is_gap(val: f32) -> bool {
+    val.is_nan()
+}
+
 pub trait TimeSeries<T> {
     fn fetch_data(self: &Rc<Self>, start_time: usize, end_time: usize, step: f32) -> Box<dyn Iterator<Item=T>>;
+
+    /// Every sample from `start_time` to `end_time` inclusive with its time, gaps included
+    fn samples(&self, start_time: usize, end_time: usize) -> Vec<(usize, T)>;
+
+    /// Samples of the range reduced to `buckets` buckets of equal duration, e.g. the pixel
+    /// columns of a chart. Unlike a fixed step this keeps the spikes.
+    fn downsample(&self, start_time: usize, end_time: usize, buckets: usize, strategy: Downsampling) -> Vec<(usize, T)>
+    where T: Copy + Into<f64> {
+        let samples = self.samples(start_time, end_time);
+        match strategy {
+            Downsampling::MinMax => min_max_buckets(&samples, start_time, end_time, buckets),
+            Downsampling::Lttb => lttb_with_gaps(&samples, buckets),
+        }
+    }
 }
 
-pub struct HrmData {
-    pub data: Vec<(usize, f32)>,
+/// How `TimeSeries::downsample` picks the samples of a bucket
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Downsampling {
+    /// Lowest and highest sample of every bucket with data, in time order, so a chart gets
+    /// one vertex pair per pixel column. A bucket with nothing but gaps yields a gap pair.
+    MinMax,
+    /// Largest-Triangle-Three-Buckets: the sample spanning the largest triangle with its
+    /// neighbours, one per bucket, first and last kept. Smoother than `MinMax`.
+    Lttb,
+}
 
-    //iters : Vec<Box<TimeSeriesIterator<f32>>>,
+fn is_gap_value<T: Copy + Into<f64>>(value: T) -> bool {
+    value.into().is_nan()
 }
 
-struct HrmDataIter {
-    data: Rc<HrmData>,
-    start_time: usize,
-    end_time: usize,
-    step: f32,
-    last_t: f32,
-    pos: usize,
+fn min_max_buckets<T: Copy + Into<f64>>(samples: &[(usize, T)], start_time: usize, end_time: usize,
+                                         buckets: usize) -> Vec<(usize, T)> {
+    let mut reduced = Vec::with_capacity(2 * buckets);
+    if buckets == 0 || end_time < start_time {
+        return reduced;
+    }
+    // 64 bits, a long ride in ms times the columns of a wide chart overflows 32
+    let span = (end_time - start_time) as u64 + 1;
+    let bucket_of = |t: usize| (t.saturating_sub(start_time) as u64 * buckets as u64 / span) as usize;
+
+    let mut first = 0;
+    while first < samples.len() {
+        let bucket = bucket_of(samples[first].0);
+        let last = first + samples[first..].iter().take_while(|(t, _)| bucket_of(*t) == bucket).count();
+        let values = samples[first..last].iter().filter(|(_, v)| !is_gap_value(*v));
+        let lowest = values.clone().min_by(|a, b| a.1.into().partial_cmp(&b.1.into()).unwrap());
+        let highest = values.max_by(|a, b| a.1.into().partial_cmp(&b.1.into()).unwrap());
+        match (lowest, highest) {
+            (Some(lowest), Some(highest)) if lowest.0 <= highest.0 => reduced.extend_from_slice(&[*lowest, *highest]),
+            (Some(lowest), Some(highest)) => reduced.extend_from_slice(&[*highest, *lowest]),
+            _ => reduced.extend_from_slice(&[samples[first], samples[first]]),
+        }
+        first = last;
+    }
+    reduced
+}
+
+/// Runs between gaps are reduced on their own, with buckets shared in proportion
+/// to their length, and a gap is kept between them
+fn lttb_with_gaps<T: Copy + Into<f64>>(samples: &[(usize, T)], buckets: usize) -> Vec<(usize, T)> {
+    let total = samples.len();
+    let mut reduced = Vec::new();
+    let mut first = 0;
+    while first < total {
+        if is_gap_value(samples[first].1) {
+            // one gap is enough to break the line
+            if reduced.last().map_or(false, |(_, v)| !is_gap_value(*v)) {
+                reduced.push(samples[first]);
+            }
+            first += 1;
+            continue;
+        }
+        let len = samples[first..].iter().take_while(|(_, v)| !is_gap_value(*v)).count();
+        let share = (buckets * len + total - 1) / total;
+        reduced.extend(lttb(&samples[first..first + len], share));
+        first += len;
+    }
+    reduced
+}
+
+fn lttb<T: Copy + Into<f64>>(samples: &[(usize, T)], threshold: usize) -> Vec<(usize, T)> {
+    let n = samples.len();
+    if threshold >= n || n < 3 {
+        return samples.to_vec();
+    }
+    if threshold < 3 {
+        return vec![samples[0], samples[n - 1]];
+    }
+    let point = |k: usize| (samples[k].0 as f64, samples[k].1.into());
+    // first and last are kept, the others go into threshold - 2 buckets
+    let every = (n - 2) as f64 / (threshold - 2) as f64;
+    let mut reduced = Vec::with_capacity(threshold);
+    reduced.push(samples[0]);
+    let mut selected = 0;
+    for bucket in 0..threshold - 2 {
+        let next_start = ((bucket + 1) as f64 * every) as usize + 1;
+        let next_end = (((bucket + 2) as f64 * every) as usize + 1).min(n);
+        let count = (next_end - next_start) as f64;
+        let (avg_x, avg_y) = (next_start..next_end)
+            .map(point)
+            .fold((0.0, 0.0), |(x, y), (px, py)| (x + px / count, y + py / count));
+
+        let start = (bucket as f64 * every) as usize + 1;
+        let end = next_start;
+        let (ax, ay) = point(selected);
+        let area = |k: usize| {
+            let (x, y) = point(k);
+            ((ax - avg_x) * (y - ay) - (ax - x) * (avg_y - ay)).abs()
+        };
+        selected = (start..end).fold(start, |best, k| if area(k) > area(best) { k } else { best });
+        reduced.push(samples[selected]);
+    }
+    reduced.push(samples[n - 1]);
+    reduced
+}
+
+/// Per-second rows, readings arriving within the same second share a row
+pub const RECORD_RESOLUTION: usize = 1000;
+
+/// Channels of the ride record holding one value per row
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RecordField {
+    /// bpm
+    HeartRate,
+    /// Watts
+    Power,
+    /// rpm
+    Cadence,
+    /// m/s
+    Speed,
+    /// m from start
+    Distance,
+    /// m
+    Altitude,
+    /// Percent
+    Grade,
+    /// J of W' left, derived from power
+    WBalance,
+    /// °C
+    Temperature,
+    /// Percent of hemoglobin saturated with oxygen
+    SmO2,
+    /// Total hemoglobin, g/dl
+    THb,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Position {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+/// Everything recorded at one moment. A channel without a reading in the row is `None`,
+/// one that went stale holds `GAP`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RecordRow {
+    /// ms
+    pub time: usize,
+    pub heart_rate: Option<f32>,
+    pub power: Option<f32>,
+    pub cadence: Option<f32>,
+    pub speed: Option<f32>,
+    pub distance: Option<f32>,
+    pub altitude: Option<f32>,
+    pub grade: Option<f32>,
+    pub w_balance: Option<f32>,
+    pub temperature: Option<f32>,
+    pub smo2: Option<f32>,
+    pub thb: Option<f32>,
+    pub position: Option<Position>,
+    /// Counted from 0
+    pub lap: u32,
+}
+
+impl RecordRow {
+    fn new(time: usize, lap: u32) -> RecordRow {
+        RecordRow {
+            time,
+            heart_rate: None,
+            power: None,
+            cadence: None,
+            speed: None,
+            distance: None,
+            altitude: None,
+            grade: None,
+            w_balance: None,
+            temperature: None,
+            smo2: None,
+            thb: None,
+            position: None,
+            lap,
+        }
+    }
+
+    pub fn get(&self, field: RecordField) -> Option<f32> {
+        match field {
+            RecordField::HeartRate => self.heart_rate,
+            RecordField::Power => self.power,
+            RecordField::Cadence => self.cadence,
+            RecordField::Speed => self.speed,
+            RecordField::Distance => self.distance,
+            RecordField::Altitude => self.altitude,
+            RecordField::Grade => self.grade,
+            RecordField::WBalance => self.w_balance,
+            RecordField::Temperature => self.temperature,
+            RecordField::SmO2 => self.smo2,
+            RecordField::THb => self.thb,
+        }
+    }
+
+    fn field_mut(&mut self, field: RecordField) -> &mut Option<f32> {
+        match field {
+            RecordField::HeartRate => &mut self.heart_rate,
+            RecordField::Power => &mut self.power,
+            RecordField::Cadence => &mut self.cadence,
+            RecordField::Speed => &mut self.speed,
+            RecordField::Distance => &mut self.distance,
+            RecordField::Altitude => &mut self.altitude,
+            RecordField::Grade => &mut self.grade,
+            RecordField::WBalance => &mut self.w_balance,
+            RecordField::Temperature => &mut self.temperature,
+            RecordField::SmO2 => &mut self.smo2,
+            RecordField::THb => &mut self.thb,
+        }
+    }
+}
+
+/// Readings of the last `duration` ms in a ring buffer. The sum is kept running and
+/// min and max come from monotonic queues, so updates are amortised O(1).
+pub struct RollingWindow {
+    duration: usize,
+    /// (sequence number, time, value)
+    samples: VecDeque<(u64, usize, f32)>,
+    next_seq: u64,
+    sum: f64,
+    /// Candidates for the minimum, values increasing from the front
+    minima: VecDeque<(u64, f32)>,
+    /// Candidates for the maximum, values decreasing from the front
+    maxima: VecDeque<(u64, f32)>,
+}
+
+impl RollingWindow {
+    pub fn new(duration: usize) -> RollingWindow {
+        RollingWindow {
+            duration,
+            samples: VecDeque::new(),
+            next_seq: 0,
+            sum: 0.0,
+            minima: VecDeque::new(),
+            maxima: VecDeque::new(),
+        }
+    }
+
+    /// ms
+    pub fn duration(&self) -> usize {
+        self.duration
+    }
+
+    pub fn push(&mut self, time: usize, value: f32) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        while self.minima.back().map_or(false, |(_, v)| *v >= value) {
+            self.minima.pop_back();
+        }
+        self.minima.push_back((seq, value));
+        while self.maxima.back().map_or(false, |(_, v)| *v <= value) {
+            self.maxima.pop_back();
+        }
+        self.maxima.push_back((seq, value));
+        self.samples.push_back((seq, time, value));
+        self.sum += value as f64;
+        self.expire(time);
+    }
+
+    /// Drop readings that are `duration` or more older than `now`
+    pub fn expire(&mut self, now: usize) {
+        while let Some((seq, time, value)) = self.samples.front().copied() {
+            if now.saturating_sub(time) < self.duration {
+                break;
+            }
+            self.samples.pop_front();
+            self.sum -= value as f64;
+            if self.minima.front().map_or(false, |(s, _)| *s == seq) {
+                self.minima.pop_front();
+            }
+            if self.maxima.front().map_or(false, |(s, _)| *s == seq) {
+                self.maxima.pop_front();
+            }
+        }
+        if self.samples.is_empty() {
+            // no rounding left behind by the running sum
+            self.sum = 0.0;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.clear();
+        self.minima.clear();
+        self.maxima.clear();
+        self.sum = 0.0;
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    pub fn sum(&self) -> f32 {
+        self.sum as f32
+    }
+
+    pub fn mean(&self) -> Option<f32> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        Some((self.sum / self.samples.len() as f64) as f32)
+    }
+
+    pub fn min(&self) -> Option<f32> {
+        self.minima.front().map(|(_, v)| *v)
+    }
+
+    pub fn max(&self) -> Option<f32> {
+        self.maxima.front().map(|(_, v)| *v)
+    }
+}
+
+/// s, seconds without a reading hold the last one for at most this long,
+/// so meters sending every other second don't count as coasting
+const MAX_HOLD: usize = 5;
+
+/// Readings resampled to one value per second. Within a second the last reading counts,
+/// a gap ends the second and nothing is emitted until readings are back.
+#[derive(Default)]
+pub struct PerSecond {
+    /// Second being filled and the latest reading in it
+    current: Option<(usize, f32)>,
+}
+
+impl PerSecond {
+    /// Reading received at `time` ms, returns the seconds it completed with their values
+    pub fn push(&mut self, time: usize, value: f32) -> Vec<(usize, f32)> {
+        let second = time / 1000;
+        let mut completed = Vec::new();
+        match self.current {
+            // late reading
+            Some((current, _)) if second < current => return completed,
+            Some((current, last)) if second > current => {
+                let held = (second - current).min(MAX_HOLD);
+                completed.extend((current..current + held).map(|k| (k, last)));
+                self.current = None;
+            }
+            _ => {}
+        }
+        if is_gap(value) {
+            completed.extend(self.current.take());
+        } else {
+            self.current = Some((second, value));
+        }
+        completed
+    }
+}
+
+/// Statistics over the whole ride
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct SessionStats {
+    pub count: usize,
+    pub sum: f64,
+    pub min: Option<f32>,
+    pub max: Option<f32>,
+}
+
+impl SessionStats {
+    pub fn push(&mut self, value: f32) {
+        self.count += 1;
+        self.sum += value as f64;
+        self.min = Some(self.min.map_or(value, |min| min.min(value)));
+        self.max = Some(self.max.map_or(value, |max| max.max(value)));
+    }
+
+    pub fn mean(&self) -> Option<f32> {
+        if self.count == 0 {
+            return None;
+        }
+        Some((self.sum / self.count as f64) as f32)
+    }
+}
+
+/// Windows of the average power tiles, ms
+pub const POWER_WINDOWS: [usize; 3] = [3000, 10000, 30000];
+/// Window of the rolling heart rate, ms
+pub const HR_WINDOW: usize = 10000;
+
+/// Rolling windows and session statistics kept up to date as readings are recorded,
+/// so displays don't rescan the history every frame
+pub struct Aggregates {
+    windows: Vec<(RecordField, RollingWindow)>,
+    sessions: Vec<(RecordField, SessionStats)>,
+}
+
+impl Aggregates {
+    /// Nothing aggregated
+    pub fn new() -> Aggregates {
+        Aggregates {
+            windows: Vec::new(),
+            sessions: Vec::new(),
+        }
+    }
+
+    pub fn with_window(mut self, field: RecordField, duration: usize) -> Aggregates {
+        self.windows.push((field, RollingWindow::new(duration)));
+        self
+    }
+
+    pub fn with_session(mut self, field: RecordField) -> Aggregates {
+        self.sessions.push((field, SessionStats::default()));
+        self
+    }
+
+    /// A gap empties the windows of the channel, the session statistics carry on
+    pub fn push(&mut self, field: RecordField, time: usize, value: f32) {
+        for (f, window) in self.windows.iter_mut() {
+            if *f != field {
+                window.expire(time);
+            } else if is_gap(value) {
+                window.clear();
+            } else {
+                window.push(time, value);
+            }
+        }
+        if is_gap(value) {
+            return;
+        }
+        for (_, session) in self.sessions.iter_mut().filter(|(f, _)| *f == field) {
+            session.push(value);
+        }
+    }
+
+    pub fn window(&self, field: RecordField, duration: usize) -> Option<&RollingWindow> {
+        self.windows.iter()
+            .find(|(f, w)| *f == field && w.duration() == duration)
+            .map(|(_, w)| w)
+    }
+
+    pub fn session(&self, field: RecordField) -> Option<&SessionStats> {
+        self.sessions.iter().find(|(f, _)| *f == field).map(|(_, s)| s)
+    }
+}
+
+impl Default for Aggregates {
+    /// Power over `POWER_WINDOWS`, heart rate over `HR_WINDOW` and the session
+    /// statistics of heart rate, power, cadence and speed
+    fn default() -> Self {
+        let mut aggregates = Aggregates::new();
+        for duration in &POWER_WINDOWS {
+            aggregates = aggregates.with_window(RecordField::Power, *duration);
+        }
+        aggregates.with_window(RecordField::HeartRate, HR_WINDOW)
+            .with_session(RecordField::HeartRate)
+            .with_session(RecordField::Power)
+            .with_session(RecordField::Cadence)
+            .with_session(RecordField::Speed)
+    }
+}
+
+/// Everything measured during the ride, rows ordered by time
+pub struct RideRecord {
+    /// ms covered by one row, 0 keeps a row per sample
+    resolution: usize,
+    rows: Vec<RecordRow>,
+    lap: u32,
+    aggregates: Aggregates,
+    training: TrainingLoad,
+    power_curve: PowerCurve,
+    w_balance: WBalance,
+}
+
+impl RideRecord {
+    pub fn new(resolution: usize) -> RideRecord {
+        RideRecord {
+            resolution,
+            rows: Vec::new(),
+            lap: 0,
+            aggregates: Aggregates::default(),
+            training: TrainingLoad::new(),
+            power_curve: PowerCurve::new(),
+            w_balance: WBalance::new(DEFAULT_CP, DEFAULT_W_PRIME, WbalModel::default()),
+        }
+    }
+
+    pub fn with_aggregates(mut self, aggregates: Aggregates) -> RideRecord {
+        self.aggregates = aggregates;
+        self
+    }
+
+    pub fn with_w_balance(mut self, w_balance: WBalance) -> RideRecord {
+        self.w_balance = w_balance;
+        self
+    }
+
+    /// Switch to a new W' balance model, e.g. after CP and W' changed, and recompute
+    /// the balance of the ride so far from the recorded power
+    pub fn set_w_balance(&mut self, mut w_balance: WBalance) {
+        let mut balances = Vec::new();
+        for row in self.rows.iter_mut() {
+            row.w_balance = None;
+            if let Some(power) = row.get(RecordField::Power) {
+                balances.extend(w_balance.push(row.time, power));
+            }
+        }
+        for (second, balance) in balances {
+            self.derived_row(second * 1000).w_balance = Some(balance);
+        }
+        self.w_balance = w_balance;
+    }
+
+    pub fn aggregates(&self) -> &Aggregates {
+        &self.aggregates
+    }
+
+    pub fn training(&self) -> &TrainingLoad {
+        &self.training
+    }
+
+    pub fn power_curve(&self) -> &PowerCurve {
+        &self.power_curve
+    }
+
+    pub fn w_balance(&self) -> &WBalance {
+        &self.w_balance
+    }
+
+    /// Training metrics against the rider's FTP, W, and body weight, kg
+    pub fn training_metrics(&self, ftp: f32, weight: f32) -> TrainingMetrics {
+        let power = self.aggregates.window(RecordField::Power, POWER_WINDOWS[0]).and_then(|w| w.mean());
+        self.training.metrics(ftp, weight, power)
+    }
+
+    pub fn rows(&self) -> &[RecordRow] {
+        &self.rows
+    }
+
+    /// Rows with `start_time <= time <= end_time`
+    pub fn range(&self, start_time: usize, end_time: usize) -> &[RecordRow] {
+        let start = self.rows.partition_point(|row| row.time < start_time);
+        let end = self.rows.partition_point(|row| row.time <= end_time);
+        &self.rows[start..end.max(start)]
+    }
+
+    /// Reading received now
+    pub fn push(&mut self, field: RecordField, val: f32) {
+        self.record(Date::now() as usize, field, val);
+    }
+
+    /// Mark that the sensor went stale, nothing is interpolated across
+    pub fn push_gap(&mut self, field: RecordField) {
+        self.record(Date::now() as usize, field, GAP);
+    }
+
+    /// Reading taken at `time` ms, the latest one wins within a row
+    pub fn record(&mut self, time: usize, field: RecordField, val: f32) {
+        *self.row_at(time).field_mut(field) = Some(val);
+        self.aggregates.push(field, time, val);
+        if field == RecordField::Power {
+            self.training.push(time, val);
+            self.power_curve.push(time, val);
+            for (second, balance) in self.w_balance.push(time, val) {
+                self.derived_row(second * 1000).w_balance = Some(balance);
+            }
+        }
+    }
+
+    pub fn record_position(&mut self, time: usize, position: Position) {
+        self.row_at(time).position = Some(position);
+    }
+
+    /// Lap of rows added from now on
+    pub fn set_lap(&mut self, lap: u32) {
+        self.lap = lap;
+    }
+
+    pub fn lap(&self) -> u32 {
+        self.lap
+    }
+
+    /// Latest reading of `field`, gaps are skipped
+    pub fn last(&self, field: RecordField) -> Option<f32> {
+        self.rows.iter().rev().filter_map(|row| row.get(field)).find(|v| !is_gap(*v))
+    }
+
+    /// Average of the readings received since `start_time`, `None` if there are none
+    pub fn mean_since(&self, field: RecordField, start_time: usize) -> Option<f32> {
+        let recent: Vec<f32> = self.rows.iter().rev()
+            .take_while(|row| row.time >= start_time)
+            .filter_map(|row| row.get(field))
+            .filter(|v| !is_gap(*v))
+            .collect();
+        if recent.is_empty() {
+            return None;
+        }
+        Some(recent.iter().sum::<f32>() / recent.len() as f32)
+    }
+
+    /// Row covering `time`, added if there is none. Rows stay ordered, a reading
+    /// older than the last row goes into it.
+    fn row_at(&mut self, time: usize) -> &mut RecordRow {
+        let slot = if self.resolution > 0 { time - time % self.resolution } else { time };
+        let append = match self.rows.last() {
+            Some(last) if slot < last.time => false,
+            Some(last) => self.resolution == 0 || slot > last.time,
+            None => true,
+        };
+        if append {
+            self.rows.push(RecordRow::new(slot, self.lap));
+        }
+        self.rows.last_mut().unwrap()
+    }
+
+    /// Row covering `time` for a value derived from earlier readings, inserted in order if there is none
+    fn derived_row(&mut self, time: usize) -> &mut RecordRow {
+        let slot = if self.resolution > 0 { time - time % self.resolution } else { time };
+        let pos = self.rows.partition_point(|row| row.time < slot);
+        if self.rows.get(pos).map_or(true, |row| row.time != slot) {
+            let lap = self.rows.get(pos.saturating_sub(1)).map_or(self.lap, |row| row.lap);
+            self.rows.insert(pos, RecordRow::new(slot, lap));
+        }
+        &mut self.rows[pos]
+    }
 }
 
-impl TimeSeries<f32> for HrmData {
+impl Default for RideRecord {
+    fn default() -> Self {
+        RideRecord::new(RECORD_RESOLUTION)
+    }
+}
+
+/// One channel of a shared ride record as a time series
+pub struct RecordSeries {
+    record: Rc<RefCell<RideRecord>>,
+    field: RecordField,
+}
+
+impl RecordSeries {
+    pub fn new(record: Rc<RefCell<RideRecord>>, field: RecordField) -> RecordSeries {
+        RecordSeries { record, field }
+    }
+}
+
+impl TimeSeries<f32> for RecordSeries {
     fn fetch_data(self: &Rc<Self>, start_time: usize, end_time: usize, step: f32) -> Box<dyn Iterator<Item=f32>> {
-        let mut pos = 0;
-        let mut last_t = self.data.get(pos).unwrap().0 as f32;
-        while last_t < start_time as f32 && pos < self.data.len() {
-            pos += 1;
-            last_t = self.data.get(pos).unwrap().0 as f32;
-        }
-        let iter = HrmDataIter {
-            data: self.clone(),
-            start_time,
+        let pos = self.record.borrow().rows.partition_point(|row| row.time < start_time);
+        Box::new(RecordSeriesIter {
+            series: self.clone(),
             end_time,
             step,
-            last_t,
+            next_time: start_time as f32,
             pos,
-        };
-        //self.iters.push(Box::new(iter));
-        Box::new(iter)
+        })
     }
-}
 
-impl HrmData {
-    pub fn add_hr(&mut self, val: f32) {
-        self.data.push((Date::now() as usize, val));
+    fn samples(&self, start_time: usize, end_time: usize) -> Vec<(usize, f32)> {
+        let field = self.field;
+        self.record.borrow().range(start_time, end_time).iter()
+            .filter_map(|row| row.get(field).map(|value| (row.time, value)))
+            .collect()
     }
 }
 
-impl Iterator for HrmDataIter {
+/// Values of a channel from a start time on, at most one per `step` ms
+struct RecordSeriesIter {
+    series: Rc<RecordSeries>,
+    end_time: usize,
+    step: f32,
+    /// Readings before this are skipped
+    next_time: f32,
+    pos: usize,
+}
+
+impl Iterator for RecordSeriesIter {
     type Item = f32;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.data.data.len() >= self.pos {
+        let record = self.series.record.borrow();
+        while let Some(row) = record.rows.get(self.pos) {
+            if row.time > self.end_time {
+                return None;
+            }
+            self.pos += 1;
+            let value = match row.get(self.series.field) {
+                Some(value) if row.time as f32 >= self.next_time => value,
+                _ => continue,
+            };
+            self.next_time = row.time as f32 + self.step;
+            return Some(value);
+        }
+        None
+    }
+}
+
+/// Beat to beat intervals, ms, stamped with the time of the closing beat
+pub struct RrData {
+    pub data: Vec<(usize, f32)>,
+    window: HrvWindow,
+}
+
+impl RrData {
+    pub fn new(window: f32) -> RrData {
+        RrData {
+            data: Vec::new(),
+            window: HrvWindow::new(window),
+        }
+    }
+
+    /// Add intervals of one notification received at `time`, oldest first; the last one ends then
+    pub fn add_rr(&mut self, time: usize, intervals: &[f32]) {
+        let mut offset: f32 = intervals.iter().sum();
+        for rr in intervals {
+            offset -= rr;
+            let t = time.saturating_sub(offset as usize);
+            self.data.push((t, *rr));
+            self.window.push(t, *rr);
+        }
+    }
+
+    pub fn hrv(&self) -> Option<Hrv> {
+        self.window.hrv()
+    }
+}
+
+impl TimeSeries<f32> for RrData {
+    fn fetch_data(self: &Rc<Self>, start_time: usize, end_time: usize, _step: f32) -> Box<dyn Iterator<Item=f32>> {
+        let values: Vec<f32> = self.data.iter()
+            .filter(|(t, _)| *t >= start_time && *t <= end_time)
+            .map(|(_, rr)| *rr)
+            .collect();
+        Box::new(values.into_iter())
+    }
+
+    fn samples(&self, start_time: usize, end_time: usize) -> Vec<(usize, f32)> {
+        self.data.iter().filter(|(t, _)| *t >= start_time && *t <= end_time).copied().collect()
+    }
+}
+
+/// Heart rate variability, ms
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Hrv {
+    pub rmssd: f32,
+    pub sdnn: f32,
+}
+
+/// Running sums over the intervals of the last `duration` ms, so that adding a beat
+/// and dropping the expired ones doesn't rescan the window
+pub struct HrvWindow {
+    duration: f32,
+    intervals: VecDeque<(usize, f32)>,
+    sum: f64,
+    sum_sq: f64,
+    sum_diff_sq: f64,
+}
+
+impl HrvWindow {
+    pub fn new(duration: f32) -> HrvWindow {
+        HrvWindow {
+            duration,
+            intervals: VecDeque::new(),
+            sum: 0.0,
+            sum_sq: 0.0,
+            sum_diff_sq: 0.0,
+        }
+    }
+
+    pub fn push(&mut self, t: usize, rr: f32) {
+        if let Some((_, prev)) = self.intervals.back() {
+            self.sum_diff_sq += ((rr - prev) as f64).powi(2);
+        }
+        self.intervals.push_back((t, rr));
+        self.sum += rr as f64;
+        self.sum_sq += (rr as f64).powi(2);
+
+        while let Some((start, first)) = self.intervals.front().copied() {
+            if t.saturating_sub(start) as f32 <= self.duration {
+                break;
+            }
+            self.intervals.pop_front();
+            self.sum -= first as f64;
+            self.sum_sq -= (first as f64).powi(2);
+            if let Some((_, next)) = self.intervals.front() {
+                self.sum_diff_sq -= ((next - first) as f64).powi(2);
+            }
+        }
+    }
+
+    /// `None` until the window holds at least two intervals
+    pub fn hrv(&self) -> Option<Hrv> {
+        let n = self.intervals.len();
+        if n < 2 {
             return None;
         }
-        while self.data.data.len() < self.pos && (self.data.data.get(self.pos).unwrap().0 as f32) < self.last_t + self.step {
-            self.pos += 1;
+        let mean = self.sum / n as f64;
+        let variance = (self.sum_sq - n as f64 * mean * mean) / (n - 1) as f64;
+        Some(Hrv {
+            rmssd: (self.sum_diff_sq.max(0.0) / (n - 1) as f64).sqrt() as f32,
+            sdnn: variance.max(0.0).sqrt() as f32,
+        })
+    }
+}
+
+/// Values stamped with the time they were received
+#[derive(Default)]
+pub struct Samples {
+    pub data: Vec<(usize, f32)>,
+}
+
+impl Samples {
+    pub fn push(&mut self, time: usize, val: f32) {
+        self.data.push((time, val));
+    }
+
+    /// Latest value, gaps are skipped
+    pub fn last(&self) -> Option<f32> {
+        self.data.iter().rev().map(|(_, v)| *v).find(|v| !is_gap(*v))
+    }
+}
+
+/// Instantaneous pedal force at a crank angle
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ForceVector {
+    /// Degrees from top dead centre
+    pub angle: f32,
+    /// Newtons
+    pub force: f32,
+}
+
+/// Accumulated torque wraps at 65536 / 32 Nm
+const TORQUE_ROLLOVER: f32 = 2048.0;
+
+/// Force array of a pedal stroke waiting for the crank angle of the next one
+struct PendingStroke {
+    /// Degrees
+    first_angle: f32,
+    revolutions: Option<u16>,
+    forces: Vec<(usize, f32)>,
+}
+
+/// Pedaling dynamics reported by the power meter
+#[derive(Default)]
+pub struct PedalingData {
+    /// Share of power of the reference pedal, percent
+    pub balance: Samples,
+    /// Last balance refers to the left pedal, the side is unknown otherwise
+    pub balance_left: bool,
+    /// Newton metres per crank revolution
+    pub torque: Samples,
+    pub vectors: Vec<(usize, ForceVector)>,
+    /// Accumulated torque and crank revolutions of the previous measurement
+    last_torque: Option<(f32, u16)>,
+    pending: Option<PendingStroke>,
+}
+
+impl PedalingData {
+    /// Take balance and torque from a power measurement received at `time`, true if anything was added
+    pub fn add_power(&mut self, time: usize, m: &PowerMeasurement) -> bool {
+        let mut changed = false;
+        if let Some(balance) = m.pedal_power_balance {
+            // without a reference the pedal is unknown, keep the value as reported
+            self.balance.push(time, balance);
+            self.balance_left = m.balance_reference_left;
+            changed = true;
         }
-        let t: &(usize, f32) = self.data.data.get(self.pos).unwrap();
-        if t.0 > self.end_time {
-            None
-        } else {
-            self.last_t += self.step;
-            Some(t.1)
+        if m.torque_source != TorqueSource::Crank {
+            return changed;
         }
+        if let (Some(torque), Some(crank)) = (m.accumulated_torque, m.crank_revolutions) {
+            if let Some((last_torque, last_revolutions)) = self.last_torque {
+                let revolutions = crank.revolutions.wrapping_sub(last_revolutions);
+                if revolutions == 0 {
+                    // keep the previous reference, the torque counts towards the next revolution
+                    return changed;
+                }
+                let delta = (torque - last_torque).rem_euclid(TORQUE_ROLLOVER);
+                self.torque.push(time, delta / revolutions as f32);
+                changed = true;
+            }
+            self.last_torque = Some((torque, crank.revolutions));
+        }
+        changed
     }
-}
\ No newline at end of file
+
+    /// Take the force array of a power vector received at `time`, true once a stroke is complete.
+    /// A stroke may span several notifications, only the first one carrying the crank angle.
+    /// Its elements are spread evenly up to the angle of the next stroke, counting the crank
+    /// revolutions in between when both report them.
+    pub fn add_vector(&mut self, time: usize, v: &PowerVector) -> bool {
+        let forces = v.forces.iter().map(|force| (time, *force as f32));
+        let first_angle = match v.first_crank_angle {
+            Some(angle) => angle as f32,
+            None => {
+                if let Some(pending) = self.pending.as_mut() {
+                    pending.forces.extend(forces);
+                }
+                return false;
+            }
+        };
+        let revolutions = v.crank_revolutions.map(|crank| crank.revolutions);
+        let next = PendingStroke { first_angle, revolutions, forces: forces.collect() };
+        let stroke = match self.pending.replace(next) {
+            Some(stroke) if !stroke.forces.is_empty() => stroke,
+            _ => return false,
+        };
+        let turns = match (stroke.revolutions, revolutions) {
+            (Some(last), Some(revolutions)) => revolutions.wrapping_sub(last) as f32,
+            _ => 0.0,
+        };
+        let mut span = 360.0 * turns + first_angle - stroke.first_angle;
+        if span <= 0.0 {
+            span = (first_angle - stroke.first_angle).rem_euclid(360.0);
+            if span == 0.0 {
+                span = 360.0;
+            }
+        } else if span > 360.0 {
+            // notifications were missed, the stroke can't be placed
+            return false;
+        }
+        let step = span / stroke.forces.len() as f32;
+        self.vectors.extend(stroke.forces.iter().enumerate().map(|(k, (t, force))| {
+            let angle = (stroke.first_angle + k as f32 * step) % 360.0;
+            (*t, ForceVector { angle, force: *force })
+        }));
+        true
+    }
+
+    /// Force vectors of the last `duration` ms
+    pub fn stroke(&self, duration: usize) -> Vec<ForceVector> {
+        let end = match self.vectors.last() {
+            Some((t, _)) => *t,
+            None => return Vec::new(),
+        };
+        self.vectors.iter().rev()
+            .take_while(|(t, _)| end.saturating_sub(*t) <= duration)
+            .map(|(_, v)| *v)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bluetooth::power::{CrankRevolutionData, MeasurementDirection};
+
+    /// RMSSD and SDNN computed directly from the intervals
+    fn reference(intervals: &[f32]) -> Hrv {
+        let n = intervals.len() as f32;
+        let mean = intervals.iter().sum::<f32>() / n;
+        let variance = intervals.iter().map(|rr| (rr - mean).powi(2)).sum::<f32>() / (n - 1.0);
+        let diff_sq = intervals.windows(2).map(|w| (w[1] - w[0]).powi(2)).sum::<f32>();
+        Hrv { rmssd: (diff_sq / (n - 1.0)).sqrt(), sdnn: variance.sqrt() }
+    }
+
+    fn assert_close(hrv: Hrv, expected: Hrv) {
+        assert!((hrv.rmssd - expected.rmssd).abs() < 1e-3, "{:?} != {:?}", hrv, expected);
+        assert!((hrv.sdnn - expected.sdnn).abs() < 1e-3, "{:?} != {:?}", hrv, expected);
+    }
+
+    #[test]
+    fn computes_rmssd_and_sdnn() {
+        let mut window = HrvWindow::new(HRV_WINDOW);
+        window.push(1000, 800.0);
+        assert_eq!(window.hrv(), None);
+        window.push(1810, 810.0);
+        window.push(2600, 790.0);
+        window.push(3420, 820.0);
+        // successive differences 10, -20, 30 ms, deviations from the 805 ms mean -5, 5, -15, 15 ms
+        assert_close(window.hrv().unwrap(), Hrv { rmssd: (1400.0f32 / 3.0).sqrt(), sdnn: (500.0f32 / 3.0).sqrt() });
+    }
+
+    #[test]
+    fn evicts_intervals_older_than_the_window() {
+        let intervals = [1000.0, 950.0, 900.0, 1100.0, 1050.0, 980.0, 1020.0];
+        let mut window = HrvWindow::new(2000.0);
+        for (k, rr) in intervals.iter().enumerate() {
+            window.push(1000 * (k + 1), *rr);
+        }
+        // beats at 5000, 6000 and 7000 ms are within 2 s of the last one
+        assert_eq!(window.intervals.len(), 3);
+        assert_close(window.hrv().unwrap(), reference(&intervals[4..]));
+
+        let mut fresh = HrvWindow::new(2000.0);
+        for (k, rr) in intervals[4..].iter().enumerate() {
+            fresh.push(5000 + 1000 * k, *rr);
+        }
+        assert!((window.sum - fresh.sum).abs() < 1e-6);
+        assert!((window.sum_sq - fresh.sum_sq).abs() < 1e-3);
+        assert!((window.sum_diff_sq - fresh.sum_diff_sq).abs() < 1e-3);
+
+        // a long gap empties the window down to the new beat
+        window.push(20_000, 1000.0);
+        assert_eq!(window.hrv(), None);
+        assert_eq!(window.sum_diff_sq, 0.0);
+    }
+
+    #[test]
+    fn stamps_intervals_with_their_closing_beat() {
+        let mut rr = RrData::new(HRV_WINDOW);
+        rr.add_rr(10_000, &[800.0, 810.0]);
+        rr.add_rr(10_790, &[790.0]);
+        assert_eq!(rr.data, vec![(9190, 800.0), (10_000, 810.0), (10_790, 790.0)]);
+        assert_close(rr.hrv().unwrap(), reference(&[800.0, 810.0, 790.0]));
+    }
+
+    fn crank_torque(torque: f32, revolutions: u16) -> PowerMeasurement {
+        PowerMeasurement {
+            accumulated_torque: Some(torque),
+            torque_source: TorqueSource::Crank,
+            crank_revolutions: Some(CrankRevolutionData { revolutions, last_event_time: 0 }),
+            ..PowerMeasurement::from_power(250)
+        }
+    }
+
+    fn force_vector(first_crank_angle: Option<u16>, revolutions: Option<u16>, forces: &[i16]) -> PowerVector {
+        PowerVector {
+            crank_revolutions: revolutions.map(|revolutions| CrankRevolutionData { revolutions, last_event_time: 0 }),
+            first_crank_angle,
+            forces: forces.to_vec(),
+            torques: Vec::new(),
+            direction: MeasurementDirection::Tangential,
+        }
+    }
+
+    #[test]
+    fn averages_torque_over_revolutions() {
+        let mut pedaling = PedalingData::default();
+        assert!(!pedaling.add_power(0, &crank_torque(2000.0, 10)));
+        // accumulated torque wraps at 2048 Nm
+        assert!(pedaling.add_power(1000, &crank_torque(32.0, 11)));
+        assert_eq!(pedaling.torque.last(), Some(80.0));
+        // the crank didn't turn, the torque counts towards the next revolution
+        assert!(!pedaling.add_power(2000, &crank_torque(40.0, 11)));
+        assert_eq!(pedaling.torque.data.len(), 1);
+        assert!(pedaling.add_power(3000, &crank_torque(172.0, 13)));
+        assert_eq!(pedaling.torque.data, vec![(1000, 80.0), (3000, 70.0)]);
+        // wheel based torque is not per pedal stroke
+        let wheel = PowerMeasurement { torque_source: TorqueSource::Wheel, ..crank_torque(300.0, 14) };
+        assert!(!pedaling.add_power(4000, &wheel));
+    }
+
+    #[test]
+    fn keeps_balance_of_unknown_pedal_as_reported() {
+        let mut pedaling = PedalingData::default();
+        let left = PowerMeasurement {
+            pedal_power_balance: Some(52.0),
+            balance_reference_left: true,
+            ..PowerMeasurement::from_power(250)
+        };
+        assert!(pedaling.add_power(0, &left));
+        assert_eq!(pedaling.balance.last(), Some(52.0));
+        assert!(pedaling.balance_left);
+        let unknown = PowerMeasurement { balance_reference_left: false, ..left };
+        assert!(pedaling.add_power(1000, &unknown));
+        assert_eq!(pedaling.balance.last(), Some(52.0));
+        assert!(!pedaling.balance_left);
+        assert!(!pedaling.add_power(2000, &PowerMeasurement::from_power(250)));
+    }
+
+    #[test]
+    fn spreads_stroke_up_to_the_next_crank_angle() {
+        let mut pedaling = PedalingData::default();
+        // a stroke split over two notifications, placed once the next one starts a revolution later
+        assert!(!pedaling.add_vector(0, &force_vector(Some(0), Some(5), &[10, 20])));
+        assert!(!pedaling.add_vector(100, &force_vector(None, None, &[30, 40])));
+        assert!(pedaling.stroke(1000).is_empty());
+        assert!(pedaling.add_vector(200, &force_vector(Some(0), Some(6), &[50])));
+        let angles: Vec<(usize, f32, f32)> = pedaling.vectors.iter().map(|(t, v)| (*t, v.angle, v.force)).collect();
+        assert_eq!(angles, vec![(0, 0.0, 10.0), (0, 90.0, 20.0), (100, 180.0, 30.0), (100, 270.0, 40.0)]);
+        // without crank revolutions the stroke ends at the next angle
+        assert!(pedaling.add_vector(300, &force_vector(Some(90), None, &[60])));
+        assert_eq!(pedaling.vectors.last(), Some(&(200, ForceVector { angle: 0.0, force: 50.0 })));
+        assert!(pedaling.add_vector(400, &force_vector(Some(90), None, &[])));
+        assert_eq!(pedaling.vectors.len(), 6);
+        // a revolution went missing
+        assert!(!pedaling.add_vector(500, &force_vector(Some(90), Some(1), &[70])));
+        assert!(!pedaling.add_vector(600, &force_vector(Some(90), Some(3), &[80])));
+        assert_eq!(pedaling.stroke(100).len(), 2);
+    }
+
+    fn series(record: &Rc<RefCell<RideRecord>>, field: RecordField, start: usize, end: usize, step: f32) -> Vec<f32> {
+        Rc::new(RecordSeries::new(record.clone(), field)).fetch_data(start, end, step).collect()
+    }
+
+    #[test]
+    fn merges_readings_into_rows_per_second() {
+        let mut record = RideRecord::default();
+        record.record(10_200, RecordField::HeartRate, 120.0);
+        record.record(10_700, RecordField::Power, 200.0);
+        record.record(10_900, RecordField::Power, 210.0);
+        record.set_lap(1);
+        record.record(11_100, RecordField::Cadence, 90.0);
+        record.record_position(11_500, Position { latitude: 46.5, longitude: 7.9 });
+
+        let rows = record.rows();
+        assert_eq!(rows.len(), 2);
+        assert_eq!((rows[0].time, rows[0].heart_rate, rows[0].power, rows[0].lap), (10_000, Some(120.0), Some(210.0), 0));
+        assert_eq!((rows[1].time, rows[1].heart_rate, rows[1].cadence, rows[1].lap), (11_000, None, Some(90.0), 1));
+        assert_eq!(rows[1].position, Some(Position { latitude: 46.5, longitude: 7.9 }));
+    }
+
+    #[test]
+    fn records_physiology_with_the_ride() {
+        let record = Rc::new(RefCell::new(RideRecord::default()));
+        record.borrow_mut().record(1000, RecordField::Temperature, 37.2);
+        record.borrow_mut().record(1000, RecordField::SmO2, 61.5);
+        record.borrow_mut().record(1500, RecordField::THb, 12.25);
+        record.borrow_mut().record(2000, RecordField::SmO2, GAP);
+        record.borrow_mut().record(3000, RecordField::SmO2, 58.0);
+
+        let rows = record.borrow().rows().to_vec();
+        assert_eq!((rows[0].temperature, rows[0].smo2, rows[0].thb), (Some(37.2), Some(61.5), Some(12.25)));
+        assert_eq!(record.borrow().last(RecordField::Temperature), Some(37.2));
+        assert_eq!(series(&record, RecordField::SmO2, 0, 1000, 0.0), vec![61.5]);
+        assert_eq!(series(&record, RecordField::SmO2, 3000, 3000, 0.0), vec![58.0]);
+    }
+
+    #[test]
+    fn keeps_rows_ordered() {
+        let mut record = RideRecord::new(0);
+        record.record(500, RecordField::Speed, 8.0);
+        record.record(500, RecordField::Speed, 8.5);
+        // late reading goes into the last row
+        record.record(400, RecordField::Grade, 2.0);
+        let rows = record.rows();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[1].grade, Some(2.0));
+        assert_eq!(record.range(500, 500).len(), 2);
+        assert!(record.range(600, 900).is_empty());
+        assert!(record.range(900, 100).is_empty());
+    }
+
+    #[test]
+    fn skips_gaps_and_missing_readings() {
+        let mut record = RideRecord::default();
+        for (k, hr) in [100.0, 110.0, 120.0].iter().enumerate() {
+            record.record(k * 1000, RecordField::HeartRate, *hr);
+            record.record(k * 1000, RecordField::Power, 150.0);
+        }
+        record.record(3000, RecordField::HeartRate, GAP);
+        record.record(4000, RecordField::Power, 160.0);
+
+        assert_eq!(record.last(RecordField::HeartRate), Some(120.0));
+        assert_eq!(record.mean_since(RecordField::HeartRate, 1000), Some(115.0));
+        assert_eq!(record.mean_since(RecordField::HeartRate, 3500), None);
+        assert_eq!(record.last(RecordField::Altitude), None);
+    }
+
+    #[test]
+    fn iterates_requested_range() {
+        let record = Rc::new(RefCell::new(RideRecord::default()));
+        for k in 0..10 {
+            record.borrow_mut().record(k * 1000, RecordField::Power, k as f32 * 10.0);
+        }
+        assert_eq!(series(&record, RecordField::Power, 2500, 5000, 0.0), vec![30.0, 40.0, 50.0]);
+        assert_eq!(series(&record, RecordField::Power, 0, 9000, 3000.0), vec![0.0, 30.0, 60.0, 90.0]);
+        assert!(series(&record, RecordField::Power, 20_000, 30_000, 0.0).is_empty());
+        assert!(series(&record, RecordField::HeartRate, 0, 9000, 0.0).is_empty());
+        assert!(series(&Rc::new(RefCell::new(RideRecord::default())), RecordField::Power, 0, 9000, 0.0).is_empty());
+    }
+
+    #[test]
+    fn rolling_window_follows_sum_min_and_max() {
+        let mut window = RollingWindow::new(3000);
+        for (t, v) in [(0, 100.0), (1000, 300.0), (2000, 200.0)].iter() {
+            window.push(*t, *v);
+        }
+        assert_eq!((window.sum(), window.mean(), window.min(), window.max()), (600.0, Some(200.0), Some(100.0), Some(300.0)));
+
+        window.push(3000, 250.0);
+        assert_eq!((window.len(), window.min(), window.max()), (3, Some(200.0), Some(300.0)));
+        window.push(4000, 150.0);
+        assert_eq!((window.mean(), window.min(), window.max()), (Some(200.0), Some(150.0), Some(250.0)));
+
+        window.expire(10_000);
+        assert!(window.is_empty());
+        assert_eq!((window.sum(), window.mean(), window.max()), (0.0, None, None));
+    }
+
+    #[test]
+    fn aggregates_recorded_readings() {
+        let mut record = RideRecord::default();
+        for k in 0..40 {
+            record.record(k * 1000, RecordField::Power, if k < 30 { 200.0 } else { 300.0 });
+        }
+        let aggregates = record.aggregates();
+        assert_eq!(aggregates.window(RecordField::Power, 3000).unwrap().mean(), Some(300.0));
+        assert_eq!(aggregates.window(RecordField::Power, 30_000).unwrap().mean(), Some(233.33333));
+        let session = aggregates.session(RecordField::Power).unwrap();
+        assert_eq!((session.count, session.mean(), session.max), (40, Some(225.0), Some(300.0)));
+
+        // readings of other channels move the windows along
+        record.record(41_500, RecordField::Distance, 1000.0);
+        assert_eq!(record.aggregates().window(RecordField::Power, 3000).unwrap().len(), 1);
+        record.record(43_000, RecordField::Power, GAP);
+        assert!(record.aggregates().window(RecordField::Power, 10_000).unwrap().is_empty());
+        assert_eq!(record.aggregates().session(RecordField::Power).unwrap().count, 40);
+        assert!(record.aggregates().window(RecordField::Power, 5000).is_none());
+    }
+
+    /// Three hours at one reading per second, steady 200 W with a sprint and a dropout
+    fn long_ride() -> Rc<RecordSeries> {
+        let record = Rc::new(RefCell::new(RideRecord::default()));
+        for k in 0..3 * 3600 {
+            let power = match k {
+                5000 => 1100.0,
+                7000..=7009 => GAP,
+                _ => 200.0 + (k % 7) as f32,
+            };
+            record.borrow_mut().record(k * 1000, RecordField::Power, power);
+        }
+        Rc::new(RecordSeries::new(record, RecordField::Power))
+    }
+
+    #[test]
+    fn min_max_gives_a_pair_per_column() {
+        let series = long_ride();
+        let end = 3 * 3600 * 1000 - 1;
+        let points = series.downsample(0, end, 800, Downsampling::MinMax);
+        assert_eq!(points.len(), 2 * 800);
+        assert!(points.windows(2).all(|p| p[0].0 <= p[1].0));
+        assert!(points.contains(&(5_000_000, 1100.0)));
+
+        let zoomed = series.downsample(7_000_000, 7_009_999, 10, Downsampling::MinMax);
+        assert_eq!(zoomed.len(), 20);
+        assert!(zoomed.iter().all(|(_, v)| is_gap(*v)));
+        assert!(series.downsample(0, end, 0, Downsampling::MinMax).is_empty());
+    }
+
+    #[test]
+    fn lttb_keeps_spikes_and_ends() {
+        let series = long_ride();
+        let points = series.downsample(0, 6_999_000, 500, Downsampling::Lttb);
+        assert_eq!(points.len(), 500);
+        assert_eq!((points[0].0, points[499].0), (0, 6_999_000));
+        assert!(points.contains(&(5_000_000, 1100.0)));
+
+        let across_gap = series.downsample(6_000_000, 8_000_000, 100, Downsampling::Lttb);
+        assert_eq!(across_gap.iter().filter(|(_, v)| is_gap(*v)).count(), 1);
+        assert!(across_gap.len() <= 100 + 1 + 2);
+        assert_eq!(lttb(&[(0, 1.0f32), (1, 2.0)], 10), vec![(0, 1.0), (1, 2.0)]);
+    }
+
+    #[test]
+    fn stores_w_balance_as_derived_channel() {
+        let mut record = RideRecord::default().with_w_balance(WBalance::new(250.0, 20_000.0, WbalModel::Differential));
+        for k in 0..10 {
+            record.record(k * 1000 + 500, RecordField::Power, 350.0);
+            record.record(k * 1000 + 900, RecordField::Distance, k as f32 * 10.0);
+        }
+        // the balance of a second is known once the next one starts
+        assert_eq!(record.rows().len(), 10);
+        assert_eq!(record.rows()[0].w_balance, Some(19_900.0));
+        assert_eq!(record.rows()[8].w_balance, Some(19_100.0));
+        assert_eq!(record.rows()[9].w_balance, None);
+        assert_eq!(record.last(RecordField::WBalance), Some(19_100.0));
+        assert_eq!(record.w_balance().percent(), Some(95.5));
+    }
+
+    #[test]
+    fn recomputes_w_balance_with_a_new_model() {
+        let mut record = RideRecord::default().with_w_balance(WBalance::new(250.0, 20_000.0, WbalModel::Differential));
+        for k in 0..10 {
+            record.record(k * 1000 + 500, RecordField::Power, 350.0);
+        }
+        let before: Vec<Option<f32>> = record.rows().iter().map(|row| row.w_balance).collect();
+
+        record.set_w_balance(WBalance::new(250.0, 20_000.0, WbalModel::Differential));
+        assert_eq!(record.rows().iter().map(|row| row.w_balance).collect::<Vec<_>>(), before);
+
+        record.set_w_balance(WBalance::new(300.0, 10_000.0, WbalModel::Differential));
+        assert_eq!(record.rows()[0].w_balance, Some(9_950.0));
+        assert_eq!(record.rows()[8].w_balance, Some(9_550.0));
+        assert_eq!(record.w_balance().w_prime(), 10_000.0);
+        // readings from now on continue from the recomputed balance
+        record.record(10_500, RecordField::Power, 300.0);
+        record.record(11_500, RecordField::Power, 300.0);
+        assert_eq!(record.rows()[9].w_balance, Some(9_500.0));
+    }
+}
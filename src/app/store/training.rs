@@ -0,0 +1,198 @@
+use serde::Serialize;
+
+use crate::timedata::{PerSecond, RollingWindow};
+
+/// s, rolling average the normalized power is computed from
+pub const NP_WINDOW: usize = 30;
+
+/// Training metrics of the ride so far
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize)]
+pub struct TrainingMetrics {
+    /// s of power data
+    pub duration: usize,
+    /// Watts
+    pub average_power: Option<f32>,
+    /// Watts, `None` until the first 30 s average
+    pub normalized_power: Option<f32>,
+    /// Normalized power over FTP
+    pub intensity_factor: Option<f32>,
+    /// 100 for an hour at FTP
+    pub training_stress_score: Option<f32>,
+    /// Normalized over average power
+    pub variability_index: Option<f32>,
+    /// kJ
+    pub work: f32,
+    /// 3 s power over body weight, W/kg
+    pub watts_per_kg: Option<f32>,
+}
+
+/// Power resampled to one value per second with running sums, so that normalized
+/// power and work are kept up to date as readings arrive
+pub struct TrainingLoad {
+    resampled: PerSecond,
+    rolling: RollingWindow,
+    seconds: usize,
+    power_sum: f64,
+    /// Sum of the fourth powers of the 30 s averages
+    rolled_sum: f64,
+    rolled: usize,
+}
+
+impl TrainingLoad {
+    pub fn new() -> TrainingLoad {
+        TrainingLoad {
+            resampled: PerSecond::default(),
+            rolling: RollingWindow::new(NP_WINDOW * 1000),
+            seconds: 0,
+            power_sum: 0.0,
+            rolled_sum: 0.0,
+            rolled: 0,
+        }
+    }
+
+    /// Power reading received at `time` ms
+    pub fn push(&mut self, time: usize, power: f32) {
+        for (second, power) in self.resampled.push(time, power) {
+            self.add_second(second, power);
+        }
+    }
+
+    fn add_second(&mut self, second: usize, power: f32) {
+        self.seconds += 1;
+        self.power_sum += power as f64;
+        self.rolling.push(second * 1000, power);
+        if self.rolling.len() >= NP_WINDOW {
+            let average = self.rolling.sum() as f64 / self.rolling.len() as f64;
+            self.rolled_sum += average.powi(4);
+            self.rolled += 1;
+        }
+    }
+
+    /// s of power data counted so far
+    pub fn duration(&self) -> usize {
+        self.seconds
+    }
+
+    pub fn average_power(&self) -> Option<f32> {
+        if self.seconds == 0 {
+            return None;
+        }
+        Some((self.power_sum / self.seconds as f64) as f32)
+    }
+
+    pub fn normalized_power(&self) -> Option<f32> {
+        if self.rolled == 0 {
+            return None;
+        }
+        Some((self.rolled_sum / self.rolled as f64).powf(0.25) as f32)
+    }
+
+    /// kJ
+    pub fn work(&self) -> f32 {
+        (self.power_sum / 1000.0) as f32
+    }
+
+    /// Metrics against the rider's `ftp` in watts; `power` is the current power
+    /// and `weight` the body weight in kg for power-to-weight
+    pub fn metrics(&self, ftp: f32, weight: f32, power: Option<f32>) -> TrainingMetrics {
+        let normalized_power = self.normalized_power();
+        let average_power = self.average_power();
+        let intensity_factor = normalized_power.filter(|_| ftp > 0.0).map(|np| np / ftp);
+        TrainingMetrics {
+            duration: self.seconds,
+            average_power,
+            normalized_power,
+            intensity_factor,
+            training_stress_score: normalized_power.zip(intensity_factor)
+                .map(|(np, intensity)| self.seconds as f32 * np * intensity / (ftp * 3600.0) * 100.0),
+            variability_index: normalized_power.zip(average_power.filter(|p| *p > 0.0))
+                .map(|(np, average)| np / average),
+            work: self.work(),
+            watts_per_kg: power.filter(|_| weight > 0.0).map(|p| p / weight),
+        }
+    }
+}
+
+impl Default for TrainingLoad {
+    fn default() -> Self {
+        TrainingLoad::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timedata::GAP;
+
+    fn ride(power: &[f32]) -> TrainingLoad {
+        let mut load = TrainingLoad::new();
+        for (k, p) in power.iter().enumerate() {
+            load.push(k * 1000 + 400, *p);
+        }
+        // the last second counts once the next one starts
+        load.push(power.len() * 1000 + 400, GAP);
+        load
+    }
+
+    /// Normalized power straight from the definition
+    fn reference_np(power: &[f32]) -> f32 {
+        let rolled: Vec<f64> = power.windows(NP_WINDOW)
+            .map(|w| w.iter().map(|p| *p as f64).sum::<f64>() / NP_WINDOW as f64)
+            .collect();
+        (rolled.iter().map(|a| a.powi(4)).sum::<f64>() / rolled.len() as f64).powf(0.25) as f32
+    }
+
+    #[test]
+    fn steady_hour_at_ftp() {
+        let metrics = ride(&[250.0; 3600]).metrics(250.0, 70.0, Some(250.0));
+        assert_eq!(metrics.duration, 3600);
+        assert_eq!(metrics.normalized_power, Some(250.0));
+        assert_eq!(metrics.intensity_factor, Some(1.0));
+        assert_eq!(metrics.training_stress_score, Some(100.0));
+        assert_eq!(metrics.variability_index, Some(1.0));
+        assert_eq!(metrics.work, 900.0);
+        assert_eq!(metrics.watts_per_kg, Some(250.0 / 70.0));
+    }
+
+    #[test]
+    fn intervals_match_reference() {
+        // 20 x (1 min at 400 W, 1 min at 100 W) after a 10 min warm up at 150 W
+        let mut power = vec![150.0; 600];
+        for _ in 0..20 {
+            power.extend_from_slice(&[400.0; 60]);
+            power.extend_from_slice(&[100.0; 60]);
+        }
+        let metrics = ride(&power).metrics(280.0, 75.0, None);
+        let np = reference_np(&power);
+        assert!((metrics.normalized_power.unwrap() - np).abs() < 0.01);
+        assert_eq!(metrics.average_power, Some(230.0));
+        assert!((metrics.intensity_factor.unwrap() - np / 280.0).abs() < 0.0001);
+        let tss = 3000.0 * np * (np / 280.0) / (280.0 * 3600.0) * 100.0;
+        assert!((metrics.training_stress_score.unwrap() - tss).abs() < 0.01);
+        assert!(metrics.variability_index.unwrap() > 1.1);
+        assert_eq!(metrics.work, 690.0);
+        assert_eq!(metrics.watts_per_kg, None);
+    }
+
+    #[test]
+    fn resamples_to_seconds() {
+        let mut load = TrainingLoad::new();
+        // one reading every other second holds its value
+        for k in 0..60 {
+            load.push(k * 2000, 200.0);
+        }
+        // several readings in a second, the last one counts
+        load.push(120_100, 500.0);
+        load.push(120_900, 300.0);
+        load.push(121_000, GAP);
+        assert_eq!(load.duration(), 121);
+        assert_eq!(load.work(), 24.3);
+
+        // dropout, nothing counted until readings are back
+        load.push(200_000, 200.0);
+        load.push(201_000, 200.0);
+        assert_eq!(load.duration(), 122);
+        assert!(TrainingLoad::new().metrics(250.0, 70.0, None).normalized_power.is_none());
+        assert_eq!(ride(&[200.0; 29]).normalized_power(), None);
+    }
+}
@@ -0,0 +1,192 @@
+use serde::{Deserialize, Serialize};
+
+use crate::timedata::PerSecond;
+
+/// W
+pub const DEFAULT_CP: f32 = 250.0;
+/// J
+pub const DEFAULT_W_PRIME: f32 = 20_000.0;
+
+/// Skiba's models of the anaerobic work capacity left above critical power
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum WbalModel {
+    /// Skiba 2012: what was expended above CP recovers exponentially with a time constant
+    /// from the average power below CP
+    Integral,
+    /// Skiba 2015: recovery in proportion to what is missing and how far below CP the power is
+    Differential,
+}
+
+impl Default for WbalModel {
+    fn default() -> Self {
+        WbalModel::Differential
+    }
+}
+
+/// Remaining W' in joules, updated once per second of power
+pub struct WBalance {
+    /// W
+    cp: f32,
+    /// J
+    w_prime: f32,
+    model: WbalModel,
+    resampled: PerSecond,
+    /// J, differential form
+    balance: f32,
+    /// J, integral form: energy expended above CP, each second decayed since
+    expended: f64,
+    /// Power below CP summed, for the recovery time constant
+    below_sum: f64,
+    below_seconds: usize,
+}
+
+impl WBalance {
+    /// `cp` in W, `w_prime` in J
+    pub fn new(cp: f32, w_prime: f32, model: WbalModel) -> WBalance {
+        WBalance {
+            cp,
+            w_prime,
+            model,
+            resampled: PerSecond::default(),
+            balance: w_prime,
+            expended: 0.0,
+            below_sum: 0.0,
+            below_seconds: 0,
+        }
+    }
+
+    /// Power reading received at `time` ms, returns the seconds it completed with the balance after each
+    pub fn push(&mut self, time: usize, power: f32) -> Vec<(usize, f32)> {
+        self.resampled.push(time, power).into_iter()
+            .map(|(second, power)| {
+                self.add_second(power);
+                (second, self.balance())
+            })
+            .collect()
+    }
+
+    fn add_second(&mut self, power: f32) {
+        let above = power - self.cp;
+        if above < 0.0 {
+            self.below_sum += power as f64;
+            self.below_seconds += 1;
+        }
+
+        // integral form, the time constant follows the average recovery power so far
+        let tau = self.tau();
+        self.expended = self.expended * (-1.0 / tau).exp() + above.max(0.0) as f64;
+
+        // differential form
+        if above >= 0.0 {
+            self.balance -= above;
+        } else if self.w_prime > 0.0 {
+            self.balance += (self.w_prime - self.balance) * (1.0 - (above / self.w_prime).exp());
+        }
+    }
+
+    /// s, recovery time constant of the integral form
+    pub fn tau(&self) -> f64 {
+        let below = if self.below_seconds > 0 { self.below_sum / self.below_seconds as f64 } else { 0.0 };
+        546.0 * (-0.01 * (self.cp as f64 - below)).exp() + 316.0
+    }
+
+    /// J, may go negative if CP or W' are set too low
+    pub fn balance(&self) -> f32 {
+        match self.model {
+            WbalModel::Integral => self.w_prime - self.expended as f32,
+            WbalModel::Differential => self.balance,
+        }
+    }
+
+    /// Share of W' left, percent
+    pub fn percent(&self) -> Option<f32> {
+        if self.w_prime <= 0.0 {
+            return None;
+        }
+        Some(self.balance() / self.w_prime * 100.0)
+    }
+
+    /// J
+    pub fn w_prime(&self) -> f32 {
+        self.w_prime
+    }
+}
+
+/// Workout constraint: don't let W'bal drop below a share of W'
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+pub struct WbalFloor {
+    /// Percent of W'
+    pub percent: f32,
+}
+
+impl WbalFloor {
+    pub fn is_met(&self, balance: &WBalance) -> bool {
+        balance.percent().map_or(true, |percent| percent >= self.percent)
+    }
+
+    /// W, highest power that can be held for `duration` s without breaking the floor,
+    /// counting on the differential form's linear drain above CP
+    pub fn ceiling(&self, balance: &WBalance, duration: f32) -> f32 {
+        let available = balance.balance() - balance.w_prime() * self.percent / 100.0;
+        balance.cp + available.max(0.0) / duration.max(1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timedata::GAP;
+
+    fn ride(model: WbalModel, power: &[f32]) -> (WBalance, Vec<f32>) {
+        let mut balance = WBalance::new(250.0, 20_000.0, model);
+        let mut series = Vec::new();
+        for (k, p) in power.iter().enumerate() {
+            series.extend(balance.push(k * 1000, *p).into_iter().map(|(_, b)| b));
+        }
+        series.extend(balance.push(power.len() * 1000, GAP).into_iter().map(|(_, b)| b));
+        (balance, series)
+    }
+
+    #[test]
+    fn drains_linearly_above_cp() {
+        for model in &[WbalModel::Integral, WbalModel::Differential] {
+            let (balance, series) = ride(*model, &[350.0; 100]);
+            assert_eq!(series.len(), 100);
+            assert!((series[0] - 19_900.0).abs() < 1.0, "{:?}", model);
+            // the integral form already recovers while working
+            let percent = balance.percent().unwrap();
+            assert!(percent > 49.9 && percent < 57.0, "{:?} {}", model, percent);
+        }
+        // below CP nothing is used
+        let (balance, _) = ride(WbalModel::Differential, &[200.0; 600]);
+        assert_eq!(balance.balance(), 20_000.0);
+    }
+
+    #[test]
+    fn recovers_below_cp() {
+        let mut power = vec![400.0; 60];
+        power.extend_from_slice(&[100.0; 600]);
+        for model in &[WbalModel::Integral, WbalModel::Differential] {
+            let (balance, series) = ride(*model, &power);
+            let lowest = series.iter().cloned().fold(f32::INFINITY, f32::min);
+            assert!(lowest > 10_900.0 && lowest < 12_000.0, "{:?} {}", model, lowest);
+            assert!(balance.balance() > 17_500.0, "{:?} {}", model, balance.balance());
+            assert!(series.windows(2).skip(60).all(|w| w[1] >= w[0]));
+        }
+        // time constant of the 2012 paper for 150 W below CP
+        let (balance, _) = ride(WbalModel::Integral, &power);
+        assert!((balance.tau() - (546.0 * (-1.5f64).exp() + 316.0)).abs() < 0.01);
+    }
+
+    #[test]
+    fn floor_limits_power() {
+        let floor = WbalFloor { percent: 50.0 };
+        let (balance, _) = ride(WbalModel::Differential, &[350.0; 60]);
+        assert!(floor.is_met(&balance));
+        // 14 kJ left, 4 kJ above the floor over 40 s
+        assert!((floor.ceiling(&balance, 40.0) - 350.0).abs() < 0.01);
+        let (balance, _) = ride(WbalModel::Differential, &[350.0; 120]);
+        assert!(!floor.is_met(&balance));
+        assert_eq!(floor.ceiling(&balance, 40.0), 250.0);
+    }
+}
@@ -5,13 +5,15 @@ use crate::{FieldSelector, Render, State, Vec4, WebRenderer};
 use crate::shader::{Shader, ShaderKind};
 use web_sys::WebGl2RenderingContext as GL;
 use crate::element::UINode;
-use crate::timedata::TimeSeries;
+use crate::geom::Transform;
+use crate::timedata::{is_gap, Downsampling, TimeSeries};
 
 pub struct Chart<T : TimeSeries<f32> > {
     sources: Vec< Rc<T>>,
     colors: Vec<Vec4>,
     widths: Vec<f32>,
     from: usize, to: usize,
+    downsampling: Downsampling,
     x : i32,
     y : i32,
     width: i32,
@@ -28,16 +30,48 @@ impl<T : TimeSeries<f32>> Chart<T> {
             widths: Vec::from(witdth),
             from,
             to,
+            downsampling: Downsampling::MinMax,
             x: 0, y: 0, width: 100, height: 100,
             id: 0,
             parent: 0,
         }
     }
 
+    pub fn with_downsampling(mut self, downsampling: Downsampling) -> Chart<T> {
+        self.downsampling = downsampling;
+        self
+    }
+
     pub fn fetch(&mut self, from: usize, to: usize) {
         self.from = from;
         self.to = to;
     }
+
+    /// Runs of vertices between gaps in the unit square, x over the time range
+    /// and y over the range of the values
+    fn vertices(&self, points: &[(usize, f32)]) -> Vec<Vec<f32>> {
+        let (low, high) = points.iter()
+            .map(|(_, v)| *v)
+            .filter(|v| !is_gap(*v))
+            .fold((f32::INFINITY, -f32::INFINITY), |(low, high), v| (low.min(v), high.max(v)));
+        let range = if high > low { high - low } else { 1.0 };
+        let span = self.to.saturating_sub(self.from).max(1) as f32;
+
+        let mut runs = vec![Vec::new()];
+        for (t, v) in points {
+            if is_gap(*v) {
+                if !runs.last().unwrap().is_empty() {
+                    runs.push(Vec::new());
+                }
+                continue;
+            }
+            let x = (t - self.from) as f32 / span;
+            let y = (v - low) / range;
+            runs.last_mut().unwrap().extend_from_slice(&[x, y, 0.0]);
+        }
+        runs.retain(|run| !run.is_empty());
+        runs
+    }
 }
 
 impl<T : TimeSeries<f32>> UINode for Chart<T> {
@@ -81,19 +115,18 @@ impl<T : TimeSeries<f32>> Render for Chart<T> {
         let w = gl.drawing_buffer_width() as f32;
         let h = gl.drawing_buffer_height() as f32;
 
+        let transform_uni = shader.get_uniform_location(gl, "transform");
+        let mut t = Transform::new_translate(2.0 * self.x as f32 / w - 1.0 + 1.0 / w, 2.0 * self.y as f32 / h - 1.0 + 1.0 / h);
+        t.scale(self.width as f32 * 2.0 / w, self.height as f32 * 2.0 / h);
+        gl.uniform_matrix3fv_with_f32_array(transform_uni.as_ref(), false, &t.to_array());
+
         for (idx, data) in self.sources.iter().enumerate() {
             let pos_attrib = gl.get_attrib_location(&shader.program, "position");
+            gl.enable_vertex_attrib_array(pos_attrib as u32);
 
-            let step = (self.to as f32 - self.from as f32)/self.width as f32;
-            let values = data.fetch_data(self.from, self.to, step);
-            let values1 = data.fetch_data(self.from, self.to, step);
-            let maxvalue = values.fold(-f32::INFINITY, |a : f32, b : f32| a.max(b));
-
-            let t: Vec<f32> = values1
-                .enumerate()
-                .flat_map(|(i,val)| [maxvalue / val, self.width as f32 / i as f32])
-                .collect();
-            Self::buffer_f32_data(&gl, &t, pos_attrib as u32, 3);
+            // one bucket per pixel column
+            let points = data.downsample(self.from, self.to, self.width.max(0) as usize, self.downsampling);
+            let runs = self.vertices(&points);
 
             let opacity_uni = shader.get_uniform_location(gl, "opacity");
             let color_uni = shader.get_uniform_location(gl, "color");
@@ -119,7 +152,10 @@ impl<T : TimeSeries<f32>> Render for Chart<T> {
             gl.uniform1i(blur_uni.as_ref(), 0);
             gl.line_width(*self.widths.get(idx).unwrap());
 
-            gl.draw_arrays(GL::LINE_STRIP, 0, t.len() as i32);
+            for run in &runs {
+                Self::buffer_f32_data(&gl, run, pos_attrib as u32, 3);
+                gl.draw_arrays(GL::LINE_STRIP, 0, run.len() as i32 / 3);
+            }
         }
     }
 }
\ No newline at end of file
@@ -76,6 +76,10 @@ impl Element {
     pub fn get_svg(&self) -> &Option<String> {
         &self.svg
     }
+
+    pub fn set_shape(&mut self, shape: Vec<ShapeSegment>) {
+        self.shape = shape;
+    }
 }
 
 impl UINode for Element  {
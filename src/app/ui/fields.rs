@@ -43,6 +43,15 @@ impl Sizing<&str> for SizedStr {
     }
 }
 
+/// Duration in seconds as label text, whole minutes from a minute up
+pub fn duration_text(duration: usize) -> String {
+    if duration < 60 {
+        format!("{} s", duration)
+    } else {
+        format!("{} min", duration / 60)
+    }
+}
+
 impl Display for FieldSelector {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "{:?}", self)
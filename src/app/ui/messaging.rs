@@ -3,11 +3,12 @@ use std::collections::{HashMap, HashSet};
 use std::fmt::{Display, Formatter};
 use web_sys::console;
 use crate::animation::Animator;
-use crate::element::{ElemBuilder, Element, UINode};
+use crate::element::{ElemBuilder, Element, ShapeSegment, UINode};
 use derivative::Derivative;
 use multimap::MultiMap;
 use crate::{FieldSelector, WebEventDispatcher};
 use crate::components::{Component, UserEvent};
+use crate::components::cp_fit_panel::FIT_KEY;
 
 #[allow(dead_code)]
 #[derive(Copy, Clone, Debug, Derivative)]
@@ -316,6 +317,11 @@ impl HandlersBean {
         }
     }
 
+    /// Replace the outline of an element, it is re-buffered on the next frame
+    pub fn set_shape(&self, target_id: usize, shape: Vec<ShapeSegment>) {
+        self.elem_by_id(target_id).borrow_mut().set_shape(shape);
+    }
+
     pub(super) fn elem_by_id(&self, target_id : usize) -> &RefCell<Element> {
         let pos = self.get_elem_pos(target_id);
         self.elements.get(pos).unwrap()
@@ -366,6 +372,13 @@ pub trait EventTarget {
 
 impl EventTarget for WebEventDispatcher {
     fn msg(&mut self, msg: &Msg) -> bool {
+        if let Msg::KeyDown(key) = msg {
+            if let Some(gear) = self.app.shift(*key) {
+                self.ui.emit(UserEvent::GearChanged(gear));
+            } else if *key == FIT_KEY {
+                self.ui.emit(UserEvent::CpFitRequested);
+            }
+        }
         if !self.ui.msg(msg) {
             self.app.store.borrow_mut().msg(msg);
         }
@@ -13,9 +13,9 @@ use messaging::EventTarget;
 
 use crate::animation::Animator;
 use crate::app::ui::drag::Draggable;
-use crate::{FieldSelector, HRM};
+use crate::FieldSelector;
 use crate::components::{Component, UserEvent};
-use crate::components::UserEvent::{HrChanged, ProcessDrag, ProcessDrop};
+use crate::components::UserEvent::{ProcessDrag, ProcessDrop};
 use crate::messaging::HandlerCallback;
 use crate::messaging::HandlersBean;
 use crate::messaging::Msg;
@@ -331,13 +331,6 @@ impl EventTarget for UI {
             Msg::KeyDown(key_code) => {
                 if *key_code == 32 { //Spacebar
                     self.toggle_fullscreen();
-                } else if *key_code == 67 { //'C'
-                    let mut handle = self.handling.clone();
-                    let hrm = HRM::new(move|js| {
-                        let hr = js.as_f64().unwrap();
-                        handle.borrow_mut().push_event(HrChanged(hr as i32))
-                    }, |js| {});
-                    hrm.reconnect_hrm();
                 }
                 false
             }
@@ -8,7 +8,6 @@ use std::rc::Rc;
 
 use console_error_panic_hook;
 use js_sys::Date;
-use rand::Rng;
 use wasm_bindgen::JsCast;
 use wasm_bindgen::prelude::*;
 use web_sys::*;
@@ -16,9 +15,20 @@ use web_sys::WebGl2RenderingContext as GL;
 use app::ui::messaging::EventTarget;
 use crate::animation::{Animation, AnimationSequence, CompositeAnimation};
 use crate::bluetooth::hrm::HRM;
+use crate::bluetooth::simulated::{SimulatedSensor, SimulatorSettings};
+use crate::components::calibration_panel::CalibrationPanel;
+use crate::components::cp_fit_panel::CpFitPanel;
+use crate::components::device_details::DeviceDetailsPanel;
+use crate::components::gear_indicator::GearIndicator;
 use crate::components::hrm_display::HRMDisplay;
+use crate::components::metric_tiles::MetricTiles;
+use crate::components::pedaling_display::PedalingDisplay;
+use crate::components::physiology_display::PhysiologyDisplay;
+use crate::components::record_banner::RecordBanner;
+use crate::components::sensor_panel::SensorPanel;
 use crate::components::slidebox::SlideBox;
-use crate::components::UserEvent::HrChanged;
+use crate::components::training_display::TrainingDisplay;
+use crate::components::wbal_gauge::WBalanceGauge;
 use crate::element::{ElemBuilder, LineStyle, ShapeSegment};
 use crate::fields::{FieldSelector, SizedStr, Vec4};
 
@@ -43,6 +53,7 @@ trait WC {
     fn update(&mut self, dt: f32);
     fn render(&self);
     fn load_textures(&self);
+    fn app(&self) -> Rc<App>;
 }
 
 /// Used to run the application from the web
@@ -70,8 +81,6 @@ struct InnerWebClient {
     fps_label_id: usize,
     //hr_label_id: usize,
     last_render_times: VecDeque<f32>,
-    hr: i32,
-    last_time: f32,
 }
 
 impl InnerWebClient {
@@ -91,6 +100,10 @@ impl InnerWebClient {
             scr_width,
             scr_height,
         ));
+        app.sensors.as_ref().borrow_mut().restore_remembered();
+        // demo data until real sensors are connected
+        let simulator = app.store.as_ref().borrow().state.get_profile().as_ref().borrow().simulator;
+        app.sensors.as_ref().borrow_mut().simulate(SimulatedSensor::from_settings(&simulator));
         //let ui_ref = Rc::new(&dispatcher.ui);
         let gl = create_webgl_context(&canvas).unwrap();
         let renderer = Rc::new(WebRenderer::new(&gl));
@@ -115,6 +128,54 @@ impl InnerWebClient {
             None
         }));
 
+        let sensor_panel = ui.add_component(SensorPanel::new(app.sensors.clone()), 0);
+        ui.set(sensor_panel, FieldSelector::X(15));
+        ui.set(sensor_panel, FieldSelector::Y(h - 168));
+
+        let calibration_panel = ui.add_component(CalibrationPanel::new(app.sensors.clone()), 0);
+        ui.set(calibration_panel, FieldSelector::X(400));
+        ui.set(calibration_panel, FieldSelector::Y(h - 48));
+
+        let pedaling = app.store.as_ref().borrow().state.get_pedaling();
+        let pedaling_display = ui.add_component(PedalingDisplay::new(pedaling), 0);
+        ui.set(pedaling_display, FieldSelector::X(w - 255));
+        ui.set(pedaling_display, FieldSelector::Y(h - 200));
+
+        let gearing = app.store.as_ref().borrow().state.get_gearing();
+        let gear_indicator = ui.add_component(GearIndicator::new(gearing), 0);
+        ui.set(gear_indicator, FieldSelector::X(w - 215));
+        ui.set(gear_indicator, FieldSelector::Y(h - 280));
+
+        let physiology_display = ui.add_component(PhysiologyDisplay::new(), 0);
+        ui.set(physiology_display, FieldSelector::X(400));
+        ui.set(physiology_display, FieldSelector::Y(h - 120));
+
+        let device_details = ui.add_component(DeviceDetailsPanel::new(app.sensors.clone()), 0);
+        ui.set(device_details, FieldSelector::X(400));
+        ui.set(device_details, FieldSelector::Y(h - 264));
+
+        let record = app.store.as_ref().borrow().state.get_record();
+        let metric_tiles = ui.add_component(MetricTiles::new(record.clone()), 0);
+        ui.set(metric_tiles, FieldSelector::X(400));
+        ui.set(metric_tiles, FieldSelector::Y(h - 336));
+
+        let profile = app.store.as_ref().borrow().state.get_profile();
+        let training_display = ui.add_component(TrainingDisplay::new(record.clone(), profile), 0);
+        ui.set(training_display, FieldSelector::X(400));
+        ui.set(training_display, FieldSelector::Y(h - 408));
+
+        let record_banner = ui.add_component(RecordBanner::new(), 0);
+        ui.set(record_banner, FieldSelector::X(400));
+        ui.set(record_banner, FieldSelector::Y(h - 432));
+
+        let wbal_gauge = ui.add_component(WBalanceGauge::new(record), 0);
+        ui.set(wbal_gauge, FieldSelector::X(15));
+        ui.set(wbal_gauge, FieldSelector::Y(h - 200));
+
+        let cp_fit_panel = ui.add_component(CpFitPanel::new(app.store.clone()), 0);
+        ui.set(cp_fit_panel, FieldSelector::X(400));
+        ui.set(cp_fit_panel, FieldSelector::Y(h - 576));
+
         let fps_label_id = Self::create_fps_label(w, h, &mut ui);
 
         let dispatcher = WebEventDispatcher {
@@ -143,8 +204,6 @@ impl InnerWebClient {
             fps_label_id,
             //hr_label_id: heart_rate_id,
             last_render_times: VecDeque::new(),
-            hr: 120,
-            last_time: 0.0,
         }
     }
 
@@ -312,19 +371,9 @@ impl WC for InnerWebClient {
         let avg = self.last_render_times.iter().sum::<f32>() / self.last_render_times.len() as f32;
         ui.set(self.fps_label_id, FieldSelector::LabelText(SizedStr::sizify(&format!("FPS {}", (1000.0 / avg) as i32 )) ) );
 
-        self.last_time += dt;
-        if self.last_time > 1000.0 {
-            self.last_time = 0.0;
-            let mut rng = rand::thread_rng();
-            self.hr += rng.gen_range(-10..10);
-            if self.hr < 80 {
-                self.hr += 2;
-            } else if self.hr > 160 {
-                self.hr -= 2;
-            }
-            ui.emit(HrChanged(self.hr));
-            let mut ref_mut = evt.as_mut().unwrap().app.store.as_ref().borrow_mut();
-            ref_mut.state.get_hr_data().as_ref().borrow_mut().add_hr(self.hr as f32);
+        self.app.sensors.as_ref().borrow_mut().tick(dt);
+        for event in self.app.sensors.as_ref().borrow().take_events() {
+            ui.emit(event);
         }
 
         evt.as_mut().unwrap().msg(&Msg::AdvanceClock(dt));
@@ -408,6 +457,10 @@ impl WC for InnerWebClient {
             true,
         );
     }
+
+    fn app(&self) -> Rc<App> {
+        self.app.clone()
+    }
 }
 
 fn window() -> Window {
@@ -455,4 +508,21 @@ impl WebClient {
         request_animation_frame(g.as_ref().borrow().as_ref().unwrap());
         Ok(())
     }
+
+    /// Wheel circumference of the bike in metres, used for measured and virtual speed
+    pub fn set_wheel_circumference(&self, wheel_circumference: f32) {
+        let app = self.wc.as_ref().borrow().app();
+        let mut drivetrain = app.store.as_ref().borrow().state.get_profile().as_ref().borrow().drivetrain.clone();
+        drivetrain.wheel_circumference = wheel_circumference;
+        app.set_drivetrain(drivetrain);
+    }
+
+    /// Script of the demo rider as JSON `SimulatorSettings`, e.g.
+    /// `{"profile": {"Ramp": {"start": 100, "step": 20, "step_duration": 60000}}}`
+    pub fn set_simulator(&self, settings: &str) -> Result<(), JsValue> {
+        let settings: SimulatorSettings = serde_json::from_str(settings)
+            .map_err(|e| JsValue::from_str(&e.to_string()))?;
+        self.wc.as_ref().borrow().app().set_simulator(settings);
+        Ok(())
+    }
 }